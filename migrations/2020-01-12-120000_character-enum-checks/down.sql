@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "character" DROP CONSTRAINT "character_status_check";
+ALTER TABLE "character" DROP CONSTRAINT "character_gender_check";
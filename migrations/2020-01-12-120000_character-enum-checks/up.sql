@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE "character"
+ADD CONSTRAINT "character_status_check"
+  CHECK ("status" IN ('Alive', 'Dead', 'unknown')),
+ADD CONSTRAINT "character_gender_check"
+  CHECK ("gender" IN ('Female', 'Male', 'Genderless', 'unknown'));
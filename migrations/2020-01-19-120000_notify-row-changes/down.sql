@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER "location_notify_row_change" ON "location";
+DROP TRIGGER "episode_notify_row_change" ON "episode";
+DROP TRIGGER "character_notify_row_change" ON "character";
+DROP FUNCTION notify_row_change();
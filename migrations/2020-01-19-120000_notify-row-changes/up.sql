@@ -0,0 +1,34 @@
+-- Your SQL goes here
+-- Publishes every row change on the "row_changes" channel so that running
+-- servers can forward edits made by other processes to their subscribers.
+CREATE FUNCTION notify_row_change()
+  RETURNS trigger AS $$
+declare
+  changed_row jsonb;
+begin
+  if TG_OP = 'DELETE' then
+    changed_row := to_jsonb(old);
+  else
+    changed_row := to_jsonb(new);
+  end if;
+  perform pg_notify('row_changes', json_build_object(
+    'table', TG_TABLE_NAME,
+    'op', TG_OP,
+    'source', current_setting('application_name'),
+    'row', changed_row - 'search_text'
+  )::text);
+  return null;
+end
+$$ LANGUAGE plpgsql;
+--
+CREATE TRIGGER "character_notify_row_change"
+AFTER INSERT OR UPDATE OR DELETE ON "character"
+FOR EACH ROW EXECUTE PROCEDURE notify_row_change();
+--
+CREATE TRIGGER "episode_notify_row_change"
+AFTER INSERT OR UPDATE OR DELETE ON "episode"
+FOR EACH ROW EXECUTE PROCEDURE notify_row_change();
+--
+CREATE TRIGGER "location_notify_row_change"
+AFTER INSERT OR UPDATE OR DELETE ON "location"
+FOR EACH ROW EXECUTE PROCEDURE notify_row_change();
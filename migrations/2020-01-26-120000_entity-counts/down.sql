@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER "location_count_truncate" ON "location";
+DROP TRIGGER "location_count_update" ON "location";
+DROP TRIGGER "episode_count_truncate" ON "episode";
+DROP TRIGGER "episode_count_update" ON "episode";
+DROP TRIGGER "character_count_truncate" ON "character";
+DROP TRIGGER "character_count_update" ON "character";
+DROP FUNCTION entity_count_update();
+DROP TABLE "entity_count";
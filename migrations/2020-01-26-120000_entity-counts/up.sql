@@ -0,0 +1,55 @@
+-- Your SQL goes here
+-- Row counts of the main tables, kept by triggers in the same transaction
+-- as the change so they survive rollbacks and writes from other clients.
+-- Each table has a single counter row, which every insert or delete locks
+-- until its transaction ends: concurrent writers of the same table wait for
+-- each other. Writes here are rare editor and admin mutations and imports
+-- while counts are read on every page, so one row to read wins. If writers
+-- ever contend, insert per-transaction deltas instead and sum them on read.
+CREATE TABLE "entity_count" (
+  "table_name" VARCHAR PRIMARY KEY,
+  "count" INTEGER NOT NULL
+);
+--
+INSERT INTO "entity_count" ("table_name", "count") VALUES
+  ('character', (SELECT count(*) FROM "character")),
+  ('episode', (SELECT count(*) FROM "episode")),
+  ('location', (SELECT count(*) FROM "location"));
+--
+CREATE FUNCTION entity_count_update()
+  RETURNS trigger AS $$
+begin
+  if TG_OP = 'INSERT' then
+    update "entity_count" set "count" = "count" + 1
+    where "table_name" = TG_TABLE_NAME;
+  elsif TG_OP = 'DELETE' then
+    update "entity_count" set "count" = "count" - 1
+    where "table_name" = TG_TABLE_NAME;
+  else
+    update "entity_count" set "count" = 0
+    where "table_name" = TG_TABLE_NAME;
+  end if;
+  return null;
+end
+$$ LANGUAGE plpgsql;
+--
+CREATE TRIGGER "character_count_update"
+AFTER INSERT OR DELETE ON "character"
+FOR EACH ROW EXECUTE PROCEDURE entity_count_update();
+CREATE TRIGGER "character_count_truncate"
+AFTER TRUNCATE ON "character"
+FOR EACH STATEMENT EXECUTE PROCEDURE entity_count_update();
+--
+CREATE TRIGGER "episode_count_update"
+AFTER INSERT OR DELETE ON "episode"
+FOR EACH ROW EXECUTE PROCEDURE entity_count_update();
+CREATE TRIGGER "episode_count_truncate"
+AFTER TRUNCATE ON "episode"
+FOR EACH STATEMENT EXECUTE PROCEDURE entity_count_update();
+--
+CREATE TRIGGER "location_count_update"
+AFTER INSERT OR DELETE ON "location"
+FOR EACH ROW EXECUTE PROCEDURE entity_count_update();
+CREATE TRIGGER "location_count_truncate"
+AFTER TRUNCATE ON "location"
+FOR EACH STATEMENT EXECUTE PROCEDURE entity_count_update();
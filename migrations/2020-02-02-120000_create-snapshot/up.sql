@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- Named copies of the main tables, one JSON array of rows per table, taken
+-- and restored by `snapshot.rs`.
+CREATE TABLE "snapshot" (
+  "name" VARCHAR PRIMARY KEY,
+  "created_at" TIMESTAMPTZ NOT NULL DEFAULT now(),
+  "data" JSONB NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- The latest migration applied when each snapshot was taken, as its rows
+-- only fit that schema. The snapshots taken so far were all taken at the
+-- migration that created the table.
+ALTER TABLE "snapshot" ADD COLUMN "schema_version" VARCHAR NOT NULL DEFAULT '20200202120000';
+ALTER TABLE "snapshot" ALTER COLUMN "schema_version" DROP DEFAULT;
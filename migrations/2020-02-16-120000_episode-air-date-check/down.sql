@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "episode" DROP CONSTRAINT "episode_air_date_check";
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- The air date filters and ordering run `to_date` on every row, so a
+-- single air date it cannot read would fail them all. The pattern guards
+-- `to_date`, and the round trip rejects the days it would roll over.
+ALTER TABLE "episode"
+ADD CONSTRAINT "episode_air_date_check"
+  CHECK (
+    CASE
+      WHEN "air_date" ~ '^(January|February|March|April|May|June|July|August|September|October|November|December) [1-9][0-9]?, [1-9][0-9]{3}$'
+      THEN to_char(to_date("air_date", 'FMMonth FMDD, YYYY'), 'FMMonth FMDD, YYYY') = "air_date"
+      ELSE false
+    END
+  );
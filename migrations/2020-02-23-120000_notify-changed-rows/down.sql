@@ -0,0 +1,43 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER "location_notify_delete" ON "location";
+DROP TRIGGER "location_notify_update" ON "location";
+DROP TRIGGER "location_notify_insert" ON "location";
+DROP TRIGGER "episode_notify_delete" ON "episode";
+DROP TRIGGER "episode_notify_update" ON "episode";
+DROP TRIGGER "episode_notify_insert" ON "episode";
+DROP TRIGGER "character_notify_delete" ON "character";
+DROP TRIGGER "character_notify_update" ON "character";
+DROP TRIGGER "character_notify_insert" ON "character";
+DROP FUNCTION notify_changed_rows();
+--
+CREATE FUNCTION notify_row_change()
+  RETURNS trigger AS $$
+declare
+  changed_row jsonb;
+begin
+  if TG_OP = 'DELETE' then
+    changed_row := to_jsonb(old);
+  else
+    changed_row := to_jsonb(new);
+  end if;
+  perform pg_notify('row_changes', json_build_object(
+    'table', TG_TABLE_NAME,
+    'op', TG_OP,
+    'source', current_setting('application_name'),
+    'row', changed_row - 'search_text'
+  )::text);
+  return null;
+end
+$$ LANGUAGE plpgsql;
+--
+CREATE TRIGGER "character_notify_row_change"
+AFTER INSERT OR UPDATE OR DELETE ON "character"
+FOR EACH ROW EXECUTE PROCEDURE notify_row_change();
+--
+CREATE TRIGGER "episode_notify_row_change"
+AFTER INSERT OR UPDATE OR DELETE ON "episode"
+FOR EACH ROW EXECUTE PROCEDURE notify_row_change();
+--
+CREATE TRIGGER "location_notify_row_change"
+AFTER INSERT OR UPDATE OR DELETE ON "location"
+FOR EACH ROW EXECUTE PROCEDURE notify_row_change();
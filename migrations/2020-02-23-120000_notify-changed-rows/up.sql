@@ -0,0 +1,74 @@
+-- Your SQL goes here
+-- Replaces the notification of each row by one per statement, so that a
+-- reset or an import notifies a few dozen times rather than once per row.
+-- The changed rows are split over as many notifications as needed to keep
+-- each payload under the 8000 bytes of `pg_notify`.
+DROP TRIGGER "character_notify_row_change" ON "character";
+DROP TRIGGER "episode_notify_row_change" ON "episode";
+DROP TRIGGER "location_notify_row_change" ON "location";
+DROP FUNCTION notify_row_change();
+--
+CREATE FUNCTION notify_changed_rows()
+  RETURNS trigger AS $$
+declare
+  changed_row text;
+  batch text[] := '{}';
+  batch_size integer := 0;
+begin
+  for changed_row in
+    select (to_jsonb(r) - 'search_text')::text from changed_rows r
+  loop
+    if batch_size + octet_length(changed_row) > 7000 and batch_size > 0 then
+      perform pg_notify('row_changes', json_build_object(
+        'table', TG_TABLE_NAME,
+        'op', TG_OP,
+        'rows', ('[' || array_to_string(batch, ',') || ']')::json
+      )::text);
+      batch := '{}';
+      batch_size := 0;
+    end if;
+    batch := batch || changed_row;
+    batch_size := batch_size + octet_length(changed_row) + 1;
+  end loop;
+  if batch_size > 0 then
+    perform pg_notify('row_changes', json_build_object(
+      'table', TG_TABLE_NAME,
+      'op', TG_OP,
+      'rows', ('[' || array_to_string(batch, ',') || ']')::json
+    )::text);
+  end if;
+  return null;
+end
+$$ LANGUAGE plpgsql;
+--
+-- A trigger with transition tables takes a single event, and each names
+-- its rows `changed_rows` for the function.
+CREATE TRIGGER "character_notify_insert"
+AFTER INSERT ON "character" REFERENCING NEW TABLE AS changed_rows
+FOR EACH STATEMENT EXECUTE PROCEDURE notify_changed_rows();
+CREATE TRIGGER "character_notify_update"
+AFTER UPDATE ON "character" REFERENCING NEW TABLE AS changed_rows
+FOR EACH STATEMENT EXECUTE PROCEDURE notify_changed_rows();
+CREATE TRIGGER "character_notify_delete"
+AFTER DELETE ON "character" REFERENCING OLD TABLE AS changed_rows
+FOR EACH STATEMENT EXECUTE PROCEDURE notify_changed_rows();
+--
+CREATE TRIGGER "episode_notify_insert"
+AFTER INSERT ON "episode" REFERENCING NEW TABLE AS changed_rows
+FOR EACH STATEMENT EXECUTE PROCEDURE notify_changed_rows();
+CREATE TRIGGER "episode_notify_update"
+AFTER UPDATE ON "episode" REFERENCING NEW TABLE AS changed_rows
+FOR EACH STATEMENT EXECUTE PROCEDURE notify_changed_rows();
+CREATE TRIGGER "episode_notify_delete"
+AFTER DELETE ON "episode" REFERENCING OLD TABLE AS changed_rows
+FOR EACH STATEMENT EXECUTE PROCEDURE notify_changed_rows();
+--
+CREATE TRIGGER "location_notify_insert"
+AFTER INSERT ON "location" REFERENCING NEW TABLE AS changed_rows
+FOR EACH STATEMENT EXECUTE PROCEDURE notify_changed_rows();
+CREATE TRIGGER "location_notify_update"
+AFTER UPDATE ON "location" REFERENCING NEW TABLE AS changed_rows
+FOR EACH STATEMENT EXECUTE PROCEDURE notify_changed_rows();
+CREATE TRIGGER "location_notify_delete"
+AFTER DELETE ON "location" REFERENCING OLD TABLE AS changed_rows
+FOR EACH STATEMENT EXECUTE PROCEDURE notify_changed_rows();
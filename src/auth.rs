@@ -0,0 +1,259 @@
+//! Bearer token authentication.
+//!
+//! Tokens are HS256 JWTs signed with `auth.jwt_secret`, carrying the caller in
+//! `sub`, one of the `Role`s in `role` and an expiry in `exp`. Requests
+//! without a token are anonymous and may only read.
+
+use crate::config::AuthConfig;
+use jsonwebtoken::{decode, encode, Header, Validation};
+use rocket::{
+  http::Status,
+  request::{self, FromRequest, Request},
+  Outcome, State,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+  Reader,
+  Editor,
+  Admin,
+}
+
+impl Role {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Role::Reader => "reader",
+      Role::Editor => "editor",
+      Role::Admin => "admin",
+    }
+  }
+}
+
+impl FromStr for Role {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "reader" => Ok(Role::Reader),
+      "editor" => Ok(Role::Editor),
+      "admin" => Ok(Role::Admin),
+      _ => Err(format!("Unknown role: {}", value)),
+    }
+  }
+}
+
+impl fmt::Display for Role {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+/// The authenticated caller of a request.
+#[derive(Clone, Debug)]
+pub struct Identity {
+  pub subject: String,
+  pub role: Role,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Claims {
+  sub: String,
+  role: Role,
+  exp: u64,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+  /// `auth.jwt_secret` is not configured, so no token can be verified.
+  NoSecret,
+  MalformedHeader,
+  InvalidToken(jsonwebtoken::errors::Error),
+  /// Rocket does not manage the state the request guards need, a bug of
+  /// the server rather than of the request.
+  Unmanaged,
+}
+
+impl fmt::Display for AuthError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      AuthError::NoSecret => write!(f, "Token authentication is not configured"),
+      AuthError::MalformedHeader => {
+        write!(f, "The Authorization header must be `Bearer <token>`")
+      }
+      AuthError::InvalidToken(err) => write!(f, "Invalid token: {}", err),
+      AuthError::Unmanaged => write!(f, "Internal server error"),
+    }
+  }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Verifies and issues tokens with the configured secret.
+pub struct Authenticator {
+  secret: Option<String>,
+}
+
+impl Authenticator {
+  /// Uses `auth.jwt_secret`, checked by `Config::validate`.
+  pub fn new(config: &AuthConfig) -> Authenticator {
+    Authenticator {
+      secret: config.jwt_secret.clone(),
+    }
+  }
+
+  pub fn is_configured(&self) -> bool {
+    self.secret.is_some()
+  }
+
+  pub fn verify(&self, token: &str) -> Result<Identity, AuthError> {
+    let secret = self.secret.as_ref().ok_or(AuthError::NoSecret)?;
+    let claims = decode::<Claims>(token, secret.as_bytes(), &Validation::default())
+      .map_err(AuthError::InvalidToken)?
+      .claims;
+    Ok(Identity {
+      subject: claims.sub,
+      role: claims.role,
+    })
+  }
+
+  pub fn issue(&self, subject: &str, role: Role, valid_for: Duration) -> Result<String, AuthError> {
+    let secret = self.secret.as_ref().ok_or(AuthError::NoSecret)?;
+    let now = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default();
+    let claims = Claims {
+      sub: subject.to_string(),
+      role,
+      exp: (now + valid_for).as_secs(),
+    };
+    encode(&Header::default(), &claims, secret.as_bytes()).map_err(AuthError::InvalidToken)
+  }
+}
+
+/// Request guard for the optional `Authorization: Bearer <token>` header.
+/// Take it as `Result<Authorization, AuthError>` to report bad tokens in
+/// the response instead of failing the request.
+pub struct Authorization(pub Option<Identity>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Authorization {
+  type Error = AuthError;
+
+  fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, AuthError> {
+    let header = match request.headers().get_one("Authorization") {
+      Some(header) => header,
+      None => return Outcome::Success(Authorization(None)),
+    };
+    let token = match header.trim().splitn(2, ' ').collect::<Vec<_>>().as_slice() {
+      [scheme, token] if scheme.eq_ignore_ascii_case("bearer") => token.trim().to_string(),
+      _ => return Outcome::Failure((Status::Unauthorized, AuthError::MalformedHeader)),
+    };
+    let authenticator = match request.guard::<State<Authenticator>>() {
+      Outcome::Success(authenticator) => authenticator,
+      _ => return Outcome::Failure((Status::Unauthorized, AuthError::NoSecret)),
+    };
+    match authenticator.verify(&token) {
+      Ok(identity) => Outcome::Success(Authorization(Some(identity))),
+      Err(err) => Outcome::Failure((Status::Unauthorized, err)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use jsonwebtoken::errors::ErrorKind;
+
+  const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+  fn authenticator(secret: Option<&str>) -> Authenticator {
+    Authenticator::new(&AuthConfig {
+      jwt_secret: secret.map(str::to_string),
+    })
+  }
+
+  fn now() -> u64 {
+    SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap()
+      .as_secs()
+  }
+
+  #[test]
+  fn issued_tokens_verify_to_their_subject_and_role() {
+    let authenticator = authenticator(Some(SECRET));
+    let token = authenticator
+      .issue("summer", Role::Editor, Duration::from_secs(60))
+      .unwrap();
+    let identity = authenticator.verify(&token).unwrap();
+    assert_eq!(identity.subject, "summer");
+    assert_eq!(identity.role, Role::Editor);
+  }
+
+  #[test]
+  fn expired_tokens_are_rejected() {
+    let claims = Claims {
+      sub: "summer".to_string(),
+      role: Role::Admin,
+      exp: now() - 60,
+    };
+    let token = encode(&Header::default(), &claims, SECRET.as_bytes()).unwrap();
+    match authenticator(Some(SECRET)).verify(&token) {
+      Err(AuthError::InvalidToken(err)) => {
+        assert!(matches!(err.kind(), ErrorKind::ExpiredSignature))
+      }
+      other => panic!("expected an expired token, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn tokens_signed_with_another_secret_are_rejected() {
+    let token = authenticator(Some("another secret of at least 32 bytes"))
+      .issue("summer", Role::Admin, Duration::from_secs(60))
+      .unwrap();
+    match authenticator(Some(SECRET)).verify(&token) {
+      Err(AuthError::InvalidToken(err)) => {
+        assert!(matches!(err.kind(), ErrorKind::InvalidSignature))
+      }
+      other => panic!("expected an invalid signature, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn tokens_with_an_unknown_role_are_rejected() {
+    #[derive(Serialize)]
+    struct OtherClaims {
+      sub: &'static str,
+      role: &'static str,
+      exp: u64,
+    }
+    let claims = OtherClaims {
+      sub: "summer",
+      role: "owner",
+      exp: now() + 60,
+    };
+    let token = encode(&Header::default(), &claims, SECRET.as_bytes()).unwrap();
+    assert!(matches!(
+      authenticator(Some(SECRET)).verify(&token),
+      Err(AuthError::InvalidToken(_))
+    ));
+  }
+
+  #[test]
+  fn nothing_verifies_without_a_secret() {
+    let authenticator = authenticator(None);
+    assert!(!authenticator.is_configured());
+    assert!(matches!(
+      authenticator.verify("a.b.c"),
+      Err(AuthError::NoSecret)
+    ));
+    assert!(matches!(
+      authenticator.issue("summer", Role::Reader, Duration::from_secs(60)),
+      Err(AuthError::NoSecret)
+    ));
+  }
+}
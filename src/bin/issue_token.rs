@@ -0,0 +1,40 @@
+//! Prints a bearer token signed with the `auth.jwt_secret` of the
+//! configuration the server reads.
+//!
+//! Usage: issue_token <subject> <reader|editor|admin> [days valid, default 30]
+
+use rick_morty_back::auth::{Authenticator, Role};
+use rick_morty_back::config::Config;
+use std::env;
+use std::process;
+use std::time::Duration;
+
+const DEFAULT_DAYS: u64 = 30;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("Usage: issue_token <subject> <reader|editor|admin> [days]");
+        process::exit(2);
+    }
+    let role: Role = args[1].parse().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(2);
+    });
+    let days = match args.get(2) {
+        Some(days) => days.parse().expect("days must be a positive integer"),
+        None => DEFAULT_DAYS,
+    };
+    let config = Config::load(None).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+    let authenticator = Authenticator::new(&config.auth);
+    match authenticator.issue(&args[0], role, Duration::from_secs(days * 24 * 60 * 60)) {
+        Ok(token) => println!("{}", token),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,619 @@
+//! Settings of the server, read from a TOML file and then overridden by
+//! `RMB_*` environment variables (`.env` included).
+//!
+//! The file is the `--config` argument, else `RMB_CONFIG`, else
+//! `rick-morty-back.toml` when it exists. Every setting has a default
+//! except the database URL, which may also come from `DATABASE_URL` like
+//! the diesel CLI expects. The `DATABASE_POOL_*` and `JWT_SECRET`
+//! variables of earlier versions still work, below their `RMB_*` names.
+
+use dotenv::dotenv;
+use serde::Deserialize;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const DEFAULT_CONFIG_FILE: &str = "rick-morty-back.toml";
+
+/// Shortest `auth.jwt_secret`, the size of an HS256 key.
+pub const MIN_JWT_SECRET_LEN: usize = 32;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+  pub server: ServerConfig,
+  pub database: DatabaseConfig,
+  pub pagination: PaginationConfig,
+  pub auth: AuthConfig,
+  /// Directory of the seed files, used by `seed`, `reset` and `resetDb`.
+  pub data_dir: PathBuf,
+  /// Directory the `export` command and `exportDb` write to.
+  pub export_dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+  pub host: String,
+  pub port: u16,
+  /// Port of the graphql-ws subscription endpoint.
+  pub ws_port: u16,
+  pub cors_origins: Vec<String>,
+  /// URL the clients reach the server at, for the links in REST
+  /// responses. Defaults to `http://<host>:<port>`.
+  pub public_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+  pub url: String,
+  pub pool_size: u32,
+  /// Time to wait for a free connection.
+  pub pool_timeout_secs: u64,
+  pub pool_idle_timeout_secs: Option<u64>,
+  /// Apply pending migrations when the server starts.
+  pub auto_migrate: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PaginationConfig {
+  /// GraphQL page size when the query does not give one.
+  pub items_per_page: i32,
+  /// Largest page size a GraphQL query may ask for.
+  pub max_page_size: i32,
+  /// Page size of the REST API, fixed like upstream.
+  pub rest_page_size: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+  /// Secret the bearer tokens are signed with. Without it every token,
+  /// and so every mutation, is rejected.
+  pub jwt_secret: Option<String>,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      server: ServerConfig::default(),
+      database: DatabaseConfig::default(),
+      pagination: PaginationConfig::default(),
+      auth: AuthConfig::default(),
+      data_dir: PathBuf::from("raw-data"),
+      export_dir: PathBuf::from("exports"),
+    }
+  }
+}
+
+impl Default for ServerConfig {
+  fn default() -> Self {
+    ServerConfig {
+      host: "127.0.0.1".to_string(),
+      port: 8000,
+      ws_port: 8001,
+      cors_origins: vec![
+        "http://localhost:4200".to_string(),
+        "http://localhost:8000".to_string(),
+      ],
+      public_url: None,
+    }
+  }
+}
+
+impl ServerConfig {
+  /// `public_url` without a trailing slash, else the address the server
+  /// listens on.
+  pub fn public_url(&self) -> String {
+    match &self.public_url {
+      Some(url) => url.trim_end_matches('/').to_string(),
+      None => format!("http://{}:{}", self.host, self.port),
+    }
+  }
+}
+
+impl Default for DatabaseConfig {
+  fn default() -> Self {
+    DatabaseConfig {
+      url: String::new(),
+      pool_size: 10,
+      pool_timeout_secs: 30,
+      pool_idle_timeout_secs: None,
+      auto_migrate: false,
+    }
+  }
+}
+
+impl Default for PaginationConfig {
+  fn default() -> Self {
+    PaginationConfig {
+      items_per_page: 30,
+      max_page_size: 100,
+      rest_page_size: 20,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+  Read {
+    path: PathBuf,
+    err: io::Error,
+  },
+  Parse {
+    path: PathBuf,
+    err: toml::de::Error,
+  },
+  /// An environment variable does not parse as its setting.
+  Env {
+    key: String,
+    value: String,
+    message: String,
+  },
+  /// The settings parsed but make no sense together.
+  Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ConfigError::Read { path, err } => {
+        write!(f, "Cannot read config file {}: {}", path.display(), err)
+      }
+      ConfigError::Parse { path, err } => {
+        write!(f, "Invalid config file {}: {}", path.display(), err)
+      }
+      ConfigError::Env {
+        key,
+        value,
+        message,
+      } => write!(f, "Invalid value {:?} for {}: {}", value, key, message),
+      ConfigError::Invalid(problems) => {
+        write!(f, "Invalid configuration:")?;
+        for problem in problems {
+          write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+      }
+    }
+  }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+  /// Reads the config file and applies the environment overrides. `path`
+  /// is the `--config` argument. Call `validate` once the command line
+  /// overrides are applied too.
+  pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+    dotenv().ok();
+    let path = match path {
+      Some(path) => Some(path.to_path_buf()),
+      None => env::var("RMB_CONFIG")
+        .ok()
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|p| p.exists())),
+    };
+    let mut config = match path {
+      Some(path) => Config::from_file(&path)?,
+      None => Config::default(),
+    };
+    config.apply_env()?;
+    Ok(config)
+  }
+
+  pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+    let contents = fs::read_to_string(path).map_err(|err| ConfigError::Read {
+      path: path.to_path_buf(),
+      err,
+    })?;
+    toml::from_str(&contents).map_err(|err| ConfigError::Parse {
+      path: path.to_path_buf(),
+      err,
+    })
+  }
+
+  /// Overrides settings with the `RMB_<SECTION>_<KEY>` variables.
+  fn apply_env(&mut self) -> Result<(), ConfigError> {
+    let server = &mut self.server;
+    env_override("RMB_SERVER_HOST", &mut server.host)?;
+    env_override("RMB_SERVER_PORT", &mut server.port)?;
+    env_override("RMB_SERVER_WS_PORT", &mut server.ws_port)?;
+    if let Ok(origins) = env::var("RMB_SERVER_CORS_ORIGINS") {
+      server.cors_origins = origins
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .map(str::to_string)
+        .collect();
+    }
+    optional_env_override("RMB_SERVER_PUBLIC_URL", &mut server.public_url)?;
+
+    let database = &mut self.database;
+    if database.url.is_empty() {
+      env_override("DATABASE_URL", &mut database.url)?;
+    }
+    env_override("RMB_DATABASE_URL", &mut database.url)?;
+    // The pool variables read before there was a config file, still
+    // honoured but overridden by their `RMB_` names.
+    env_override("DATABASE_POOL_SIZE", &mut database.pool_size)?;
+    env_override("RMB_DATABASE_POOL_SIZE", &mut database.pool_size)?;
+    env_override(
+      "DATABASE_POOL_TIMEOUT_SECS",
+      &mut database.pool_timeout_secs,
+    )?;
+    env_override(
+      "RMB_DATABASE_POOL_TIMEOUT_SECS",
+      &mut database.pool_timeout_secs,
+    )?;
+    optional_env_override(
+      "DATABASE_POOL_IDLE_TIMEOUT_SECS",
+      &mut database.pool_idle_timeout_secs,
+    )?;
+    optional_env_override(
+      "RMB_DATABASE_POOL_IDLE_TIMEOUT_SECS",
+      &mut database.pool_idle_timeout_secs,
+    )?;
+    env_override("RMB_DATABASE_AUTO_MIGRATE", &mut database.auto_migrate)?;
+
+    let pagination = &mut self.pagination;
+    env_override(
+      "RMB_PAGINATION_ITEMS_PER_PAGE",
+      &mut pagination.items_per_page,
+    )?;
+    env_override(
+      "RMB_PAGINATION_MAX_PAGE_SIZE",
+      &mut pagination.max_page_size,
+    )?;
+    env_override(
+      "RMB_PAGINATION_REST_PAGE_SIZE",
+      &mut pagination.rest_page_size,
+    )?;
+
+    let auth = &mut self.auth;
+    // Left empty in the `.env` of earlier versions to turn tokens off.
+    if let Ok(secret) = env::var("JWT_SECRET") {
+      if !secret.is_empty() {
+        auth.jwt_secret = Some(secret);
+      }
+    }
+    optional_env_override("RMB_AUTH_JWT_SECRET", &mut auth.jwt_secret)?;
+
+    env_override("RMB_DATA_DIR", &mut self.data_dir)?;
+    env_override("RMB_EXPORT_DIR", &mut self.export_dir)
+  }
+
+  /// Reports every problem at once, naming the setting to change.
+  pub fn validate(&self) -> Result<(), ConfigError> {
+    let mut problems = vec![];
+    if self.database.url.is_empty() {
+      problems.push(
+        "database.url is not set: add it to the config file or set RMB_DATABASE_URL or DATABASE_URL"
+          .to_string(),
+      );
+    }
+    if self.database.pool_size == 0 {
+      problems.push("database.pool_size must be at least 1".to_string());
+    }
+    if self.server.port == self.server.ws_port {
+      problems.push(format!(
+        "server.port and server.ws_port are both {}, give the subscription endpoint its own port",
+        self.server.port
+      ));
+    }
+    for origin in &self.server.cors_origins {
+      if !origin.starts_with("http://") && !origin.starts_with("https://") {
+        problems.push(format!(
+          "server.cors_origins: {:?} must be a full origin such as http://localhost:4200",
+          origin
+        ));
+      }
+    }
+    if let Some(url) = &self.server.public_url {
+      if !url.starts_with("http://") && !url.starts_with("https://") {
+        problems.push(format!(
+          "server.public_url: {:?} must be a full URL such as https://api.example.com",
+          url
+        ));
+      }
+    }
+    let pagination = &self.pagination;
+    if pagination.max_page_size < 1 {
+      problems.push("pagination.max_page_size must be at least 1".to_string());
+    }
+    if pagination.items_per_page < 1 || pagination.items_per_page > pagination.max_page_size {
+      problems.push(format!(
+        "pagination.items_per_page must be between 1 and pagination.max_page_size ({}), got {}",
+        pagination.max_page_size, pagination.items_per_page
+      ));
+    }
+    if pagination.rest_page_size < 1 {
+      problems.push("pagination.rest_page_size must be at least 1".to_string());
+    }
+    if let Some(secret) = &self.auth.jwt_secret {
+      if secret.len() < MIN_JWT_SECRET_LEN {
+        problems.push(format!(
+          "auth.jwt_secret must be at least {} bytes long, got {}",
+          MIN_JWT_SECRET_LEN,
+          secret.len()
+        ));
+      }
+    }
+    if problems.is_empty() {
+      Ok(())
+    } else {
+      Err(ConfigError::Invalid(problems))
+    }
+  }
+}
+
+fn parse_env<T: FromStr>(key: &str, value: String) -> Result<T, ConfigError>
+where
+  T::Err: fmt::Display,
+{
+  value.parse().map_err(|err: T::Err| ConfigError::Env {
+    key: key.to_string(),
+    message: err.to_string(),
+    value,
+  })
+}
+
+fn env_override<T: FromStr>(key: &str, setting: &mut T) -> Result<(), ConfigError>
+where
+  T::Err: fmt::Display,
+{
+  if let Ok(value) = env::var(key) {
+    *setting = parse_env(key, value)?;
+  }
+  Ok(())
+}
+
+fn optional_env_override<T: FromStr>(key: &str, setting: &mut Option<T>) -> Result<(), ConfigError>
+where
+  T::Err: fmt::Display,
+{
+  if let Ok(value) = env::var(key) {
+    *setting = Some(parse_env(key, value)?);
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::process;
+  use std::sync::Mutex;
+
+  /// Held while a test changes the environment, which the tests share.
+  static ENV: Mutex<()> = Mutex::new(());
+
+  /// Every variable `apply_env` reads.
+  const KEYS: &[&str] = &[
+    "RMB_SERVER_HOST",
+    "RMB_SERVER_PORT",
+    "RMB_SERVER_WS_PORT",
+    "RMB_SERVER_CORS_ORIGINS",
+    "RMB_SERVER_PUBLIC_URL",
+    "DATABASE_URL",
+    "RMB_DATABASE_URL",
+    "DATABASE_POOL_SIZE",
+    "RMB_DATABASE_POOL_SIZE",
+    "DATABASE_POOL_TIMEOUT_SECS",
+    "RMB_DATABASE_POOL_TIMEOUT_SECS",
+    "DATABASE_POOL_IDLE_TIMEOUT_SECS",
+    "RMB_DATABASE_POOL_IDLE_TIMEOUT_SECS",
+    "RMB_DATABASE_AUTO_MIGRATE",
+    "RMB_PAGINATION_ITEMS_PER_PAGE",
+    "RMB_PAGINATION_MAX_PAGE_SIZE",
+    "RMB_PAGINATION_REST_PAGE_SIZE",
+    "JWT_SECRET",
+    "RMB_AUTH_JWT_SECRET",
+    "RMB_DATA_DIR",
+    "RMB_EXPORT_DIR",
+  ];
+
+  /// Applies the environment made of `vars` alone to `config`.
+  fn apply(mut config: Config, vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+    let _lock = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    for key in KEYS {
+      env::remove_var(key);
+    }
+    for (key, value) in vars {
+      env::set_var(key, value);
+    }
+    let result = config.apply_env();
+    for (key, _) in vars {
+      env::remove_var(key);
+    }
+    result.map(|()| config)
+  }
+
+  fn from_toml(contents: &str) -> Result<Config, ConfigError> {
+    let path = env::temp_dir().join(format!(
+      "rick-morty-back-{}-{:?}.toml",
+      process::id(),
+      std::thread::current().id()
+    ));
+    fs::write(&path, contents).unwrap();
+    let config = Config::from_file(&path);
+    fs::remove_file(&path).unwrap();
+    config
+  }
+
+  fn valid() -> Config {
+    let mut config = Config::default();
+    config.database.url = "postgres://localhost/rick_and_morty".to_string();
+    config
+  }
+
+  fn problems(config: &Config) -> Vec<String> {
+    match config.validate() {
+      Ok(()) => vec![],
+      Err(ConfigError::Invalid(problems)) => problems,
+      Err(err) => panic!("unexpected error: {}", err),
+    }
+  }
+
+  #[test]
+  fn environment_overrides_the_file() {
+    let file = from_toml(
+      r#"
+      data_dir = "seeds"
+
+      [server]
+      host = "0.0.0.0"
+      port = 9000
+
+      [database]
+      url = "postgres://file/db"
+      "#,
+    )
+    .unwrap();
+    let config = apply(
+      file,
+      &[
+        ("RMB_SERVER_PORT", "9100"),
+        (
+          "RMB_SERVER_CORS_ORIGINS",
+          "https://a.example, ,http://b.example",
+        ),
+        ("RMB_DATABASE_POOL_IDLE_TIMEOUT_SECS", "600"),
+        ("DATABASE_URL", "postgres://diesel/db"),
+      ],
+    )
+    .unwrap();
+    assert_eq!(config.server.host, "0.0.0.0");
+    assert_eq!(config.server.port, 9100);
+    assert_eq!(config.server.ws_port, 8001);
+    assert_eq!(
+      config.server.cors_origins,
+      vec!["https://a.example", "http://b.example"]
+    );
+    assert_eq!(config.database.pool_idle_timeout_secs, Some(600));
+    // `DATABASE_URL` only fills a missing URL.
+    assert_eq!(config.database.url, "postgres://file/db");
+    assert_eq!(config.data_dir, PathBuf::from("seeds"));
+  }
+
+  #[test]
+  fn database_url_falls_back_to_the_diesel_variable() {
+    let config = apply(
+      Config::default(),
+      &[("DATABASE_URL", "postgres://diesel/db")],
+    )
+    .unwrap();
+    assert_eq!(config.database.url, "postgres://diesel/db");
+    let config = apply(
+      Config::default(),
+      &[
+        ("DATABASE_URL", "postgres://diesel/db"),
+        ("RMB_DATABASE_URL", "postgres://rmb/db"),
+      ],
+    )
+    .unwrap();
+    assert_eq!(config.database.url, "postgres://rmb/db");
+  }
+
+  #[test]
+  fn legacy_variables_apply_below_their_rmb_names() {
+    let config = apply(
+      Config::default(),
+      &[
+        ("DATABASE_POOL_SIZE", "4"),
+        ("DATABASE_POOL_TIMEOUT_SECS", "5"),
+        ("RMB_DATABASE_POOL_TIMEOUT_SECS", "6"),
+        ("DATABASE_POOL_IDLE_TIMEOUT_SECS", "7"),
+        ("JWT_SECRET", "legacy"),
+      ],
+    )
+    .unwrap();
+    assert_eq!(config.database.pool_size, 4);
+    assert_eq!(config.database.pool_timeout_secs, 6);
+    assert_eq!(config.database.pool_idle_timeout_secs, Some(7));
+    assert_eq!(config.auth.jwt_secret.as_deref(), Some("legacy"));
+
+    let config = apply(
+      Config::default(),
+      &[("JWT_SECRET", "legacy"), ("RMB_AUTH_JWT_SECRET", "current")],
+    )
+    .unwrap();
+    assert_eq!(config.auth.jwt_secret.as_deref(), Some("current"));
+    // An empty `JWT_SECRET` turned tokens off.
+    let config = apply(Config::default(), &[("JWT_SECRET", "")]).unwrap();
+    assert_eq!(config.auth.jwt_secret, None);
+  }
+
+  #[test]
+  fn invalid_values_name_their_variable() {
+    match apply(Config::default(), &[("RMB_SERVER_PORT", "eighty")]) {
+      Err(ConfigError::Env { key, value, .. }) => {
+        assert_eq!(key, "RMB_SERVER_PORT");
+        assert_eq!(value, "eighty");
+      }
+      other => panic!("expected an Env error, got {:?}", other),
+    }
+    match apply(Config::default(), &[("DATABASE_POOL_SIZE", "-1")]) {
+      Err(ConfigError::Env { key, .. }) => assert_eq!(key, "DATABASE_POOL_SIZE"),
+      other => panic!("expected an Env error, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn invalid_files_are_rejected() {
+    assert!(matches!(
+      from_toml("[server]\nport = \"eighty\"\n"),
+      Err(ConfigError::Parse { .. })
+    ));
+    assert!(matches!(
+      from_toml("[server]\nprot = 8000\n"),
+      Err(ConfigError::Parse { .. })
+    ));
+    assert!(matches!(
+      Config::from_file(Path::new("/nonexistent/rick-morty-back.toml")),
+      Err(ConfigError::Read { .. })
+    ));
+  }
+
+  #[test]
+  fn validate_accepts_the_defaults_with_a_database() {
+    assert_eq!(problems(&valid()), Vec::<String>::new());
+  }
+
+  #[test]
+  fn validate_reports_every_problem() {
+    let mut config = Config::default();
+    config.database.pool_size = 0;
+    config.server.ws_port = config.server.port;
+    config.server.cors_origins = vec!["localhost:4200".to_string()];
+    config.server.public_url = Some("api.example.com".to_string());
+    config.pagination.items_per_page = 200;
+    config.pagination.rest_page_size = 0;
+    config.auth.jwt_secret = Some("short".to_string());
+    let problems = problems(&config);
+    let settings: Vec<&str> = problems
+      .iter()
+      .map(|problem| problem.split(|c| c == ' ' || c == ':').next().unwrap())
+      .collect();
+    assert_eq!(
+      settings,
+      vec![
+        "database.url",
+        "database.pool_size",
+        "server.port",
+        "server.cors_origins",
+        "server.public_url",
+        "pagination.items_per_page",
+        "pagination.rest_page_size",
+        "auth.jwt_secret",
+      ]
+    );
+    assert!(ConfigError::Invalid(problems)
+      .to_string()
+      .starts_with("Invalid configuration:\n  - database.url is not set"));
+  }
+}
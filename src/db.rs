@@ -1,27 +1,60 @@
+use crate::config::DatabaseConfig;
 use crate::graphql::{
   character_model::Character,
   episode_model::{CharacterEpisode, Episode},
   location_model::Location,
 };
+use crate::loader::{find_seed_file, load_file, load_values, LoadReport, RowError, RowErrors};
 use crate::schema::*;
-use csv;
 use diesel::{
-  dsl::{count_star, Select},
+  connection::SimpleConnection,
   pg::PgConnection,
   prelude::*,
+  r2d2::{self, ConnectionManager, CustomizeConnection, Pool, PoolError, PooledConnection},
 };
-use dotenv::dotenv;
-use serde::de::DeserializeOwned;
-use std::env;
+use serde_json::{Map, Value};
+use std::fmt;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use std::time::Duration;
 
-pub fn establish_connection() -> PgConnection {
-  dotenv().ok();
-  let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-  let connection = PgConnection::establish(&database_url).expect("Database connection failed");
+pub type DbPool = Pool<ConnectionManager<PgConnection>>;
+pub type DbConnection = PooledConnection<ConnectionManager<PgConnection>>;
 
-  connection
+/// Builds the connection pool shared by every request.
+pub fn create_pool(config: &DatabaseConfig) -> Result<DbPool, PoolError> {
+  Pool::builder()
+    .max_size(config.pool_size)
+    .connection_timeout(Duration::from_secs(config.pool_timeout_secs))
+    .idle_timeout(config.pool_idle_timeout_secs.map(Duration::from_secs))
+    .connection_customizer(Box::new(SetApplicationName))
+    .build(ConnectionManager::new(config.url.as_str()))
+}
+
+/// The `application_name` of this process' pool connections, telling them
+/// apart in `pg_stat_activity`.
+pub fn application_name() -> String {
+  format!("rick_morty_back:{}", std::process::id())
+}
+
+#[derive(Debug)]
+struct SetApplicationName;
+
+impl CustomizeConnection<PgConnection, r2d2::Error> for SetApplicationName {
+  fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), r2d2::Error> {
+    conn
+      .batch_execute(&format!("SET application_name = '{}'", application_name()))
+      .map_err(r2d2::Error::QueryError)
+  }
+}
+
+/// A pool that never connects, for code that needs an `AppState` but not
+/// the database, such as schema introspection.
+pub fn unconnected_pool() -> DbPool {
+  Pool::builder()
+    .min_idle(Some(0))
+    .build_unchecked(ConnectionManager::new(""))
 }
 
 #[derive(Debug)]
@@ -31,55 +64,272 @@ pub struct DbCounts {
   pub episode: i32,
 }
 
-pub fn init_db() -> Result<DbCounts, diesel::result::Error> {
-  let conn = establish_connection();
-  let res = get_count(character::table, &conn)?;
+#[derive(Debug)]
+pub enum DbError {
+  Query(diesel::result::Error),
+  /// Seed files that could not be read or rows that do not deserialize.
+  Seed(Vec<RowError>),
+  /// An export file that could not be written.
+  Export {
+    path: PathBuf,
+    err: io::Error,
+  },
+  /// A data source that could not be read at all, e.g. an unreachable API.
+  Source {
+    name: String,
+    message: String,
+  },
+}
+
+impl fmt::Display for DbError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      DbError::Query(err) => write!(f, "{}", err),
+      DbError::Seed(errors) => write!(f, "{}", RowErrors(errors)),
+      DbError::Export { path, err } => write!(f, "Cannot write {}: {}", path.display(), err),
+      DbError::Source { name, message } => write!(f, "Cannot read {}: {}", name, message),
+    }
+  }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<diesel::result::Error> for DbError {
+  fn from(err: diesel::result::Error) -> Self {
+    DbError::Query(err)
+  }
+}
+
+// ######### DATA SOURCES ###############
+
+/// Receives the rows of a table in batches and returns how many it wrote.
+pub type Insert<'a, T> = &'a mut dyn FnMut(Vec<T>) -> QueryResult<usize>;
+
+/// Where the seed rows come from. Each method streams the rows of a table
+/// to `insert`, recording the rows that do not deserialize in `report`,
+/// and fails only when the source cannot be read or an insert fails.
+/// Callers ask for the tables in the order of the methods, so that the
+/// foreign keys of a table point to rows already loaded.
+pub trait DataSource {
+  /// Names the source in messages, e.g. its directory.
+  fn describe(&self) -> String;
+  fn locations(
+    &mut self,
+    report: &mut LoadReport,
+    insert: Insert<Location>,
+  ) -> Result<usize, DbError>;
+  fn characters(
+    &mut self,
+    report: &mut LoadReport,
+    insert: Insert<Character>,
+  ) -> Result<usize, DbError>;
+  fn episodes(
+    &mut self,
+    report: &mut LoadReport,
+    insert: Insert<Episode>,
+  ) -> Result<usize, DbError>;
+  fn character_episodes(
+    &mut self,
+    report: &mut LoadReport,
+    insert: Insert<CharacterEpisode>,
+  ) -> Result<usize, DbError>;
+}
+
+/// The seed files of a directory like `raw-data/`, in any format
+/// `find_seed_file` accepts.
+pub struct DirSource(pub PathBuf);
+
+impl DataSource for DirSource {
+  fn describe(&self) -> String {
+    self.0.display().to_string()
+  }
+  fn locations(
+    &mut self,
+    report: &mut LoadReport,
+    insert: Insert<Location>,
+  ) -> Result<usize, DbError> {
+    load_file(&find_seed_file(&self.0, "locations")?, report, insert)
+  }
+  fn characters(
+    &mut self,
+    report: &mut LoadReport,
+    insert: Insert<Character>,
+  ) -> Result<usize, DbError> {
+    load_file(&find_seed_file(&self.0, "characters")?, report, insert)
+  }
+  fn episodes(
+    &mut self,
+    report: &mut LoadReport,
+    insert: Insert<Episode>,
+  ) -> Result<usize, DbError> {
+    load_file(&find_seed_file(&self.0, "episodes")?, report, insert)
+  }
+  fn character_episodes(
+    &mut self,
+    report: &mut LoadReport,
+    insert: Insert<CharacterEpisode>,
+  ) -> Result<usize, DbError> {
+    load_file(
+      &find_seed_file(&self.0, "character_episode_join")?,
+      report,
+      insert,
+    )
+  }
+}
+
+/// A single JSON file holding every table under the name of its seed
+/// file, each an array of flat objects like the JSON seed files:
+/// `{"locations": [...], "characters": [...], "episodes": [...],
+/// "character_episode_join": [...]}`. It is read once, on first use.
+pub struct JsonDump {
+  path: PathBuf,
+  tables: Option<Map<String, Value>>,
+}
+
+impl JsonDump {
+  pub fn new(path: PathBuf) -> JsonDump {
+    JsonDump { path, tables: None }
+  }
+
+  fn error(&self, message: String) -> DbError {
+    DbError::Source {
+      name: self.path.display().to_string(),
+      message,
+    }
+  }
+
+  fn table<T, F>(
+    &mut self,
+    name: &str,
+    report: &mut LoadReport,
+    insert: F,
+  ) -> Result<usize, DbError>
+  where
+    T: serde::de::DeserializeOwned,
+    F: FnMut(Vec<T>) -> QueryResult<usize>,
+  {
+    if self.tables.is_none() {
+      let file = File::open(&self.path).map_err(|err| self.error(err.to_string()))?;
+      let tables =
+        serde_json::from_reader(BufReader::new(file)).map_err(|err| self.error(err.to_string()))?;
+      self.tables = Some(tables);
+    }
+    let rows = match self.tables.as_mut().and_then(|tables| tables.remove(name)) {
+      Some(Value::Array(rows)) => rows,
+      Some(_) => return Err(self.error(format!("{} is not an array", name))),
+      None => return Err(self.error(format!("No {} table", name))),
+    };
+    load_values(
+      &format!("{}: {}", self.path.display(), name),
+      rows.into_iter().map(Ok),
+      report,
+      insert,
+    )
+  }
+}
+
+impl DataSource for JsonDump {
+  fn describe(&self) -> String {
+    self.path.display().to_string()
+  }
+  fn locations(
+    &mut self,
+    report: &mut LoadReport,
+    insert: Insert<Location>,
+  ) -> Result<usize, DbError> {
+    self.table("locations", report, insert)
+  }
+  fn characters(
+    &mut self,
+    report: &mut LoadReport,
+    insert: Insert<Character>,
+  ) -> Result<usize, DbError> {
+    self.table("characters", report, insert)
+  }
+  fn episodes(
+    &mut self,
+    report: &mut LoadReport,
+    insert: Insert<Episode>,
+  ) -> Result<usize, DbError> {
+    self.table("episodes", report, insert)
+  }
+  fn character_episodes(
+    &mut self,
+    report: &mut LoadReport,
+    insert: Insert<CharacterEpisode>,
+  ) -> Result<usize, DbError> {
+    self.table("character_episode_join", report, insert)
+  }
+}
+
+// ######### SEEDING ###############
 
-  if res == 0 {
-    reset_db(&conn)?;
+/// Seeds the database from `source` when it is empty.
+pub fn init_db(conn: &PgConnection, source: &mut dyn DataSource) -> Result<DbCounts, DbError> {
+  if count_rows("character", conn)? == 0 {
+    reset_db(&conn, source)?;
   }
   Ok(get_all_counts(&conn)?)
 }
 
-pub fn reset_db(conn: &PgConnection) -> Result<(), diesel::result::Error> {
+/// Replaces every row with the rows of `source`, rolling back when any
+/// file or row cannot be loaded.
+pub fn reset_db(conn: &PgConnection, source: &mut dyn DataSource) -> Result<LoadReport, DbError> {
   // let table_with_files = vec![
   //   ("locations", location::table),
   //   ("characters", character::table),
   //   ("episodes", episode::table),
   // ];
-  Ok(conn.transaction::<(), diesel::result::Error, _>(|| {
+  conn.transaction::<LoadReport, DbError, _>(|| {
+    let mut report = LoadReport::default();
     diesel::delete(character_episode::table).execute(conn)?;
     diesel::delete(episode::table).execute(conn)?;
     diesel::delete(character::table).execute(conn)?;
     diesel::delete(location::table).execute(conn)?;
     // ############  location  ################
-    diesel::insert_into(location::table)
-      .values(read_tsv::<Location>("raw-data/locations.tsv"))
-      .execute(conn)?;
+    source.locations(&mut report, &mut |rows| {
+      diesel::insert_into(location::table)
+        .values(&rows)
+        .execute(conn)
+    })?;
     // #############  character  ################
-    diesel::insert_into(character::table)
-      .values(read_tsv::<Character>("raw-data/characters.tsv"))
-      .execute(conn)?;
+    source.characters(&mut report, &mut |rows| {
+      diesel::insert_into(character::table)
+        .values(&rows)
+        .execute(conn)
+    })?;
     // ##############  episode  ################
-    diesel::insert_into(episode::table)
-      .values(read_tsv::<Episode>("raw-data/episodes.tsv"))
-      .execute(conn)?;
+    source.episodes(&mut report, &mut |rows| {
+      diesel::insert_into(episode::table)
+        .values(&rows)
+        .execute(conn)
+    })?;
     // ############  character_episode  ################
-    diesel::insert_into(character_episode::table)
-      .values(read_tsv::<CharacterEpisode>(
-        "raw-data/character_episode_join.tsv",
-      ))
-      .execute(conn)?;
-
-    for t_name in ["character", "location", "episode"].iter() {
-      let query = format!(
-        "SELECT setval('{}_id_seq', (SELECT MAX(\"id\") FROM \"{}\"));",
-        t_name, t_name
-      );
-      diesel::sql_query(query).execute(conn)?;
-    }
-    Ok(())
-  })?)
+    source.character_episodes(&mut report, &mut |rows| {
+      diesel::insert_into(character_episode::table)
+        .values(&rows)
+        .execute(conn)
+    })?;
+
+    // `setval` is not rolled back, so the sequences only move once every
+    // row loaded.
+    let report = report.into_result()?;
+    reset_sequences(conn)?;
+    Ok(report)
+  })
+}
+
+/// Moves the id sequences past the largest id, after rows were inserted
+/// with explicit ids.
+pub fn reset_sequences(conn: &PgConnection) -> QueryResult<()> {
+  for t_name in ["character", "location", "episode"].iter() {
+    let query = format!(
+      "SELECT setval('{}_id_seq', (SELECT MAX(\"id\") FROM \"{}\"));",
+      t_name, t_name
+    );
+    diesel::sql_query(query).execute(conn)?;
+  }
+  Ok(())
 }
 
 // fn populate_table<Table, Model>(
@@ -99,29 +349,77 @@ pub fn reset_db(conn: &PgConnection) -> Result<(), diesel::result::Error> {
 //     .execute(conn)?;
 // }
 
-use diesel::query_dsl::select_dsl::SelectDsl;
-use diesel::query_dsl::{LoadQuery, RunQueryDsl};
+use diesel::result::QueryResult;
 
-fn get_count<T: SelectDsl<count_star>>(table: T, conn: &PgConnection) -> QueryResult<i64>
-where
-  Select<T, count_star>: LoadQuery<PgConnection, i64>,
-{
-  Ok(table.select(count_star()).get_result(conn)?)
+/// Number of rows in `table_name` ("character", "episode" or "location"),
+/// from the `entity_count` table that triggers keep in step with each
+/// insert, delete and truncate, so it follows rolled back transactions
+/// and writes from other processes. Writers of a table queue on its
+/// counter row, see the migration for why that is acceptable.
+pub fn count_rows(table_name: &str, conn: &PgConnection) -> QueryResult<i32> {
+  entity_count::table
+    .find(table_name)
+    .select(entity_count::count)
+    .get_result(conn)
 }
-use diesel::result::QueryResult;
+
 pub fn get_all_counts(conn: &PgConnection) -> QueryResult<DbCounts> {
   Ok(DbCounts {
-    character: get_count(character::table, &conn)? as i32,
-    location: get_count(location::table, &conn)? as i32,
-    episode: get_count(episode::table, &conn)? as i32,
+    character: count_rows("character", conn)?,
+    location: count_rows("location", conn)?,
+    episode: count_rows("episode", conn)?,
   })
 }
 
-fn read_tsv<T: DeserializeOwned>(filename: &str) -> Vec<T> {
-  let file = File::open(filename).unwrap();
-  let reader = BufReader::new(file);
-  let mut reader = csv::ReaderBuilder::new()
-    .delimiter(b'\t')
-    .from_reader(reader);
-  reader.deserialize::<T>().map(|r| r.unwrap()).collect()
+/// A row whose foreign key points to a row that does not exist.
+///
+/// For `character_episode`, `row_id` is the other half of the pair: the
+/// episode id when `character_id` is missing and vice versa.
+#[derive(Debug, QueryableByName)]
+pub struct DanglingReference {
+  #[sql_type = "diesel::sql_types::Text"]
+  pub table_name: String,
+  #[sql_type = "diesel::sql_types::Text"]
+  pub column_name: String,
+  #[sql_type = "diesel::sql_types::Int4"]
+  pub row_id: i32,
+  #[sql_type = "diesel::sql_types::Int4"]
+  pub missing_id: i32,
+}
+
+impl fmt::Display for DanglingReference {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{} {} references missing id {} in column {}",
+      self.table_name, self.row_id, self.missing_id, self.column_name
+    )
+  }
+}
+
+/// Lists every character and character_episode row referencing a missing
+/// location, character or episode.
+pub fn check_integrity(conn: &PgConnection) -> QueryResult<Vec<DanglingReference>> {
+  diesel::sql_query(
+    r#"
+    SELECT 'character' AS table_name, 'origin_id' AS column_name,
+      c."id" AS row_id, c."origin_id" AS missing_id
+    FROM "character" c LEFT JOIN "location" l ON l."id" = c."origin_id"
+    WHERE c."origin_id" IS NOT NULL AND l."id" IS NULL
+    UNION ALL
+    SELECT 'character', 'location_id', c."id", c."location_id"
+    FROM "character" c LEFT JOIN "location" l ON l."id" = c."location_id"
+    WHERE c."location_id" IS NOT NULL AND l."id" IS NULL
+    UNION ALL
+    SELECT 'character_episode', 'character_id', ce."episode_id", ce."character_id"
+    FROM "character_episode" ce LEFT JOIN "character" c ON c."id" = ce."character_id"
+    WHERE c."id" IS NULL
+    UNION ALL
+    SELECT 'character_episode', 'episode_id', ce."character_id", ce."episode_id"
+    FROM "character_episode" ce LEFT JOIN "episode" e ON e."id" = ce."episode_id"
+    WHERE e."id" IS NULL
+    ORDER BY 1, 2, 3
+    "#,
+  )
+  .load(conn)
 }
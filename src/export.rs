@@ -0,0 +1,348 @@
+//! Writes the whole dataset back out as seed files, so that rows edited
+//! through the mutations survive the next reset.
+//!
+//! Every table is written with the file name and columns `db::reset_db`
+//! reads, as TSV, a JSON array or NDJSON, so an export directory can be
+//! used as `data_dir` as is.
+
+use crate::auth::{Authorization, Role};
+use crate::db::DbError;
+use crate::graphql::{
+  character_model::{Character, CHARACTER_COLUMNS},
+  episode_model::{CharacterEpisode, Episode},
+  location_model::Location,
+  AppState, Ctx,
+};
+use crate::loader::{SeedFormat, BATCH_SIZE};
+use crate::schema::*;
+use diesel::{pg::PgConnection, prelude::*};
+use rocket::{get, http::Status, response::NamedFile, Route, State};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(juniper::GraphQLEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+  /// Tab separated values with a header line, like `raw-data/*.tsv`
+  Tsv,
+  /// An array of objects, one per line
+  Json,
+  /// One object per line
+  Ndjson,
+}
+
+impl ExportFormat {
+  pub fn extension(self) -> &'static str {
+    self.seed_format().extension()
+  }
+
+  fn seed_format(self) -> SeedFormat {
+    match self {
+      ExportFormat::Tsv => SeedFormat::Tsv,
+      ExportFormat::Json => SeedFormat::Json,
+      ExportFormat::Ndjson => SeedFormat::Ndjson,
+    }
+  }
+}
+
+impl FromStr for ExportFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "tsv" => Ok(ExportFormat::Tsv),
+      "json" => Ok(ExportFormat::Json),
+      "ndjson" => Ok(ExportFormat::Ndjson),
+      _ => Err(format!(
+        "Unknown export format {}, use tsv, json or ndjson",
+        s
+      )),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct ExportReport {
+  pub dir: PathBuf,
+  /// Each file written and the number of rows it holds.
+  pub files: Vec<(PathBuf, usize)>,
+}
+
+/// A new directory name for an export, unique per millisecond.
+pub fn export_name(format: ExportFormat) -> String {
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default();
+  format!("export-{}-{}", now.as_millis(), format.extension())
+}
+
+/// Writes every table to `dir`, creating it when needed. The rows are read
+/// in one repeatable read transaction, so the files are consistent with
+/// each other even while mutations run.
+pub fn export_db(
+  conn: &PgConnection,
+  dir: &Path,
+  format: ExportFormat,
+) -> Result<ExportReport, DbError> {
+  fs::create_dir_all(dir).map_err(|err| export_error(dir, err))?;
+  conn
+    .build_transaction()
+    .read_only()
+    .repeatable_read()
+    .run::<_, DbError, _>(|| {
+      let mut report = ExportReport {
+        dir: dir.to_path_buf(),
+        files: vec![],
+      };
+      // ############  location  ################
+      export_table(dir, "locations", format, &mut report, |offset| {
+        location::table
+          .order(location::id)
+          .offset(offset)
+          .limit(BATCH_SIZE as i64)
+          .load::<Location>(conn)
+      })?;
+      // #############  character  ################
+      export_table(dir, "characters", format, &mut report, |offset| {
+        character::table
+          .select(CHARACTER_COLUMNS)
+          .order(character::id)
+          .offset(offset)
+          .limit(BATCH_SIZE as i64)
+          .load::<Character>(conn)
+      })?;
+      // ##############  episode  ################
+      export_table(dir, "episodes", format, &mut report, |offset| {
+        episode::table
+          .order(episode::id)
+          .offset(offset)
+          .limit(BATCH_SIZE as i64)
+          .load::<Episode>(conn)
+      })?;
+      // ############  character_episode  ################
+      export_table(
+        dir,
+        "character_episode_join",
+        format,
+        &mut report,
+        |offset| {
+          character_episode::table
+            .order((
+              character_episode::character_id,
+              character_episode::episode_id,
+            ))
+            .offset(offset)
+            .limit(BATCH_SIZE as i64)
+            .load::<CharacterEpisode>(conn)
+        },
+      )?;
+      Ok(report)
+    })
+}
+
+fn export_error(path: &Path, err: io::Error) -> DbError {
+  DbError::Export {
+    path: path.to_path_buf(),
+    err,
+  }
+}
+
+/// Writes the rows `fetch` returns, from the given offset, to
+/// `<dir>/<stem>.<extension>` until a batch comes back short.
+fn export_table<T, F>(
+  dir: &Path,
+  stem: &str,
+  format: ExportFormat,
+  report: &mut ExportReport,
+  mut fetch: F,
+) -> Result<usize, DbError>
+where
+  T: Serialize,
+  F: FnMut(i64) -> QueryResult<Vec<T>>,
+{
+  let path = dir.join(format!("{}.{}", stem, format.extension()));
+  let file = File::create(&path).map_err(|err| export_error(&path, err))?;
+  let mut writer =
+    RowWriter::new(format, BufWriter::new(file)).map_err(|err| export_error(&path, err))?;
+  let mut written = 0;
+  loop {
+    let rows = fetch(written as i64)?;
+    for row in &rows {
+      writer.write(row).map_err(|err| export_error(&path, err))?;
+    }
+    written += rows.len();
+    if rows.len() < BATCH_SIZE {
+      break;
+    }
+  }
+  writer.finish().map_err(|err| export_error(&path, err))?;
+  report.files.push((path, written));
+  Ok(written)
+}
+
+enum RowWriter<W: Write> {
+  /// Writes the header line along with the first row.
+  Tsv(csv::Writer<W>),
+  Json {
+    out: W,
+    first: bool,
+  },
+  Ndjson(W),
+}
+
+impl<W: Write> RowWriter<W> {
+  fn new(format: ExportFormat, mut out: W) -> io::Result<RowWriter<W>> {
+    Ok(match format {
+      ExportFormat::Tsv => {
+        RowWriter::Tsv(csv::WriterBuilder::new().delimiter(b'\t').from_writer(out))
+      }
+      ExportFormat::Json => {
+        out.write_all(b"[")?;
+        RowWriter::Json { out, first: true }
+      }
+      ExportFormat::Ndjson => RowWriter::Ndjson(out),
+    })
+  }
+
+  fn write<T: Serialize>(&mut self, row: &T) -> io::Result<()> {
+    match self {
+      RowWriter::Tsv(writer) => Ok(writer.serialize(row)?),
+      RowWriter::Json { out, first } => {
+        out.write_all(if *first { &b"\n"[..] } else { &b",\n"[..] })?;
+        *first = false;
+        Ok(serde_json::to_writer(out, row)?)
+      }
+      RowWriter::Ndjson(out) => {
+        serde_json::to_writer(&mut *out, row)?;
+        out.write_all(b"\n")
+      }
+    }
+  }
+
+  /// Closes the JSON array and flushes, reporting the errors `Drop` would
+  /// swallow.
+  fn finish(self) -> io::Result<()> {
+    match self {
+      RowWriter::Tsv(mut writer) => writer.flush(),
+      RowWriter::Json { mut out, .. } => {
+        out.write_all(b"\n]\n")?;
+        out.flush()
+      }
+      RowWriter::Ndjson(mut out) => out.flush(),
+    }
+  }
+}
+
+// ######### DOWNLOAD ###############
+
+pub fn routes() -> Vec<Route> {
+  rocket::routes![download]
+}
+
+/// Whether `name` is a single path segment, so it cannot leave `export_dir`.
+fn is_file_name(name: &str) -> bool {
+  !name.is_empty() && name != "." && name != ".." && !name.contains(|c| c == '/' || c == '\\')
+}
+
+/// A file of an export, for admins only like the `exportDb` mutation.
+#[get("/<name>/<file>")]
+fn download(
+  name: String,
+  file: String,
+  auth: Authorization,
+  app: State<Arc<AppState>>,
+) -> Result<NamedFile, Status> {
+  match auth.0 {
+    None => return Err(Status::Unauthorized),
+    Some(identity) if identity.role < Role::Admin => return Err(Status::Forbidden),
+    Some(_) => {}
+  }
+  if !is_file_name(&name) || !is_file_name(&file) {
+    return Err(Status::NotFound);
+  }
+  let path = app.config().export_dir.join(name).join(file);
+  NamedFile::open(path).map_err(|_| Status::NotFound)
+}
+
+// ######### SCHEMA ###############
+
+pub struct DataExport {
+  name: String,
+  format: ExportFormat,
+  files: Vec<ExportedFile>,
+}
+
+pub struct ExportedFile {
+  name: String,
+  rows: i32,
+  path: String,
+}
+
+impl DataExport {
+  /// Exports the database to a new directory of `export_dir`, served under
+  /// `/exports`.
+  pub fn create(
+    conn: &PgConnection,
+    export_dir: &Path,
+    format: ExportFormat,
+  ) -> Result<DataExport, DbError> {
+    let name = export_name(format);
+    let report = export_db(conn, &export_dir.join(&name), format)?;
+    let files = report
+      .files
+      .iter()
+      .map(|(path, rows)| {
+        let file_name = path
+          .file_name()
+          .map(|file_name| file_name.to_string_lossy().into_owned())
+          .unwrap_or_default();
+        ExportedFile {
+          path: format!("/exports/{}/{}", name, file_name),
+          name: file_name,
+          rows: *rows as i32,
+        }
+      })
+      .collect();
+    Ok(DataExport {
+      name,
+      format,
+      files,
+    })
+  }
+}
+
+#[juniper::object(Context = Ctx,)]
+impl DataExport {
+  /// Name of the export directory
+  fn name(&self) -> &str {
+    &self.name
+  }
+  fn format(&self) -> ExportFormat {
+    self.format
+  }
+  /// Download path of the export, each of its files being under it
+  fn path(&self) -> String {
+    format!("/exports/{}", self.name)
+  }
+  fn files(&self) -> &Vec<ExportedFile> {
+    &self.files
+  }
+}
+
+#[juniper::object(Context = Ctx,)]
+impl ExportedFile {
+  fn name(&self) -> &str {
+    &self.name
+  }
+  fn rows(&self) -> i32 {
+    self.rows
+  }
+  /// Download path of the file, which needs an admin token
+  fn path(&self) -> &str {
+    &self.path
+  }
+}
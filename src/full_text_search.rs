@@ -0,0 +1,44 @@
+//! Minimal Diesel support for Postgres full text search.
+//!
+//! Only the pieces used by the `character.search_text` column are covered:
+//! the `tsvector`/`tsquery` SQL types, `plainto_tsquery`, `ts_rank` and
+//! the `@@` match operator. Every search string reaches the database as a
+//! bound parameter, never as part of the SQL text.
+
+pub mod types {
+  #[derive(Clone, Copy, QueryId, SqlType)]
+  #[postgres(oid = "3614", array_oid = "3643")]
+  pub struct TsVector;
+
+  #[derive(Clone, Copy, QueryId, SqlType)]
+  #[postgres(oid = "3615", array_oid = "3645")]
+  pub struct TsQuery;
+}
+
+pub mod functions {
+  use super::types::*;
+  use diesel::sql_types::*;
+
+  sql_function!(fn plainto_tsquery(query: Text) -> TsQuery);
+  sql_function!(fn ts_rank(vector: TsVector, query: TsQuery) -> Float);
+}
+
+pub mod dsl {
+  pub use super::functions::*;
+  pub use super::TsVectorExtensions;
+}
+
+use diesel::expression::{AsExpression, Expression};
+use diesel::pg::Pg;
+use types::*;
+
+diesel_infix_operator!(Matches, " @@ ", backend: Pg);
+
+pub trait TsVectorExtensions: Expression<SqlType = TsVector> + Sized {
+  /// `self @@ query`
+  fn matches<T: AsExpression<TsQuery>>(self, query: T) -> Matches<Self, T::Expression> {
+    Matches::new(self, query.as_expression())
+  }
+}
+
+impl<T: Expression<SqlType = TsVector>> TsVectorExtensions for T {}
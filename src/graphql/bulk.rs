@@ -0,0 +1,236 @@
+//! Batch versions of the create, update and delete mutations, run in a
+//! single transaction with a result per item.
+
+use crate::graphql::{
+  character_model::Character, episode_model::Episode, location_model::Location, Ctx,
+};
+use diesel::{pg::PgConnection, prelude::*, result::Error};
+use juniper::FieldResult;
+
+/// Largest batch a single bulk mutation accepts.
+const MAX_BULK_ITEMS: usize = 1000;
+
+#[derive(juniper::GraphQLEnum, Clone, Copy, Debug, PartialEq)]
+pub enum BulkMode {
+  /// Any failed item rolls the whole batch back
+  AllOrNothing,
+  /// Failed items are skipped and the others are committed
+  BestEffort,
+}
+
+impl Default for BulkMode {
+  fn default() -> Self {
+    BulkMode::AllOrNothing
+  }
+}
+
+pub struct BulkItem<Model> {
+  index: i32,
+  node: Option<Model>,
+  error: Option<String>,
+}
+
+pub struct BulkResult<Model> {
+  items: Vec<BulkItem<Model>>,
+  committed: bool,
+}
+
+impl<Model> BulkResult<Model> {
+  fn count_failed(&self) -> i32 {
+    self
+      .items
+      .iter()
+      .filter(|item| item.error.is_some())
+      .count() as i32
+  }
+}
+
+/// Why a single item failed.
+pub enum ItemError {
+  Query(Error),
+  /// The item was rejected before reaching the database.
+  Invalid(String),
+}
+
+impl From<Error> for ItemError {
+  fn from(err: Error) -> Self {
+    ItemError::Query(err)
+  }
+}
+
+/// A readable message for the error of a single item.
+fn describe(err: &ItemError) -> String {
+  match err {
+    ItemError::Query(Error::NotFound) => "No row with this id".to_string(),
+    ItemError::Query(Error::DatabaseError(_, info)) => match info.details() {
+      Some(details) => format!("{} ({})", info.message(), details),
+      None => info.message().to_string(),
+    },
+    ItemError::Query(err) => err.to_string(),
+    ItemError::Invalid(message) => message.clone(),
+  }
+}
+
+/// Applies `apply` to every item in one transaction, each item in its own
+/// savepoint so a failure leaves the others untouched. In `AllOrNothing`
+/// mode any failure rolls the transaction back, and the results still
+/// report every item, all without a node. Subscribers hear of the rows
+/// written once the transaction commits, through the database triggers.
+pub fn run_bulk<Item, Model, F>(
+  context: &Ctx,
+  mode: BulkMode,
+  items: Vec<Item>,
+  apply: F,
+) -> FieldResult<BulkResult<Model>>
+where
+  F: Fn(Item, &PgConnection) -> Result<Model, ItemError>,
+{
+  if items.len() > MAX_BULK_ITEMS {
+    return Err(
+      format!(
+        "A bulk mutation takes at most {} items, got {}",
+        MAX_BULK_ITEMS,
+        items.len()
+      )
+      .into(),
+    );
+  }
+  let conn = context.conn()?;
+  let mut results = Vec::with_capacity(items.len());
+  let outcome = conn.transaction::<(), Error, _>(|| {
+    for (index, item) in items.into_iter().enumerate() {
+      let index = index as i32;
+      match conn.transaction(|| apply(item, &conn)) {
+        Ok(node) => results.push(BulkItem {
+          index,
+          node: Some(node),
+          error: None,
+        }),
+        Err(err) => results.push(BulkItem {
+          index,
+          node: None,
+          error: Some(describe(&err)),
+        }),
+      }
+    }
+    if mode == BulkMode::AllOrNothing && results.iter().any(|item| item.error.is_some()) {
+      return Err(Error::RollbackTransaction);
+    }
+    Ok(())
+  });
+  let committed = match outcome {
+    Ok(()) => true,
+    Err(Error::RollbackTransaction) => false,
+    Err(err) => return Err(err.into()),
+  };
+  if !committed {
+    // Nothing was written, so no item has a node to return.
+    for item in &mut results {
+      item.node = None;
+    }
+  }
+  Ok(BulkResult {
+    items: results,
+    committed,
+  })
+}
+
+// ######### SCHEMA ###############
+
+#[juniper::object(name = "CharacterBulkItem", Context = Ctx,)]
+impl BulkItem<Character> {
+  /// Position of the item in the input list
+  fn index(&self) -> i32 {
+    self.index
+  }
+  /// The character as written, null when the item failed or the batch was
+  /// rolled back
+  fn character(&self) -> Option<&Character> {
+    self.node.as_ref()
+  }
+  fn error(&self) -> Option<&str> {
+    self.error.as_ref().map(String::as_str)
+  }
+}
+#[juniper::object(name = "EpisodeBulkItem", Context = Ctx,)]
+impl BulkItem<Episode> {
+  /// Position of the item in the input list
+  fn index(&self) -> i32 {
+    self.index
+  }
+  /// The episode as written, null when the item failed or the batch was
+  /// rolled back
+  fn episode(&self) -> Option<&Episode> {
+    self.node.as_ref()
+  }
+  fn error(&self) -> Option<&str> {
+    self.error.as_ref().map(String::as_str)
+  }
+}
+#[juniper::object(name = "LocationBulkItem", Context = Ctx,)]
+impl BulkItem<Location> {
+  /// Position of the item in the input list
+  fn index(&self) -> i32 {
+    self.index
+  }
+  /// The location as written, null when the item failed or the batch was
+  /// rolled back
+  fn location(&self) -> Option<&Location> {
+    self.node.as_ref()
+  }
+  fn error(&self) -> Option<&str> {
+    self.error.as_ref().map(String::as_str)
+  }
+}
+
+#[juniper::object(name = "CharacterBulkResult", Context = Ctx,)]
+impl BulkResult<Character> {
+  fn items(&self) -> &Vec<BulkItem<Character>> {
+    &self.items
+  }
+  /// False when the batch was rolled back
+  fn committed(&self) -> bool {
+    self.committed
+  }
+  /// Items applied without error, only written when `committed`
+  fn succeeded(&self) -> i32 {
+    self.items.len() as i32 - self.count_failed()
+  }
+  fn failed(&self) -> i32 {
+    self.count_failed()
+  }
+}
+#[juniper::object(name = "EpisodeBulkResult", Context = Ctx,)]
+impl BulkResult<Episode> {
+  fn items(&self) -> &Vec<BulkItem<Episode>> {
+    &self.items
+  }
+  /// False when the batch was rolled back
+  fn committed(&self) -> bool {
+    self.committed
+  }
+  /// Items applied without error, only written when `committed`
+  fn succeeded(&self) -> i32 {
+    self.items.len() as i32 - self.count_failed()
+  }
+  fn failed(&self) -> i32 {
+    self.count_failed()
+  }
+}
+#[juniper::object(name = "LocationBulkResult", Context = Ctx,)]
+impl BulkResult<Location> {
+  fn items(&self) -> &Vec<BulkItem<Location>> {
+    &self.items
+  }
+  /// False when the batch was rolled back
+  fn committed(&self) -> bool {
+    self.committed
+  }
+  /// Items applied without error, only written when `committed`
+  fn succeeded(&self) -> i32 {
+    self.items.len() as i32 - self.count_failed()
+  }
+  fn failed(&self) -> i32 {
+    self.count_failed()
+  }
+}
@@ -1,28 +1,183 @@
-use crate::db::establish_connection;
+use crate::db::DanglingReference;
 use crate::graphql::{
+  bulk::{run_bulk, BulkMode, BulkResult},
+  connection::{Connection, ConnectionArgs},
   episode_model::{CharacterEpisode, Episode},
   location_model::Location,
   Ctx,
 };
-use crate::schema::{character, character_episode, episode, location};
-use diesel::{self, prelude::*, Insertable, Queryable};
-use juniper::FieldResult;
+use crate::schema::{character, character_episode};
+use diesel::{
+  self,
+  deserialize::{self, FromSql},
+  pg::Pg,
+  prelude::*,
+  serialize::{self, IsNull, Output, ToSql},
+  sql_types::Varchar,
+  Connection as _, Insertable, Queryable,
+};
+use juniper::{FieldResult, IntoFieldError};
 use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::str::FromStr;
+
+#[derive(
+  juniper::GraphQLEnum,
+  AsExpression,
+  FromSqlRow,
+  Serialize,
+  Deserialize,
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  Hash,
+)]
+#[sql_type = "Varchar"]
+pub enum CharacterStatus {
+  #[serde(rename = "Alive")]
+  Alive,
+  #[serde(rename = "Dead")]
+  Dead,
+  #[serde(rename = "unknown")]
+  Unknown,
+}
+
+impl CharacterStatus {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      CharacterStatus::Alive => "Alive",
+      CharacterStatus::Dead => "Dead",
+      CharacterStatus::Unknown => "unknown",
+    }
+  }
+}
+
+impl FromStr for CharacterStatus {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "Alive" => Ok(CharacterStatus::Alive),
+      "Dead" => Ok(CharacterStatus::Dead),
+      "unknown" => Ok(CharacterStatus::Unknown),
+      _ => Err(format!("Unrecognized character status: {:?}", value)),
+    }
+  }
+}
+
+#[derive(
+  juniper::GraphQLEnum,
+  AsExpression,
+  FromSqlRow,
+  Serialize,
+  Deserialize,
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  Hash,
+)]
+#[sql_type = "Varchar"]
+pub enum CharacterGender {
+  #[serde(rename = "Female")]
+  Female,
+  #[serde(rename = "Male")]
+  Male,
+  #[serde(rename = "Genderless")]
+  Genderless,
+  #[serde(rename = "unknown")]
+  Unknown,
+}
+
+impl CharacterGender {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      CharacterGender::Female => "Female",
+      CharacterGender::Male => "Male",
+      CharacterGender::Genderless => "Genderless",
+      CharacterGender::Unknown => "unknown",
+    }
+  }
+}
+
+impl FromStr for CharacterGender {
+  type Err = String;
 
-#[derive(Serialize, Deserialize, Queryable, Insertable)]
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "Female" => Ok(CharacterGender::Female),
+      "Male" => Ok(CharacterGender::Male),
+      "Genderless" => Ok(CharacterGender::Genderless),
+      "unknown" => Ok(CharacterGender::Unknown),
+      _ => Err(format!("Unrecognized character gender: {:?}", value)),
+    }
+  }
+}
+
+// Both enums are stored as their `as_str` value in varchar columns, which
+// the "character_*_check" constraints restrict to the same set of values.
+macro_rules! varchar_enum_sql {
+  ($name:ident) => {
+    impl ToSql<Varchar, Pg> for $name {
+      fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+      }
+    }
+
+    impl FromSql<Varchar, Pg> for $name {
+      fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        let value = <String as FromSql<Varchar, Pg>>::from_sql(bytes)?;
+        Ok(value.parse()?)
+      }
+    }
+  };
+}
+
+varchar_enum_sql!(CharacterStatus);
+varchar_enum_sql!(CharacterGender);
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Queryable, Insertable)]
 #[table_name = "character"]
 pub struct Character {
-  id: i32,
-  name: String,
-  status: String,
-  species: String,
-  gender: String,
+  pub id: i32,
+  pub name: String,
+  pub status: CharacterStatus,
+  pub species: String,
+  pub gender: CharacterGender,
   #[serde(rename = "type")]
-  type_: Option<String>,
-  origin_id: Option<i32>,
-  location_id: Option<i32>,
+  pub type_: Option<String>,
+  pub origin_id: Option<i32>,
+  pub location_id: Option<i32>,
 }
 
+pub type CharacterColumns = (
+  character::id,
+  character::name,
+  character::status,
+  character::species,
+  character::gender,
+  character::type_,
+  character::origin_id,
+  character::location_id,
+);
+
+/// The columns a `Character` is loaded from. `search_text` is maintained
+/// by a trigger and has no Rust representation, so it is never selected.
+pub const CHARACTER_COLUMNS: CharacterColumns = (
+  character::id,
+  character::name,
+  character::status,
+  character::species,
+  character::gender,
+  character::type_,
+  character::origin_id,
+  character::location_id,
+);
+
 #[juniper::object(
   Context = Ctx,
 )]
@@ -33,14 +188,14 @@ impl Character {
   fn name(&self) -> &str {
     &self.name
   }
-  fn status(&self) -> &str {
-    &self.status
+  fn status(&self) -> CharacterStatus {
+    self.status
   }
   fn species(&self) -> &str {
     &self.species
   }
-  fn gender(&self) -> &str {
-    &self.gender
+  fn gender(&self) -> CharacterGender {
+    self.gender
   }
   fn origin_id(&self) -> &Option<i32> {
     &self.origin_id
@@ -53,42 +208,76 @@ impl Character {
     &self.type_
   }
 
-  fn origin(&self) -> Option<Location> {
-    let conn = establish_connection();
-    match self.origin_id {
-      Some(origin_id) => Some(location::table.find(origin_id).get_result(&conn).unwrap()),
-      None => None,
-    }
+  fn origin(&self, context: &Ctx) -> FieldResult<Option<Location>> {
+    self.find_location("origin_id", self.origin_id, context)
   }
 
-  fn location(&self) -> Option<Location> {
-    let conn = establish_connection();
-    match self.location_id {
-      Some(location_id) => Some(location::table.find(location_id).get_result(&conn).unwrap()),
-      None => None,
-    }
+  fn location(&self, context: &Ctx) -> FieldResult<Option<Location>> {
+    self.find_location("location_id", self.location_id, context)
   }
 
-  fn episodes(&self) -> FieldResult<Vec<Episode>> {
-    let conn = establish_connection();
-    Ok(
-      character_episode::table
-        .inner_join(character::table)
-        .inner_join(episode::table)
-        .filter(character::id.eq(self.id))
-        .select(episode::all_columns)
-        .get_results(&conn)?,
+  fn episodes(&self, context: &Ctx) -> FieldResult<Vec<Episode>> {
+    let db_conn = context.conn()?;
+    Ok(context.loaders.episodes_of_character(self.id, &db_conn)?)
+  }
+
+  fn episodes_connection(
+    &self,
+    first: Option<i32>,
+    after: Option<String>,
+    last: Option<i32>,
+    before: Option<String>,
+    context: &Ctx,
+  ) -> FieldResult<Connection<Episode>> {
+    let db_conn = context.conn()?;
+    let items = context.loaders.episodes_of_character(self.id, &db_conn)?;
+    Connection::from_vec(
+      items,
+      ConnectionArgs {
+        first,
+        after,
+        last,
+        before,
+      },
+      context.pagination(),
     )
   }
 }
 
+impl Character {
+  fn find_location(
+    &self,
+    column_name: &str,
+    location_id: Option<i32>,
+    context: &Ctx,
+  ) -> FieldResult<Option<Location>> {
+    let location_id = match location_id {
+      Some(location_id) => location_id,
+      None => return Ok(None),
+    };
+    let db_conn = context.conn()?;
+    match context.loaders.location(location_id, &db_conn)? {
+      Some(location) => Ok(Some(location)),
+      None => Err(
+        DanglingReference {
+          table_name: "character".to_string(),
+          column_name: column_name.to_string(),
+          row_id: self.id,
+          missing_id: location_id,
+        }
+        .into_field_error(),
+      ),
+    }
+  }
+}
+
 #[derive(juniper::GraphQLInputObject, Insertable)]
 #[table_name = "character"]
 pub struct CharacterCreator {
   name: String,
-  status: String,
+  status: CharacterStatus,
   species: String,
-  gender: String,
+  gender: CharacterGender,
   #[graphql(name = "type")]
   type_: Option<String>,
   origin_id: Option<i32>,
@@ -101,9 +290,9 @@ pub struct CharacterCreator {
 pub struct CharacterUpdater {
   id: i32,
   name: String,
-  status: String,
+  status: CharacterStatus,
   species: String,
-  gender: String,
+  gender: CharacterGender,
   #[graphql(name = "type")]
   pub type_: Option<String>,
   pub origin_id: Option<i32>,
@@ -115,6 +304,20 @@ pub struct CharacterRelations {
   episode_ids: Vec<i32>,
 }
 
+/// One item of `createCharacters`.
+#[derive(juniper::GraphQLInputObject)]
+pub struct CharacterCreation {
+  creator: CharacterCreator,
+  relations: Option<CharacterRelations>,
+}
+
+/// One item of `updateCharacters`.
+#[derive(juniper::GraphQLInputObject)]
+pub struct CharacterUpdate {
+  updater: CharacterUpdater,
+  relations: Option<CharacterRelations>,
+}
+
 fn insert_character_relations(
   id: i32,
   relations: CharacterRelations,
@@ -133,6 +336,50 @@ fn insert_character_relations(
     .execute(conn)?;
   Ok(())
 }
+
+fn insert_character_row(
+  creator: CharacterCreator,
+  relations: Option<CharacterRelations>,
+  conn: &PgConnection,
+) -> QueryResult<Character> {
+  let ans: Character = diesel::insert_into(character::table)
+    .values(creator)
+    .returning(CHARACTER_COLUMNS)
+    .get_result(conn)?;
+  if let Some(relations) = relations {
+    if relations.episode_ids.len() > 0 {
+      insert_character_relations(ans.id, relations, conn)?;
+    }
+  }
+  Ok(ans)
+}
+
+fn update_character_row(
+  updater: CharacterUpdater,
+  relations: Option<CharacterRelations>,
+  conn: &PgConnection,
+) -> QueryResult<Character> {
+  if let Some(relations) = relations {
+    diesel::delete(character_episode::table)
+      .filter(character_episode::character_id.eq(updater.id))
+      .execute(conn)?;
+    insert_character_relations(updater.id, relations, conn)?;
+  }
+  diesel::update(&updater)
+    .set(&updater)
+    .returning(CHARACTER_COLUMNS)
+    .get_result(conn)
+}
+
+fn delete_character_row(id: i32, conn: &PgConnection) -> QueryResult<Option<Character>> {
+  diesel::delete(character_episode::table.filter(character_episode::character_id.eq(id)))
+    .execute(conn)?;
+  diesel::delete(character::table.find(id))
+    .returning(CHARACTER_COLUMNS)
+    .get_result(conn)
+    .optional()
+}
+
 pub struct CaracterMutation;
 
 #[juniper::object(
@@ -144,39 +391,28 @@ impl CaracterMutation {
     relations: CharacterRelations,
     context: &Ctx,
   ) -> FieldResult<Character> {
-    let db_conn = establish_connection();
+    let db_conn = context.conn()?;
     Ok(
       db_conn.transaction::<Character, diesel::result::Error, _>(|| {
-        let ans: Character = diesel::insert_into(character::table)
-          .values(creator)
-          .get_result(&db_conn)?;
-        if relations.episode_ids.len() > 0 {
-          insert_character_relations(ans.id, relations, &db_conn)?;
-        }
-        *context.character.write().unwrap() += 1;
-        Ok(ans)
+        insert_character_row(creator, Some(relations), &db_conn)
       })?,
     )
   }
 
   pub fn delete_character(id: i32, context: &Ctx) -> FieldResult<bool> {
-    let conn = establish_connection();
-    conn.transaction(|| {
-      diesel::delete(character_episode::table.filter(character_episode::character_id.eq(id)))
-        .execute(&conn)?;
-      let made_delete = diesel::delete(character::table.find(id)).execute(&conn)? == 1;
-      if made_delete {
-        *context.character.write().unwrap() -= 1;
-      }
-      Ok(made_delete)
-    })
+    let conn = context.conn()?;
+    let deleted = conn.transaction::<Option<Character>, diesel::result::Error, _>(|| {
+      delete_character_row(id, &conn)
+    })?;
+    Ok(deleted.is_some())
   }
 
   pub fn update_character(
-    mut updater: CharacterUpdater,
+    updater: CharacterUpdater,
     relations: Option<CharacterRelations>,
+    context: &Ctx,
   ) -> FieldResult<Character> {
-    let conn = establish_connection();
+    let conn = context.conn()?;
     // if let Some(Some(v)) = &updater.location_id {
     //   if *v < 0 {
     //     updater.location_id = Some(None);
@@ -193,13 +429,41 @@ impl CaracterMutation {
     //   }
     // }
     Ok(conn.transaction::<Character, diesel::result::Error, _>(|| {
-      if let Some(relations) = relations {
-        diesel::delete(character_episode::table)
-          .filter(character_episode::character_id.eq(updater.id))
-          .execute(&conn)?;
-        insert_character_relations(updater.id, relations, &conn)?;
-      }
-      Ok(updater.save_changes(&conn)?)
+      update_character_row(updater, relations, &conn)
     })?)
   }
+
+  /// Creates every character in a single transaction
+  pub fn create_characters(
+    items: Vec<CharacterCreation>,
+    mode: Option<BulkMode>,
+    context: &Ctx,
+  ) -> FieldResult<BulkResult<Character>> {
+    run_bulk(context, mode.unwrap_or_default(), items, |item, conn| {
+      Ok(insert_character_row(item.creator, item.relations, conn)?)
+    })
+  }
+
+  /// Updates every character in a single transaction
+  pub fn update_characters(
+    items: Vec<CharacterUpdate>,
+    mode: Option<BulkMode>,
+    context: &Ctx,
+  ) -> FieldResult<BulkResult<Character>> {
+    run_bulk(context, mode.unwrap_or_default(), items, |item, conn| {
+      Ok(update_character_row(item.updater, item.relations, conn)?)
+    })
+  }
+
+  /// Deletes every character in a single transaction; a missing id is an
+  /// error of its item
+  pub fn delete_characters(
+    ids: Vec<i32>,
+    mode: Option<BulkMode>,
+    context: &Ctx,
+  ) -> FieldResult<BulkResult<Character>> {
+    run_bulk(context, mode.unwrap_or_default(), ids, |id, conn| {
+      Ok(delete_character_row(id, conn)?.ok_or(diesel::result::Error::NotFound)?)
+    })
+  }
 }
@@ -0,0 +1,247 @@
+use crate::config::PaginationConfig;
+use crate::graphql::{
+  character_model::Character, episode_model::Episode, location_model::Location, Ctx,
+};
+use diesel::QueryResult;
+use juniper::{FieldError, FieldResult};
+
+const CURSOR_PREFIX: &str = "cursor:";
+
+/// Anything that can be paginated with a keyset cursor on its id.
+pub trait Node {
+  fn node_id(&self) -> i32;
+}
+
+impl Node for Character {
+  fn node_id(&self) -> i32 {
+    self.id
+  }
+}
+impl Node for Episode {
+  fn node_id(&self) -> i32 {
+    self.id
+  }
+}
+impl Node for Location {
+  fn node_id(&self) -> i32 {
+    self.id
+  }
+}
+
+pub fn encode_cursor(id: i32) -> String {
+  base64::encode(&format!("{}{}", CURSOR_PREFIX, id))
+}
+
+pub fn decode_cursor(cursor: &str) -> FieldResult<i32> {
+  base64::decode(cursor)
+    .ok()
+    .and_then(|bytes| String::from_utf8(bytes).ok())
+    .filter(|decoded| decoded.starts_with(CURSOR_PREFIX))
+    .and_then(|decoded| decoded[CURSOR_PREFIX.len()..].parse().ok())
+    .ok_or_else(|| FieldError::from(format!("Invalid cursor: {}", cursor)))
+}
+
+/// The `first`/`after`/`last`/`before` arguments of a connection field.
+pub struct ConnectionArgs {
+  pub first: Option<i32>,
+  pub after: Option<String>,
+  pub last: Option<i32>,
+  pub before: Option<String>,
+}
+
+/// A validated page request: rows with `after < id < before`, walking
+/// the ids backwards when paginating with `last`.
+pub struct Keyset {
+  pub after: Option<i32>,
+  pub before: Option<i32>,
+  pub limit: i64,
+  pub backwards: bool,
+}
+
+impl ConnectionArgs {
+  pub fn keyset(&self, pagination: &PaginationConfig) -> FieldResult<Keyset> {
+    let (size, backwards) = match (self.first, self.last) {
+      (Some(_), Some(_)) => return Err("Use either `first` or `last`, not both".into()),
+      (Some(first), None) => (first, false),
+      (None, Some(last)) => (last, true),
+      (None, None) => (pagination.items_per_page, false),
+    };
+    if size < 0 || size > pagination.max_page_size {
+      return Err(
+        format!(
+          "`first` and `last` must be between 0 and {}",
+          pagination.max_page_size
+        )
+        .into(),
+      );
+    }
+    Ok(Keyset {
+      after: self.after.as_ref().map(|c| decode_cursor(c)).transpose()?,
+      before: self.before.as_ref().map(|c| decode_cursor(c)).transpose()?,
+      limit: size as i64,
+      backwards,
+    })
+  }
+}
+
+#[derive(juniper::GraphQLObject)]
+pub struct PageInfo {
+  has_next_page: bool,
+  has_previous_page: bool,
+  start_cursor: Option<String>,
+  end_cursor: Option<String>,
+}
+
+pub struct Edge<Model> {
+  cursor: String,
+  node: Model,
+}
+
+pub struct Connection<Model> {
+  edges: Vec<Edge<Model>>,
+  page_info: PageInfo,
+  total_count: i32,
+}
+
+impl<Model: Node> Connection<Model> {
+  /// Builds a connection from the rows fetched for `keyset`. `fetch` must
+  /// return up to `limit + 1` rows ordered by id, descending when
+  /// `backwards`, so the extra row tells whether there is another page.
+  pub fn load<F>(
+    args: ConnectionArgs,
+    pagination: &PaginationConfig,
+    total_count: i32,
+    fetch: F,
+  ) -> FieldResult<Self>
+  where
+    F: FnOnce(&Keyset) -> QueryResult<Vec<Model>>,
+  {
+    let keyset = args.keyset(pagination)?;
+    let mut rows = fetch(&keyset)?;
+    let has_more = rows.len() as i64 > keyset.limit;
+    rows.truncate(keyset.limit as usize);
+    if keyset.backwards {
+      rows.reverse();
+    }
+    let (has_next_page, has_previous_page) = if keyset.backwards {
+      (keyset.before.is_some(), has_more)
+    } else {
+      (has_more, keyset.after.is_some())
+    };
+    Ok(Connection::new(
+      rows,
+      has_next_page,
+      has_previous_page,
+      total_count,
+    ))
+  }
+
+  /// Paginates a list that was already loaded, e.g. through the loaders.
+  pub fn from_vec(
+    mut items: Vec<Model>,
+    args: ConnectionArgs,
+    pagination: &PaginationConfig,
+  ) -> FieldResult<Self> {
+    let keyset = args.keyset(pagination)?;
+    let total_count = items.len() as i32;
+    items.sort_by_key(Node::node_id);
+    items.retain(|item| {
+      keyset.after.map_or(true, |after| item.node_id() > after)
+        && keyset.before.map_or(true, |before| item.node_id() < before)
+    });
+    Connection::load(args, pagination, total_count, |_| {
+      if keyset.backwards {
+        items.reverse();
+      }
+      items.truncate(keyset.limit as usize + 1);
+      Ok(items)
+    })
+  }
+
+  fn new(rows: Vec<Model>, has_next_page: bool, has_previous_page: bool, total_count: i32) -> Self {
+    let edges: Vec<Edge<Model>> = rows
+      .into_iter()
+      .map(|node| Edge {
+        cursor: encode_cursor(node.node_id()),
+        node,
+      })
+      .collect();
+    let page_info = PageInfo {
+      has_next_page,
+      has_previous_page,
+      start_cursor: edges.first().map(|e| e.cursor.clone()),
+      end_cursor: edges.last().map(|e| e.cursor.clone()),
+    };
+    Connection {
+      edges,
+      page_info,
+      total_count,
+    }
+  }
+}
+
+#[juniper::object(name = "CharacterConnection", Context = Ctx,)]
+impl Connection<Character> {
+  fn edges(&self) -> &Vec<Edge<Character>> {
+    &self.edges
+  }
+  fn page_info(&self) -> &PageInfo {
+    &self.page_info
+  }
+  fn total_count(&self) -> i32 {
+    self.total_count
+  }
+}
+#[juniper::object(name = "CharacterEdge", Context = Ctx,)]
+impl Edge<Character> {
+  fn cursor(&self) -> &str {
+    &self.cursor
+  }
+  fn node(&self) -> &Character {
+    &self.node
+  }
+}
+
+#[juniper::object(name = "EpisodeConnection", Context = Ctx,)]
+impl Connection<Episode> {
+  fn edges(&self) -> &Vec<Edge<Episode>> {
+    &self.edges
+  }
+  fn page_info(&self) -> &PageInfo {
+    &self.page_info
+  }
+  fn total_count(&self) -> i32 {
+    self.total_count
+  }
+}
+#[juniper::object(name = "EpisodeEdge", Context = Ctx,)]
+impl Edge<Episode> {
+  fn cursor(&self) -> &str {
+    &self.cursor
+  }
+  fn node(&self) -> &Episode {
+    &self.node
+  }
+}
+
+#[juniper::object(name = "LocationConnection", Context = Ctx,)]
+impl Connection<Location> {
+  fn edges(&self) -> &Vec<Edge<Location>> {
+    &self.edges
+  }
+  fn page_info(&self) -> &PageInfo {
+    &self.page_info
+  }
+  fn total_count(&self) -> i32 {
+    self.total_count
+  }
+}
+#[juniper::object(name = "LocationEdge", Context = Ctx,)]
+impl Edge<Location> {
+  fn cursor(&self) -> &str {
+    &self.cursor
+  }
+  fn node(&self) -> &Location {
+    &self.node
+  }
+}
@@ -1,20 +1,94 @@
-use crate::db::establish_connection;
-use crate::graphql::{character_model::Character, Ctx};
-use crate::schema::{character, character_episode, episode};
-use diesel::{self, prelude::*, Insertable, Queryable};
+use crate::graphql::{
+  bulk::{run_bulk, BulkMode, BulkResult, ItemError},
+  character_model::Character,
+  connection::{Connection, ConnectionArgs},
+  filters::is_day,
+  Ctx,
+};
+use crate::schema::{character_episode, episode};
+use diesel::{self, prelude::*, Connection as _, Insertable, Queryable};
 use juniper::FieldResult;
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
 
-#[derive(Serialize, Deserialize, Queryable, Insertable)]
+/// Postgres `to_date` pattern matching air dates such as "December 2, 2013".
+pub const AIR_DATE_FORMAT: &str = "FMMonth FMDD, YYYY";
+
+const MONTHS: [&str; 12] = [
+  "January",
+  "February",
+  "March",
+  "April",
+  "May",
+  "June",
+  "July",
+  "August",
+  "September",
+  "October",
+  "November",
+  "December",
+];
+
+sql_function!(fn to_date(text: diesel::sql_types::Text, format: diesel::sql_types::Text) -> diesel::sql_types::Date);
+
+/// Whether `value` is an existing day written like "December 2, 2013", the
+/// air dates `to_date` reads with `AIR_DATE_FORMAT`. The
+/// `episode_air_date_check` constraint accepts the same values.
+pub fn is_air_date(value: &str) -> bool {
+  let digits = |part: &str, lengths: &[usize]| {
+    lengths.contains(&part.len())
+      && !part.starts_with('0')
+      && part.bytes().all(|b| b.is_ascii_digit())
+  };
+  let (month_day, year) = match value.find(", ") {
+    Some(comma) => (&value[..comma], &value[comma + 2..]),
+    None => return false,
+  };
+  let mut parts = month_day.split(' ');
+  match (parts.next(), parts.next(), parts.next()) {
+    (Some(month), Some(day), None) if digits(day, &[1, 2]) && digits(year, &[4]) => {
+      match MONTHS.iter().position(|name| *name == month) {
+        // The digits always parse.
+        Some(month) => is_day(
+          year.parse().unwrap_or_default(),
+          month as u32 + 1,
+          day.parse().unwrap_or_default(),
+        ),
+        None => false,
+      }
+    }
+    _ => false,
+  }
+}
+
+fn check_air_date(value: &str) -> Result<(), String> {
+  if is_air_date(value) {
+    Ok(())
+  } else {
+    Err(format!(
+      "airDate must be a day written like \"December 2, 2013\", got {:?}",
+      value
+    ))
+  }
+}
+
+/// Rejects the seed rows whose `air_date` the filters could not read.
+fn deserialize_air_date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+  let value = String::deserialize(deserializer)?;
+  check_air_date(&value).map_err(de::Error::custom)?;
+  Ok(value)
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Queryable, Insertable)]
 #[table_name = "episode"]
 pub struct Episode {
-  id: i32,
-  name: String,
-  air_date: String,
-  code: String,
+  pub id: i32,
+  pub name: String,
+  #[serde(deserialize_with = "deserialize_air_date")]
+  pub air_date: String,
+  pub code: String,
 }
 
-#[derive(Deserialize, Serialize, Insertable)]
+#[derive(Deserialize, Serialize, Queryable, Insertable)]
 #[table_name = "character_episode"]
 pub struct CharacterEpisode {
   pub character_id: i32,
@@ -38,15 +112,30 @@ impl Episode {
     &self.code
   }
 
-  fn characters(&self) -> FieldResult<Vec<Character>> {
-    let conn = establish_connection();
-    Ok(
-      character_episode::table
-        .inner_join(character::table)
-        .inner_join(episode::table)
-        .filter(episode::id.eq(self.id))
-        .select(character::all_columns)
-        .get_results(&conn)?,
+  fn characters(&self, context: &Ctx) -> FieldResult<Vec<Character>> {
+    let db_conn = context.conn()?;
+    Ok(context.loaders.characters_of_episode(self.id, &db_conn)?)
+  }
+
+  fn characters_connection(
+    &self,
+    first: Option<i32>,
+    after: Option<String>,
+    last: Option<i32>,
+    before: Option<String>,
+    context: &Ctx,
+  ) -> FieldResult<Connection<Character>> {
+    let db_conn = context.conn()?;
+    let items = context.loaders.characters_of_episode(self.id, &db_conn)?;
+    Connection::from_vec(
+      items,
+      ConnectionArgs {
+        first,
+        after,
+        last,
+        before,
+      },
+      context.pagination(),
     )
   }
 }
@@ -69,34 +158,111 @@ struct EpisodeCreator {
   code: String,
 }
 
+fn delete_episode_row(id: i32, conn: &PgConnection) -> QueryResult<Option<Episode>> {
+  diesel::delete(character_episode::table.filter(character_episode::episode_id.eq(id)))
+    .execute(conn)?;
+  diesel::delete(episode::table.find(id))
+    .get_result(conn)
+    .optional()
+}
+
 pub struct EpisodeMutation;
 
 #[juniper::object(Context= Ctx,)]
 impl EpisodeMutation {
   pub fn create_episode(creator: EpisodeCreator, context: &Ctx) -> FieldResult<Episode> {
-    let db_conn = establish_connection();
-    let ans: Episode = diesel::insert_into(episode::table)
-      .values(creator)
-      .get_result(&db_conn)?;
-    *context.episode.write().unwrap() += 1;
-    Ok(ans)
+    check_air_date(&creator.air_date)?;
+    let db_conn = context.conn()?;
+    Ok(
+      diesel::insert_into(episode::table)
+        .values(creator)
+        .get_result(&db_conn)?,
+    )
   }
 
   pub fn delete_episode(id: i32, context: &Ctx) -> FieldResult<bool> {
-    let conn = establish_connection();
-    conn.transaction(|| {
-      diesel::delete(character_episode::table.filter(character_episode::episode_id.eq(id)))
-        .execute(&conn)?;
-      let made_delete = diesel::delete(episode::table.find(id)).execute(&conn)? == 1;
-      if made_delete {
-        *context.episode.write().unwrap() -= 1;
-      }
-      Ok(made_delete)
+    let conn = context.conn()?;
+    let deleted = conn
+      .transaction::<Option<Episode>, diesel::result::Error, _>(|| delete_episode_row(id, &conn))?;
+    Ok(deleted.is_some())
+  }
+
+  pub fn update_episode(updater: EpisodeUpdater, context: &Ctx) -> FieldResult<Episode> {
+    check_air_date(&updater.air_date)?;
+    let conn = context.conn()?;
+    Ok(updater.save_changes(&*conn)?)
+  }
+
+  /// Creates every episode in a single transaction
+  pub fn create_episodes(
+    creators: Vec<EpisodeCreator>,
+    mode: Option<BulkMode>,
+    context: &Ctx,
+  ) -> FieldResult<BulkResult<Episode>> {
+    run_bulk(
+      context,
+      mode.unwrap_or_default(),
+      creators,
+      |creator, conn| {
+        check_air_date(&creator.air_date).map_err(ItemError::Invalid)?;
+        Ok(
+          diesel::insert_into(episode::table)
+            .values(creator)
+            .get_result(conn)?,
+        )
+      },
+    )
+  }
+
+  /// Updates every episode in a single transaction
+  pub fn update_episodes(
+    updaters: Vec<EpisodeUpdater>,
+    mode: Option<BulkMode>,
+    context: &Ctx,
+  ) -> FieldResult<BulkResult<Episode>> {
+    run_bulk(
+      context,
+      mode.unwrap_or_default(),
+      updaters,
+      |updater, conn| {
+        check_air_date(&updater.air_date).map_err(ItemError::Invalid)?;
+        Ok(updater.save_changes(conn)?)
+      },
+    )
+  }
+
+  /// Deletes every episode in a single transaction; a missing id is an
+  /// error of its item
+  pub fn delete_episodes(
+    ids: Vec<i32>,
+    mode: Option<BulkMode>,
+    context: &Ctx,
+  ) -> FieldResult<BulkResult<Episode>> {
+    run_bulk(context, mode.unwrap_or_default(), ids, |id, conn| {
+      Ok(delete_episode_row(id, conn)?.ok_or(diesel::result::Error::NotFound)?)
     })
   }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::is_air_date;
 
-  pub fn update_episode(updater: EpisodeUpdater) -> FieldResult<Episode> {
-    let conn = establish_connection();
-    Ok(updater.save_changes(&conn)?)
+  #[test]
+  fn accepts_existing_days_only() {
+    assert!(is_air_date("December 2, 2013"));
+    assert!(is_air_date("February 29, 2016"));
+    assert!(is_air_date("May 31, 2020"));
+    assert!(!is_air_date("February 29, 2015"));
+    assert!(!is_air_date("April 31, 2014"));
+    assert!(!is_air_date("December 0, 2013"));
+    assert!(!is_air_date("December 02, 2013"));
+    assert!(!is_air_date("Dec 2, 2013"));
+    assert!(!is_air_date("december 2, 2013"));
+    assert!(!is_air_date("December 2 2013"));
+    assert!(!is_air_date("December  2, 2013"));
+    assert!(!is_air_date("December 2, 13"));
+    assert!(!is_air_date("2013-12-02"));
+    assert!(!is_air_date(""));
   }
 }
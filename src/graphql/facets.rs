@@ -0,0 +1,178 @@
+use crate::graphql::{
+  character_model::{CharacterGender, CharacterStatus},
+  filters::{filter_characters, CharacterFilter},
+  location_model::Location,
+  Ctx,
+};
+use crate::schema::character;
+use diesel::{dsl::sql, expression::SqlLiteral, prelude::*, sql_types::BigInt};
+use juniper::{DefaultScalarValue, FieldResult};
+use std::convert::TryFrom;
+
+#[derive(juniper::GraphQLObject)]
+pub struct FacetValue {
+  value: Option<String>,
+  count: i32,
+}
+
+#[derive(juniper::GraphQLObject)]
+pub struct StatusFacetValue {
+  value: CharacterStatus,
+  count: i32,
+}
+
+#[derive(juniper::GraphQLObject)]
+pub struct GenderFacetValue {
+  value: CharacterGender,
+  count: i32,
+}
+
+pub struct LocationFacetValue {
+  location_id: Option<i32>,
+  count: i32,
+}
+
+#[juniper::object(Context = Ctx,)]
+impl LocationFacetValue {
+  fn location_id(&self) -> Option<i32> {
+    self.location_id
+  }
+  fn count(&self) -> i32 {
+    self.count
+  }
+  fn location(&self, context: &Ctx) -> FieldResult<Option<Location>> {
+    match self.location_id {
+      Some(id) => {
+        let db_conn = context.conn()?;
+        Ok(context.loaders.location(id, &db_conn)?)
+      }
+      None => Ok(None),
+    }
+  }
+}
+
+/// Distinct values of each character field with their counts. Each facet
+/// is counted under the filter without its own constraint, so selecting a
+/// status does not hide the other statuses.
+#[derive(juniper::GraphQLObject)]
+#[graphql(Context = Ctx, Scalar = DefaultScalarValue)]
+pub struct CharacterFacets {
+  status: Vec<StatusFacetValue>,
+  species: Vec<FacetValue>,
+  gender: Vec<GenderFacetValue>,
+  #[graphql(name = "type")]
+  type_: Vec<FacetValue>,
+  origin: Vec<LocationFacetValue>,
+  location: Vec<LocationFacetValue>,
+}
+
+/// The `(value, count)` rows of a facet as `facet(value, count)`.
+fn facet_values<V, T, F>(rows: Vec<(V, i64)>, facet: F) -> FieldResult<Vec<T>>
+where
+  F: Fn(V, i32) -> T,
+{
+  rows
+    .into_iter()
+    .map(|(value, count)| match i32::try_from(count) {
+      Ok(count) => Ok(facet(value, count)),
+      Err(_) => Err(format!("{} rows do not fit in an Int", count).into()),
+    })
+    .collect()
+}
+
+/// `count(*)` as a literal, since diesel 1.4 rejects `count_star()` next
+/// to the grouped column.
+fn row_count() -> SqlLiteral<BigInt> {
+  sql("count(*)")
+}
+
+pub fn character_facets(filter: &CharacterFilter, context: &Ctx) -> FieldResult<CharacterFacets> {
+  let conn = context.conn()?;
+  let status: Vec<(CharacterStatus, i64)> = {
+    let filter = CharacterFilter {
+      status: None,
+      ..filter.clone()
+    };
+    character::table
+      .filter(filter_characters(&filter))
+      .group_by(character::status)
+      .select((character::status, row_count()))
+      .order((row_count().desc(), character::status))
+      .load(&conn)?
+  };
+  let species: Vec<(String, i64)> = {
+    let filter = CharacterFilter {
+      species: None,
+      ..filter.clone()
+    };
+    character::table
+      .filter(filter_characters(&filter))
+      .group_by(character::species)
+      .select((character::species, row_count()))
+      .order((row_count().desc(), character::species))
+      .load(&conn)?
+  };
+  let gender: Vec<(CharacterGender, i64)> = {
+    let filter = CharacterFilter {
+      gender: None,
+      ..filter.clone()
+    };
+    character::table
+      .filter(filter_characters(&filter))
+      .group_by(character::gender)
+      .select((character::gender, row_count()))
+      .order((row_count().desc(), character::gender))
+      .load(&conn)?
+  };
+  // There is no `type` constraint in `CharacterFilter` to exclude.
+  let type_: Vec<(Option<String>, i64)> = character::table
+    .filter(filter_characters(filter))
+    .group_by(character::type_)
+    .select((character::type_, row_count()))
+    .order((row_count().desc(), character::type_))
+    .load(&conn)?;
+  let origin: Vec<(Option<i32>, i64)> = {
+    let filter = CharacterFilter {
+      origin_id: None,
+      ..filter.clone()
+    };
+    character::table
+      .filter(filter_characters(&filter))
+      .group_by(character::origin_id)
+      .select((character::origin_id, row_count()))
+      .order((row_count().desc(), character::origin_id))
+      .load(&conn)?
+  };
+  let location: Vec<(Option<i32>, i64)> = {
+    let filter = CharacterFilter {
+      location_id: None,
+      ..filter.clone()
+    };
+    character::table
+      .filter(filter_characters(&filter))
+      .group_by(character::location_id)
+      .select((character::location_id, row_count()))
+      .order((row_count().desc(), character::location_id))
+      .load(&conn)?
+  };
+
+  context.loaders.register_location_ids(
+    origin
+      .iter()
+      .chain(location.iter())
+      .filter_map(|(id, _)| *id),
+  );
+
+  let location_facet = |location_id, count| LocationFacetValue { location_id, count };
+  Ok(CharacterFacets {
+    status: facet_values(status, |value, count| StatusFacetValue { value, count })?,
+    species: facet_values(species, |value, count| FacetValue {
+      value: Some(value),
+      count,
+    })?,
+    gender: facet_values(gender, |value, count| GenderFacetValue { value, count })?,
+    type_: facet_values(type_, |value, count| FacetValue { value, count })?,
+    origin: facet_values(origin, location_facet)?,
+    location: facet_values(location, location_facet)?,
+  })
+}
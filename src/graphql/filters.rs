@@ -0,0 +1,302 @@
+use crate::full_text_search::dsl::*;
+use crate::graphql::{
+  character_model::{CharacterGender, CharacterStatus},
+  episode_model::{to_date, AIR_DATE_FORMAT},
+};
+use crate::schema::{character, character_episode, episode, location};
+use diesel::{dsl::not, pg::Pg, prelude::*, sql_types::Bool};
+use juniper::FieldResult;
+
+/// `to_date` pattern for the `airDateFrom`/`airDateTo` filter arguments.
+const FILTER_DATE_FORMAT: &str = "YYYY-MM-DD";
+
+/// `value` as a LIKE/ILIKE pattern that only matches itself.
+pub fn exact_pattern(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace('%', "\\%")
+    .replace('_', "\\_")
+}
+
+/// `%value%` for LIKE/ILIKE, with the pattern characters in `value` escaped.
+pub fn contains_pattern(value: &str) -> String {
+  format!("%{}%", exact_pattern(value))
+}
+
+// ######### CHARACTER ###############
+
+#[derive(juniper::GraphQLInputObject, Clone, Default)]
+pub struct CharacterFilter {
+  pub search_text: Option<String>,
+  pub status: Option<Vec<CharacterStatus>>,
+  pub species: Option<Vec<String>>,
+  pub gender: Option<Vec<CharacterGender>>,
+  pub origin_id: Option<Vec<i32>>,
+  pub location_id: Option<Vec<i32>>,
+}
+
+type FilterCharacterExpr<'a> =
+  Box<dyn BoxableExpression<character::table, Pg, SqlType = Bool> + 'a>;
+
+pub fn filter_characters<'a>(filter: &'a CharacterFilter) -> FilterCharacterExpr<'a> {
+  let mut filters: Vec<FilterCharacterExpr> = vec![];
+
+  let always_true = Box::new(character::id.eq(character::id));
+
+  if let Some(search_text) = &filter.search_text {
+    filters.push(Box::new(
+      character::search_text.matches(plainto_tsquery(search_text)),
+    ));
+  }
+  if let Some(status) = &filter.status {
+    filters.push(Box::new(character::status.eq_any(status)));
+  }
+  if let Some(species) = &filter.species {
+    filters.push(Box::new(character::species.eq_any(species)));
+  }
+  if let Some(gender) = &filter.gender {
+    filters.push(Box::new(character::gender.eq_any(gender)));
+  }
+  if let Some(origin_id) = &filter.origin_id {
+    filters.push(Box::new(character::origin_id.eq_any(origin_id)));
+  }
+  if let Some(location_id) = &filter.location_id {
+    filters.push(Box::new(character::location_id.eq_any(location_id)));
+  }
+  filters
+    .into_iter()
+    .fold(always_true, |query, curr| Box::new(query.and(curr)))
+}
+
+// ######### EPISODE ###############
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct EpisodeFilter {
+  /// Case insensitive substring of the episode name
+  name: Option<String>,
+  /// Season numbers, taken from the `SxxEyy` episode code
+  season: Option<Vec<i32>>,
+  /// Inclusive lower bound on the episode code, e.g. "S02E01"
+  code_from: Option<String>,
+  /// Inclusive upper bound on the episode code, e.g. "S02E10"
+  code_to: Option<String>,
+  /// Inclusive lower bound on the air date, as YYYY-MM-DD
+  air_date_from: Option<String>,
+  /// Inclusive upper bound on the air date, as YYYY-MM-DD
+  air_date_to: Option<String>,
+  /// Episodes featuring at least one of these characters
+  character_id: Option<Vec<i32>>,
+}
+
+impl EpisodeFilter {
+  /// Rejects air date bounds that are not real YYYY-MM-DD dates, which
+  /// `to_date` would otherwise fail on or silently roll over.
+  pub fn validate(&self) -> FieldResult<()> {
+    for (argument, value) in &[
+      ("airDateFrom", &self.air_date_from),
+      ("airDateTo", &self.air_date_to),
+    ] {
+      if let Some(value) = value {
+        if !is_date(value) {
+          return Err(
+            format!(
+              "filter.{} must be a date as YYYY-MM-DD, got {:?}",
+              argument, value
+            )
+            .into(),
+          );
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Whether `value` is an existing day written as YYYY-MM-DD.
+fn is_date(value: &str) -> bool {
+  let parts: Vec<&str> = value.split('-').collect();
+  let (year, month, day) = match parts.as_slice() {
+    [year, month, day]
+      if year.len() == 4
+        && month.len() == 2
+        && day.len() == 2
+        && parts
+          .iter()
+          .all(|part| part.bytes().all(|b| b.is_ascii_digit())) =>
+    {
+      // Four and two ASCII digits always parse.
+      (
+        year.parse::<u32>().unwrap_or_default(),
+        month.parse::<u32>().unwrap_or_default(),
+        day.parse::<u32>().unwrap_or_default(),
+      )
+    }
+    _ => return false,
+  };
+  is_day(year, month, day)
+}
+
+/// Whether `day` exists in `month` (1 to 12) of `year`.
+pub fn is_day(year: u32, month: u32, day: u32) -> bool {
+  let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+  let days_in_month = match month {
+    1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+    4 | 6 | 9 | 11 => 30,
+    2 if leap => 29,
+    2 => 28,
+    _ => return false,
+  };
+  day >= 1 && day <= days_in_month
+}
+
+type FilterEpisodeExpr<'a> = Box<dyn BoxableExpression<episode::table, Pg, SqlType = Bool> + 'a>;
+
+pub fn filter_episodes<'a>(filter: &'a EpisodeFilter) -> FilterEpisodeExpr<'a> {
+  let mut filters: Vec<FilterEpisodeExpr> = vec![];
+
+  let always_true = Box::new(episode::id.eq(episode::id));
+
+  if let Some(name) = &filter.name {
+    filters.push(Box::new(episode::name.ilike(contains_pattern(name))));
+  }
+  if let Some(seasons) = &filter.season {
+    let always_false: FilterEpisodeExpr = Box::new(episode::id.ne(episode::id));
+    filters.push(seasons.iter().fold(always_false, |query, season| {
+      Box::new(query.or(episode::code.like(format!("S{:02}E%", season))))
+    }));
+  }
+  if let Some(code_from) = &filter.code_from {
+    filters.push(Box::new(episode::code.ge(code_from)));
+  }
+  if let Some(code_to) = &filter.code_to {
+    filters.push(Box::new(episode::code.le(code_to)));
+  }
+  if let Some(air_date_from) = &filter.air_date_from {
+    filters.push(Box::new(
+      to_date(episode::air_date, AIR_DATE_FORMAT).ge(to_date(air_date_from, FILTER_DATE_FORMAT)),
+    ));
+  }
+  if let Some(air_date_to) = &filter.air_date_to {
+    filters.push(Box::new(
+      to_date(episode::air_date, AIR_DATE_FORMAT).le(to_date(air_date_to, FILTER_DATE_FORMAT)),
+    ));
+  }
+  if let Some(character_id) = &filter.character_id {
+    filters.push(Box::new(
+      episode::id.eq_any(
+        character_episode::table
+          .select(character_episode::episode_id)
+          .filter(character_episode::character_id.eq_any(character_id)),
+      ),
+    ));
+  }
+  filters
+    .into_iter()
+    .fold(always_true, |query, curr| Box::new(query.and(curr)))
+}
+
+// ######### LOCATION ###############
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct LocationFilter {
+  /// Case insensitive substring of the location name
+  name: Option<String>,
+  #[graphql(name = "type")]
+  type_: Option<Vec<String>>,
+  dimension: Option<Vec<String>>,
+  /// Whether some character currently lives in the location
+  has_residents: Option<bool>,
+}
+
+type FilterLocationExpr<'a> = Box<dyn BoxableExpression<location::table, Pg, SqlType = Bool> + 'a>;
+
+pub fn filter_locations<'a>(filter: &'a LocationFilter) -> FilterLocationExpr<'a> {
+  let mut filters: Vec<FilterLocationExpr> = vec![];
+
+  let always_true = Box::new(location::id.eq(location::id));
+
+  if let Some(name) = &filter.name {
+    filters.push(Box::new(location::name.ilike(contains_pattern(name))));
+  }
+  if let Some(type_) = &filter.type_ {
+    filters.push(Box::new(location::type_.eq_any(type_)));
+  }
+  if let Some(dimension) = &filter.dimension {
+    filters.push(Box::new(location::dimension.eq_any(dimension)));
+  }
+  if let Some(has_residents) = filter.has_residents {
+    let with_residents = location::id.nullable().eq_any(
+      character::table
+        .select(character::location_id)
+        .filter(character::location_id.is_not_null()),
+    );
+    if has_residents {
+      filters.push(Box::new(with_residents));
+    } else {
+      filters.push(Box::new(not(with_residents)));
+    }
+  }
+  filters
+    .into_iter()
+    .fold(always_true, |query, curr| Box::new(query.and(curr)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{filter_characters, is_date, CharacterFilter};
+  use crate::schema::character;
+  use diesel::{debug_query, pg::Pg, prelude::*};
+
+  /// The SQL and the binds of the query filtering the characters by
+  /// `search_text`.
+  fn search_query(search_text: &str) -> (String, String) {
+    let filter = CharacterFilter {
+      search_text: Some(search_text.to_string()),
+      status: None,
+      species: None,
+      gender: None,
+      origin_id: None,
+      location_id: None,
+    };
+    let query = character::table
+      .select(character::id)
+      .filter(filter_characters(&filter));
+    let debug = debug_query::<Pg, _>(&query).to_string();
+    let (sql, binds) = debug.split_at(debug.find(" -- binds: ").unwrap());
+    (sql.to_string(), binds.to_string())
+  }
+
+  #[test]
+  fn search_text_is_bound() {
+    let (plain_sql, _) = search_query("rick");
+    assert!(plain_sql.ends_with("plainto_tsquery($1)"), "{}", plain_sql);
+    for search_text in &[
+      "'); DROP TABLE character; --",
+      "Rick's \"portal",
+      "'''",
+      "\"",
+      "& | ! : *",
+      "rick & !morty | :* (",
+    ] {
+      let (sql, binds) = search_query(search_text);
+      assert_eq!(sql, plain_sql);
+      assert_eq!(binds, format!(" -- binds: [{:?}]", search_text));
+    }
+  }
+
+  #[test]
+  fn accepts_existing_days_only() {
+    assert!(is_date("2013-12-02"));
+    assert!(is_date("2016-02-29"));
+    assert!(is_date("2000-02-29"));
+    assert!(!is_date("1900-02-29"));
+    assert!(!is_date("2013-02-30"));
+    assert!(!is_date("2013-13-01"));
+    assert!(!is_date("2013-00-10"));
+    assert!(!is_date("2013-1-2"));
+    assert!(!is_date("December 2, 2013"));
+    assert!(!is_date("2013-12-02'; --"));
+    assert!(!is_date("+013-12-02"));
+    assert!(!is_date(""));
+  }
+}
@@ -0,0 +1,167 @@
+//! GraphQL requests over HTTP, alone or in a batch like juniper_rocket
+//! reads them. The introspection they run is completed with the
+//! subscription schema, which the `RootNode` of juniper 0.14 cannot hold.
+
+use crate::graphql::{subscriptions, Ctx, GraphqlSchema};
+use juniper::{
+  http,
+  parser::{Lexer, Token},
+  InputValue,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Deserialize)]
+pub struct HttpRequest {
+  query: String,
+  #[serde(rename = "operationName")]
+  operation_name: Option<String>,
+  variables: Option<InputValue>,
+}
+
+/// The body of `POST /graphql`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum BatchRequest {
+  Single(HttpRequest),
+  Batch(Vec<HttpRequest>),
+}
+
+impl HttpRequest {
+  pub fn new(
+    query: String,
+    operation_name: Option<String>,
+    variables: Option<InputValue>,
+  ) -> HttpRequest {
+    HttpRequest {
+      query,
+      operation_name,
+      variables,
+    }
+  }
+
+  /// The JSON response, and whether the request could run at all rather
+  /// than being rejected as a whole (HTTP 400).
+  pub fn execute(&self, schema: &GraphqlSchema, context: &Ctx) -> (bool, Value) {
+    let request = http::GraphQLRequest::new(
+      self.query.clone(),
+      self.operation_name.clone(),
+      self.variables.clone(),
+    );
+    let response = request.execute(schema, context);
+    let ok = response.is_ok();
+    let mut response = serde_json::to_value(&response).expect("Response is not valid JSON");
+    if let (true, Some(query)) = (ok, self.subscription_introspection()) {
+      let request =
+        http::GraphQLRequest::new(query, self.operation_name.clone(), self.variables.clone());
+      let subscription_schema = subscriptions::subscription_root(None);
+      let answer = request.execute(&subscription_schema, context);
+      let answer = serde_json::to_value(&answer).expect("Response is not valid JSON");
+      // Documents that also select other fields fail against the
+      // subscription schema and are answered as they are.
+      if let (Some(data), None) = (response.get_mut("data"), answer.get("errors")) {
+        subscriptions::merge_subscription_introspection(data, &answer["data"]);
+      }
+    }
+    (ok, response)
+  }
+
+  /// The document to run against the subscription schema when this one
+  /// selects `__schema` or `__type`, names only the introspection fields
+  /// can have. Strings and comments do not count. The subscription schema
+  /// has its root as query type, so `subscriptionType` asks for `queryType`
+  /// under the same response key.
+  fn subscription_introspection(&self) -> Option<String> {
+    let tokens: Vec<_> = Lexer::new(&self.query).map_while(Result::ok).collect();
+    let introspects = tokens.iter().any(|token| match token.item {
+      Token::Name(name) => name == "__schema" || name == "__type",
+      _ => false,
+    });
+    if !introspects {
+      return None;
+    }
+    // The lexer counts characters, not bytes.
+    let offsets: Vec<usize> = self
+      .query
+      .char_indices()
+      .map(|(offset, _)| offset)
+      .chain(Some(self.query.len()))
+      .collect();
+    let mut query = String::new();
+    let mut copied = 0;
+    for (i, token) in tokens.iter().enumerate() {
+      let before = i.checked_sub(1).map(|i| tokens[i].item);
+      let after = tokens.get(i + 1).map(|token| token.item);
+      // Aliases, variables and fragment spreads of that name stay.
+      if token.item != Token::Name("subscriptionType")
+        || after == Some(Token::Colon)
+        || before == Some(Token::Dollar)
+        || before == Some(Token::Ellipsis)
+      {
+        continue;
+      }
+      let start = offsets[token.start.index()];
+      query.push_str(&self.query[copied..start]);
+      if before != Some(Token::Colon) {
+        query.push_str("subscriptionType: ");
+      }
+      query.push_str("queryType");
+      copied = offsets[token.end.index()];
+    }
+    query.push_str(&self.query[copied..]);
+    Some(query)
+  }
+}
+
+impl BatchRequest {
+  /// Like `HttpRequest::execute`, a batch being answered in the order of
+  /// its requests and rejected when any of them is.
+  pub fn execute(&self, schema: &GraphqlSchema, context: &Ctx) -> (bool, Value) {
+    match self {
+      BatchRequest::Single(request) => request.execute(schema, context),
+      BatchRequest::Batch(requests) => {
+        let (oks, responses): (Vec<bool>, Vec<Value>) = requests
+          .iter()
+          .map(|request| request.execute(schema, context))
+          .unzip();
+        (oks.into_iter().all(|ok| ok), Value::Array(responses))
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn subscription_introspection(query: &str) -> Option<String> {
+    HttpRequest::new(query.to_string(), None, None).subscription_introspection()
+  }
+
+  #[test]
+  fn names_in_strings_and_comments_are_not_introspection() {
+    assert_eq!(
+      subscription_introspection(
+        "query ($name: String = \"__schema\") { character(id: 1) { name } } # __type"
+      ),
+      None
+    );
+  }
+
+  #[test]
+  fn subscription_type_asks_for_the_query_type_under_its_key() {
+    assert_eq!(
+      subscription_introspection(
+        "{ __schema { subscriptionType { name } root: subscriptionType { name } } }"
+      ),
+      Some(
+        "{ __schema { subscriptionType: queryType { name } root: queryType { name } } }"
+          .to_string()
+      )
+    );
+    assert_eq!(
+      subscription_introspection("{ __type(name: \"Ü\") { name } ...subscriptionType }"),
+      Some("{ __type(name: \"Ü\") { name } ...subscriptionType }".to_string())
+    );
+  }
+}
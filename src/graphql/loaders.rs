@@ -0,0 +1,191 @@
+use crate::graphql::{
+  character_model::{Character, CHARACTER_COLUMNS},
+  episode_model::Episode,
+  location_model::Location,
+};
+use crate::schema::{character, character_episode, episode, location};
+use diesel::{pg::PgConnection, prelude::*, QueryResult};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::Mutex;
+
+/// Request scoped cache for one relation.
+///
+/// Resolvers that produce a list register the keys their children will ask
+/// for. The first child that misses the cache fetches every registered key
+/// in one query, so each relation costs one query per nesting level.
+pub struct Loader<K, V> {
+  pending: Mutex<HashSet<K>>,
+  cache: Mutex<HashMap<K, Option<V>>>,
+}
+
+impl<K: Eq + Hash + Copy, V: Clone> Loader<K, V> {
+  pub fn new() -> Self {
+    Loader {
+      pending: Mutex::new(HashSet::new()),
+      cache: Mutex::new(HashMap::new()),
+    }
+  }
+
+  pub fn register<I: IntoIterator<Item = K>>(&self, keys: I) {
+    let cache = self.cache.lock().unwrap();
+    let mut pending = self.pending.lock().unwrap();
+    pending.extend(keys.into_iter().filter(|k| !cache.contains_key(k)));
+  }
+
+  pub fn load<F>(&self, key: K, fetch: F) -> QueryResult<Option<V>>
+  where
+    F: FnOnce(&[K]) -> QueryResult<HashMap<K, V>>,
+  {
+    if let Some(value) = self.cache.lock().unwrap().get(&key) {
+      return Ok(value.clone());
+    }
+    let keys: Vec<K> = {
+      let mut pending = self.pending.lock().unwrap();
+      pending.insert(key);
+      pending.drain().collect()
+    };
+    let mut found = fetch(&keys)?;
+
+    let mut cache = self.cache.lock().unwrap();
+    for k in keys {
+      cache.insert(k, found.remove(&k));
+    }
+    Ok(cache[&key].clone())
+  }
+}
+
+fn group_by_key<V>(rows: Vec<(i32, V)>) -> HashMap<i32, Vec<V>> {
+  let mut map: HashMap<i32, Vec<V>> = HashMap::new();
+  for (key, value) in rows {
+    map.entry(key).or_insert_with(Vec::new).push(value);
+  }
+  map
+}
+
+pub struct Loaders {
+  location: Loader<i32, Location>,
+  episodes_by_character: Loader<i32, Vec<Episode>>,
+  characters_by_episode: Loader<i32, Vec<Character>>,
+  characters_by_origin: Loader<i32, Vec<Character>>,
+  characters_by_location: Loader<i32, Vec<Character>>,
+}
+
+impl Loaders {
+  pub fn new() -> Loaders {
+    Loaders {
+      location: Loader::new(),
+      episodes_by_character: Loader::new(),
+      characters_by_episode: Loader::new(),
+      characters_by_origin: Loader::new(),
+      characters_by_location: Loader::new(),
+    }
+  }
+
+  pub fn register_characters(&self, characters: &[Character]) {
+    self.episodes_by_character.register(characters.iter().map(|c| c.id));
+    self.location.register(
+      characters
+        .iter()
+        .flat_map(|c| c.origin_id.into_iter().chain(c.location_id)),
+    );
+  }
+
+  pub fn register_location_ids<I: IntoIterator<Item = i32>>(&self, ids: I) {
+    self.location.register(ids);
+  }
+
+  pub fn register_episodes(&self, episodes: &[Episode]) {
+    self.characters_by_episode.register(episodes.iter().map(|e| e.id));
+  }
+
+  pub fn register_locations(&self, locations: &[Location]) {
+    self.characters_by_origin.register(locations.iter().map(|l| l.id));
+    self.characters_by_location.register(locations.iter().map(|l| l.id));
+  }
+
+  pub fn location(&self, id: i32, conn: &PgConnection) -> QueryResult<Option<Location>> {
+    self.location.load(id, |ids| {
+      let rows: Vec<Location> = location::table
+        .filter(location::id.eq_any(ids))
+        .load(conn)?;
+      self.register_locations(&rows);
+      Ok(rows.into_iter().map(|l| (l.id, l)).collect())
+    })
+  }
+
+  pub fn episodes_of_character(&self, id: i32, conn: &PgConnection) -> QueryResult<Vec<Episode>> {
+    let episodes = self.episodes_by_character.load(id, |ids| {
+      let rows: Vec<(i32, Episode)> = character_episode::table
+        .inner_join(episode::table)
+        .filter(character_episode::character_id.eq_any(ids))
+        .select((character_episode::character_id, episode::all_columns))
+        .order(episode::id)
+        .load(conn)?;
+      self
+        .characters_by_episode
+        .register(rows.iter().map(|(_, e)| e.id));
+      Ok(group_by_key(rows))
+    })?;
+    Ok(episodes.unwrap_or_default())
+  }
+
+  pub fn characters_of_episode(&self, id: i32, conn: &PgConnection) -> QueryResult<Vec<Character>> {
+    let characters = self.characters_by_episode.load(id, |ids| {
+      let rows: Vec<(i32, Character)> = character_episode::table
+        .inner_join(character::table)
+        .filter(character_episode::episode_id.eq_any(ids))
+        .select((character_episode::episode_id, CHARACTER_COLUMNS))
+        .order(character::id)
+        .load(conn)?;
+      let characters: Vec<Character> = rows.iter().map(|(_, c)| c.clone()).collect();
+      self.register_characters(&characters);
+      Ok(group_by_key(rows))
+    })?;
+    Ok(characters.unwrap_or_default())
+  }
+
+  pub fn characters_with_origin(
+    &self,
+    id: i32,
+    conn: &PgConnection,
+  ) -> QueryResult<Vec<Character>> {
+    let characters = self.characters_by_origin.load(id, |ids| {
+      let rows: Vec<Character> = character::table
+        .select(CHARACTER_COLUMNS)
+        .filter(character::origin_id.eq_any(ids))
+        .order(character::id)
+        .load(conn)?;
+      self.register_characters(&rows);
+      Ok(group_by_key(
+        rows
+          .into_iter()
+          .filter_map(|c| c.origin_id.map(|origin_id| (origin_id, c)))
+          .collect(),
+      ))
+    })?;
+    Ok(characters.unwrap_or_default())
+  }
+
+  pub fn characters_with_location(
+    &self,
+    id: i32,
+    conn: &PgConnection,
+  ) -> QueryResult<Vec<Character>> {
+    let characters = self.characters_by_location.load(id, |ids| {
+      let rows: Vec<Character> = character::table
+        .select(CHARACTER_COLUMNS)
+        .filter(character::location_id.eq_any(ids))
+        .order(character::id)
+        .load(conn)?;
+      self.register_characters(&rows);
+      Ok(group_by_key(
+        rows
+          .into_iter()
+          .filter_map(|c| c.location_id.map(|location_id| (location_id, c)))
+          .collect(),
+      ))
+    })?;
+    Ok(characters.unwrap_or_default())
+  }
+}
@@ -1,18 +1,22 @@
-use crate::db::establish_connection;
-use crate::graphql::{character_model::Character, Ctx};
+use crate::graphql::{
+  bulk::{run_bulk, BulkMode, BulkResult},
+  character_model::Character,
+  connection::{Connection, ConnectionArgs},
+  Ctx,
+};
 use crate::schema::{character, location};
-use diesel::{self, prelude::*, Insertable, Queryable};
+use diesel::{self, prelude::*, Connection as _, Insertable, Queryable};
 use juniper::FieldResult;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Queryable, Insertable)]
+#[derive(Clone, PartialEq, Serialize, Deserialize, Queryable, Insertable)]
 #[table_name = "location"]
 pub struct Location {
-  id: i32,
-  name: String,
+  pub id: i32,
+  pub name: String,
   #[serde(rename = "type")]
-  type_: String,
-  dimension: String,
+  pub type_: String,
+  pub dimension: String,
 }
 
 #[juniper::object(Context = Ctx,)]
@@ -31,21 +35,63 @@ impl Location {
     &self.dimension
   }
 
-  fn characters_with_origin(&self) -> FieldResult<Vec<Character>> {
-    let conn = establish_connection();
+  fn characters_with_origin(&self, context: &Ctx) -> FieldResult<Vec<Character>> {
+    let db_conn = context.conn()?;
+    Ok(context.loaders.characters_with_origin(self.id, &db_conn)?)
+  }
+
+  fn characters_with_location(&self, context: &Ctx) -> FieldResult<Vec<Character>> {
+    let db_conn = context.conn()?;
     Ok(
-      character::table
-        .filter(character::origin_id.eq(self.id))
-        .load(&conn)?,
+      context
+        .loaders
+        .characters_with_location(self.id, &db_conn)?,
     )
   }
 
-  fn characters_with_location(&self) -> FieldResult<Vec<Character>> {
-    let conn = establish_connection();
-    Ok(
-      character::table
-        .filter(character::location_id.eq(self.id))
-        .load(&conn)?,
+  fn characters_with_origin_connection(
+    &self,
+    first: Option<i32>,
+    after: Option<String>,
+    last: Option<i32>,
+    before: Option<String>,
+    context: &Ctx,
+  ) -> FieldResult<Connection<Character>> {
+    let db_conn = context.conn()?;
+    let items = context.loaders.characters_with_origin(self.id, &db_conn)?;
+    Connection::from_vec(
+      items,
+      ConnectionArgs {
+        first,
+        after,
+        last,
+        before,
+      },
+      context.pagination(),
+    )
+  }
+
+  fn characters_with_location_connection(
+    &self,
+    first: Option<i32>,
+    after: Option<String>,
+    last: Option<i32>,
+    before: Option<String>,
+    context: &Ctx,
+  ) -> FieldResult<Connection<Character>> {
+    let db_conn = context.conn()?;
+    let items = context
+      .loaders
+      .characters_with_location(self.id, &db_conn)?;
+    Connection::from_vec(
+      items,
+      ConnectionArgs {
+        first,
+        after,
+        last,
+        before,
+      },
+      context.pagination(),
     )
   }
 }
@@ -70,38 +116,88 @@ struct LocationCreator {
   dimension: String,
 }
 
+/// Deletes a location, unsetting the origin and location of the
+/// characters that referenced it.
+fn delete_location_row(id: i32, conn: &PgConnection) -> QueryResult<Option<Location>> {
+  diesel::update(character::table.filter(character::origin_id.eq(id)))
+    .set(character::origin_id.eq(None::<i32> {}))
+    .execute(conn)?;
+  diesel::update(character::table.filter(character::location_id.eq(id)))
+    .set(character::location_id.eq(None::<i32> {}))
+    .execute(conn)?;
+  diesel::delete(location::table.find(id))
+    .get_result(conn)
+    .optional()
+}
+
 pub struct LocationMutation;
 
 #[juniper::object(Context= Ctx,)]
 impl LocationMutation {
   pub fn create_location(creator: LocationCreator, context: &Ctx) -> FieldResult<Location> {
-    let db_conn = establish_connection();
-    let ans: Location = diesel::insert_into(location::table)
-      .values(creator)
-      .get_result(&db_conn)?;
-    *context.location.write().unwrap() += 1;
-    Ok(ans)
+    let db_conn = context.conn()?;
+    Ok(
+      diesel::insert_into(location::table)
+        .values(creator)
+        .get_result(&db_conn)?,
+    )
   }
 
   pub fn delete_location(id: i32, context: &Ctx) -> FieldResult<bool> {
-    let conn = establish_connection();
-    conn.transaction(|| {
-      diesel::update(character::table.filter(character::origin_id.eq(id)))
-        .set(character::origin_id.eq(None::<i32> {}))
-        .execute(&conn)?;
-      diesel::update(character::table.filter(character::location_id.eq(id)))
-        .set(character::location_id.eq(None::<i32> {}))
-        .execute(&conn)?;
-      let made_delete = diesel::delete(location::table.find(id)).execute(&conn)? == 1;
-      if made_delete {
-        *context.location.write().unwrap() -= 1;
-      }
-      Ok(made_delete)
-    })
+    let conn = context.conn()?;
+    let deleted =
+      conn.transaction::<_, diesel::result::Error, _>(|| delete_location_row(id, &conn))?;
+    Ok(deleted.is_some())
+  }
+
+  pub fn update_location(updater: LocationUpdater, context: &Ctx) -> FieldResult<Location> {
+    let conn = context.conn()?;
+    Ok(updater.save_changes(&*conn)?)
+  }
+
+  /// Creates every location in a single transaction
+  pub fn create_locations(
+    creators: Vec<LocationCreator>,
+    mode: Option<BulkMode>,
+    context: &Ctx,
+  ) -> FieldResult<BulkResult<Location>> {
+    run_bulk(
+      context,
+      mode.unwrap_or_default(),
+      creators,
+      |creator, conn| {
+        Ok(
+          diesel::insert_into(location::table)
+            .values(creator)
+            .get_result(conn)?,
+        )
+      },
+    )
+  }
+
+  /// Updates every location in a single transaction
+  pub fn update_locations(
+    updaters: Vec<LocationUpdater>,
+    mode: Option<BulkMode>,
+    context: &Ctx,
+  ) -> FieldResult<BulkResult<Location>> {
+    run_bulk(
+      context,
+      mode.unwrap_or_default(),
+      updaters,
+      |updater, conn| Ok(updater.save_changes(conn)?),
+    )
   }
 
-  pub fn update_location(updater: LocationUpdater) -> FieldResult<Location> {
-    let conn = establish_connection();
-    Ok(updater.save_changes(&conn)?)
+  /// Deletes every location in a single transaction, unsetting the
+  /// characters that referenced them; a missing id is an error of its item
+  pub fn delete_locations(
+    ids: Vec<i32>,
+    mode: Option<BulkMode>,
+    context: &Ctx,
+  ) -> FieldResult<BulkResult<Location>> {
+    run_bulk(context, mode.unwrap_or_default(), ids, |id, conn| {
+      Ok(delete_location_row(id, conn)?.ok_or(diesel::result::Error::NotFound)?)
+    })
   }
 }
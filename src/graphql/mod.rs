@@ -1,13 +1,15 @@
-use crate::db::{self, establish_connection};
+use crate::auth::{Identity, Role};
+use crate::config::{Config, PaginationConfig};
+use crate::db::{self, DanglingReference, DbConnection, DbPool, DirSource};
+use crate::export::{DataExport, ExportFormat};
+use crate::import::{self, ImportOptions, TableChanges};
+use crate::snapshot::{self, Snapshot};
+use crate::full_text_search::dsl::*;
 use crate::schema::{character, episode, location};
-use diesel::{
-  dsl::sql,
-  pg::Pg,
-  prelude::*,
-  sql_types::{Bool, Float},
-};
-use juniper::FieldResult;
-use std::sync::RwLock;
+use diesel::{dsl::count_star, pg::PgConnection, prelude::*, r2d2::PoolError};
+use juniper::{graphql_value, FieldError, FieldResult, IntoFieldError, ScalarValue};
+use std::str::FromStr;
+use std::sync::Arc;
 
 pub mod character_model;
 use character_model::*;
@@ -15,124 +17,441 @@ pub mod episode_model;
 use episode_model::*;
 pub mod location_model;
 use location_model::*;
+pub mod loaders;
+use loaders::Loaders;
+pub mod connection;
+use connection::{Connection, ConnectionArgs};
+pub mod ordering;
+use ordering::*;
+pub mod filters;
+use filters::*;
+pub mod facets;
+use facets::{character_facets, CharacterFacets};
+pub mod subscriptions;
+use subscriptions::EventBus;
+pub mod bulk;
+pub mod http;
+pub mod request;
+pub mod schema_diff;
+pub mod sdl;
 
 // ######### CONTEXT ###############
-pub struct Ctx {
-  character: RwLock<i32>,
-  location: RwLock<i32>,
-  episode: RwLock<i32>,
+
+/// State shared by every request, managed by Rocket as `Arc<AppState>`.
+pub struct AppState {
+  pool: DbPool,
+  config: Arc<Config>,
+  events: Arc<EventBus>,
 }
-impl juniper::Context for Ctx {}
-impl Ctx {
-  pub fn new(counts: db::DbCounts) -> Ctx {
-    Ctx {
-      character: RwLock::from(counts.character),
-      location: RwLock::from(counts.location),
-      episode: RwLock::from(counts.episode),
+impl AppState {
+  pub fn new(pool: DbPool, config: Arc<Config>) -> AppState {
+    AppState {
+      pool,
+      config,
+      events: Arc::new(EventBus::default()),
     }
   }
-}
 
-// ######### QUERIES ###############
+  /// Checks a connection out of the shared pool.
+  pub fn conn(&self) -> Result<DbConnection, PoolError> {
+    self.pool.get()
+  }
+
+  pub fn config(&self) -> &Config {
+    &self.config
+  }
 
-#[derive(juniper::GraphQLInputObject)]
-struct CharacterFilter {
-  search_text: Option<String>,
-  status: Option<Vec<String>>,
-  species: Option<Vec<String>>,
-  gender: Option<Vec<String>>,
-  origin_id: Option<Vec<i32>>,
-  location_id: Option<Vec<i32>>,
+  /// The bus the changes notified by the database are published to.
+  pub fn events(&self) -> Arc<EventBus> {
+    self.events.clone()
+  }
 }
 
-type FilterCharacterExpr<'a> =
-  Box<dyn BoxableExpression<character::table, Pg, SqlType = Bool> + 'a>;
+/// Context of a single GraphQL request, built by its request guard.
+pub struct Ctx {
+  app: Arc<AppState>,
+  identity: Option<Identity>,
+  request_id: String,
+  loaders: Loaders,
+}
+impl juniper::Context for Ctx {}
+impl Ctx {
+  pub fn new(app: Arc<AppState>, identity: Option<Identity>, request_id: String) -> Ctx {
+    Ctx {
+      app,
+      identity,
+      request_id,
+      loaders: Loaders::new(),
+    }
+  }
 
-fn filter_characters<'a>(filter: &'a CharacterFilter) -> FilterCharacterExpr<'a> {
-  let mut filters: Vec<FilterCharacterExpr> = vec![];
+  /// The caller of this request, `None` when anonymous.
+  pub fn identity(&self) -> Option<&Identity> {
+    self.identity.as_ref()
+  }
 
-  let always_true = Box::new(character::id.eq(character::id));
+  /// Fails unless the caller has at least `role`.
+  pub fn require(&self, role: Role) -> FieldResult<&Identity> {
+    match &self.identity {
+      None => Err(FieldError::new(
+        format!(
+          "Authentication required: this operation needs the {} role",
+          role
+        ),
+        graphql_value!({
+          "code": "UNAUTHENTICATED",
+          "requiredRole": (role.as_str()),
+        }),
+      )),
+      Some(identity) if identity.role < role => Err(FieldError::new(
+        format!(
+          "Forbidden: this operation needs the {} role, {} has {}",
+          role, identity.subject, identity.role
+        ),
+        graphql_value!({
+          "code": "FORBIDDEN",
+          "requiredRole": (role.as_str()),
+        }),
+      )),
+      Some(identity) => Ok(identity),
+    }
+  }
 
-  if let Some(status) = &filter.status {
-    filters.push(Box::new(character::status.eq_any(status)));
+  /// Identifies this request in logs and in the `X-Request-Id` header.
+  pub fn request_id(&self) -> &str {
+    &self.request_id
   }
-  if let Some(species) = &filter.species {
-    filters.push(Box::new(character::species.eq_any(species)));
+
+  pub fn app(&self) -> &AppState {
+    &self.app
   }
-  if let Some(gender) = &filter.gender {
-    filters.push(Box::new(character::gender.eq_any(gender)));
+
+  /// Checks a connection out of the shared pool.
+  pub fn conn(&self) -> Result<DbConnection, PoolError> {
+    self.app.conn()
   }
-  if let Some(origin_id) = &filter.origin_id {
-    filters.push(Box::new(character::origin_id.eq_any(origin_id)));
+
+  pub fn pagination(&self) -> &PaginationConfig {
+    &self.app.config.pagination
   }
-  if let Some(location_id) = &filter.location_id {
-    filters.push(Box::new(character::location_id.eq_any(location_id)));
+}
+
+// ######### ERRORS ###############
+
+impl<S: ScalarValue> IntoFieldError<S> for DanglingReference {
+  fn into_field_error(self) -> FieldError<S> {
+    FieldError::new(
+      &self,
+      graphql_value!({
+        "code": "DANGLING_REFERENCE",
+        "table": (self.table_name.as_str()),
+        "column": (self.column_name.as_str()),
+        "rowId": (self.row_id),
+        "missingId": (self.missing_id),
+      }),
+    )
   }
-  filters
-    .into_iter()
-    .fold(always_true, |query, curr| Box::new(query.and(curr)))
 }
 
+// ######### QUERIES ###############
+
 pub struct Query;
 #[juniper::object(
     Context = Ctx,
 )]
 impl Query {
-  fn characters(mut page: i32, context: &Ctx) -> FieldResult<ListResult<Character>> {
-    if page == -1 {
-      page = 1;
-    }
-    Ok(load_many(character::table, page, &context.character)?)
+  fn characters(
+    page: i32,
+    page_size: Option<i32>,
+    order_by: Option<Vec<CharacterOrder>>,
+    context: &Ctx,
+  ) -> FieldResult<ListResult<Character>> {
+    let query = order_characters(
+      character::table.select(CHARACTER_COLUMNS).into_boxed(),
+      &order_by.unwrap_or_default(),
+    );
+    let db_conn = context.conn()?;
+    let list = load_many(
+      query,
+      page,
+      page_size,
+      db::count_rows("character", &db_conn)?,
+      context.pagination(),
+      &db_conn,
+    )?;
+    context.loaders.register_characters(&list.results);
+    Ok(list)
   }
 
   fn characters_filtered(
     limit: i32,
     offset: i32,
     filter: CharacterFilter,
-  ) -> FieldResult<Vec<Character>> {
-    let query = character::table.filter(filter_characters(&filter));
-    match &filter.search_text {
-      Some(search_text) => {
-        let raw_filter = format!("\"search_text\" @@ plainto_tsquery('{}')", search_text);
-        let raw_order = format!(
-          "ts_rank(\"search_text\", plainto_tsquery('{}')) desc",
-          search_text
-        );
-        let query = query.filter(sql(&raw_filter)).order(sql::<
-          Box<dyn diesel::expression::Expression<SqlType = Float>>,
-        >(&raw_order));
-        let query = diesel::QueryDsl::limit(query, limit as i64);
-        Ok(diesel::QueryDsl::offset(query, offset as i64).load(&establish_connection())?)
+    context: &Ctx,
+  ) -> FieldResult<ListResult<Character>> {
+    let limit = check_page_size(Some(limit), context.pagination())?;
+    if offset < 0 {
+      return Err(format!("offset must not be negative, got {}", offset).into());
+    }
+    let db_conn = context.conn()?;
+    let item_count: i64 = character::table
+      .filter(filter_characters(&filter))
+      .select(count_star())
+      .get_result(&db_conn)?;
+
+    let mut query = character::table
+      .select(CHARACTER_COLUMNS)
+      .filter(filter_characters(&filter))
+      .into_boxed();
+    if let Some(search_text) = &filter.search_text {
+      query = query.order(ts_rank(character::search_text, plainto_tsquery(search_text)).desc());
+    }
+    // The id breaks the ties of the rank and orders the rows without a
+    // search, so that the pages do not overlap.
+    let query = query.then_order_by(character::id);
+    let query = diesel::QueryDsl::limit(query, limit as i64);
+    let results: Vec<Character> = diesel::QueryDsl::offset(query, offset as i64).load(&db_conn)?;
+    context.loaders.register_characters(&results);
+    Ok(ListResult {
+      info: InfoListResult::new(item_count as i32, i64::from(offset), limit),
+      results,
+    })
+  }
+
+  /// The snapshots `restoreSnapshot` can return to, newest first
+  fn snapshots(context: &Ctx) -> FieldResult<Vec<Snapshot>> {
+    context.require(Role::Admin)?;
+    let db_conn = context.conn()?;
+    Ok(snapshot::list_snapshots(&db_conn)?)
+  }
+
+  fn character_facets(
+    filter: Option<CharacterFilter>,
+    context: &Ctx,
+  ) -> FieldResult<CharacterFacets> {
+    character_facets(&filter.unwrap_or_default(), context)
+  }
+
+  fn character(id: i32, context: &Ctx) -> FieldResult<Character> {
+    let db_conn = context.conn()?;
+    Ok(
+      character::table
+        .find(id)
+        .select(CHARACTER_COLUMNS)
+        .first(&db_conn)?,
+    )
+  }
+
+  fn characters_connection(
+    first: Option<i32>,
+    after: Option<String>,
+    last: Option<i32>,
+    before: Option<String>,
+    context: &Ctx,
+  ) -> FieldResult<Connection<Character>> {
+    let args = ConnectionArgs {
+      first,
+      after,
+      last,
+      before,
+    };
+    let db_conn = context.conn()?;
+    let total_count = db::count_rows("character", &db_conn)?;
+    Connection::load(args, context.pagination(), total_count, |keyset| {
+      let mut query = character::table.select(CHARACTER_COLUMNS).into_boxed();
+      if let Some(after) = keyset.after {
+        query = query.filter(character::id.gt(after));
       }
-      None => {
-        let query = diesel::QueryDsl::limit(query, limit as i64);
-        Ok(diesel::QueryDsl::offset(query, offset as i64).load(&establish_connection())?)
+      if let Some(before) = keyset.before {
+        query = query.filter(character::id.lt(before));
       }
-    }
+      let query = if keyset.backwards {
+        query.order(character::id.desc())
+      } else {
+        query.order(character::id.asc())
+      };
+      let rows: Vec<Character> = diesel::QueryDsl::limit(query, keyset.limit + 1).load(&db_conn)?;
+      context.loaders.register_characters(&rows);
+      Ok(rows)
+    })
   }
 
-  fn character(id: i32) -> FieldResult<Character> {
-    let db_conn = establish_connection();
-    Ok(character::table.find(id).first(&db_conn)?)
+  fn episodes(
+    page: i32,
+    page_size: Option<i32>,
+    order_by: Option<Vec<EpisodeOrder>>,
+    context: &Ctx,
+  ) -> FieldResult<ListResult<Episode>> {
+    let query = order_episodes(episode::table.into_boxed(), &order_by.unwrap_or_default());
+    let db_conn = context.conn()?;
+    let list = load_many(
+      query,
+      page,
+      page_size,
+      db::count_rows("episode", &db_conn)?,
+      context.pagination(),
+      &db_conn,
+    )?;
+    context.loaders.register_episodes(&list.results);
+    Ok(list)
   }
 
-  fn episodes(page: i32, context: &Ctx) -> FieldResult<ListResult<Episode>> {
-    Ok(load_many(episode::table, page, &context.episode)?)
+  fn episodes_filtered(
+    page: i32,
+    page_size: Option<i32>,
+    order_by: Option<Vec<EpisodeOrder>>,
+    filter: EpisodeFilter,
+    context: &Ctx,
+  ) -> FieldResult<ListResult<Episode>> {
+    filter.validate()?;
+    let db_conn = context.conn()?;
+    let item_count: i64 = episode::table
+      .filter(filter_episodes(&filter))
+      .select(count_star())
+      .get_result(&db_conn)?;
+    let query = order_episodes(
+      episode::table.filter(filter_episodes(&filter)).into_boxed(),
+      &order_by.unwrap_or_default(),
+    );
+    let list = load_many(
+      query,
+      page,
+      page_size,
+      item_count as i32,
+      context.pagination(),
+      &db_conn,
+    )?;
+    context.loaders.register_episodes(&list.results);
+    Ok(list)
   }
 
-  fn episode(id: i32) -> FieldResult<Episode> {
-    let db_conn = establish_connection();
+  fn episode(id: i32, context: &Ctx) -> FieldResult<Episode> {
+    let db_conn = context.conn()?;
     Ok(episode::table.find(id).first(&db_conn)?)
   }
 
-  fn locations(page: i32, context: &Ctx) -> FieldResult<ListResult<Location>> {
-    Ok(load_many(location::table, page, &context.location)?)
+  fn episodes_connection(
+    first: Option<i32>,
+    after: Option<String>,
+    last: Option<i32>,
+    before: Option<String>,
+    context: &Ctx,
+  ) -> FieldResult<Connection<Episode>> {
+    let args = ConnectionArgs {
+      first,
+      after,
+      last,
+      before,
+    };
+    let db_conn = context.conn()?;
+    let total_count = db::count_rows("episode", &db_conn)?;
+    Connection::load(args, context.pagination(), total_count, |keyset| {
+      let mut query = episode::table.into_boxed();
+      if let Some(after) = keyset.after {
+        query = query.filter(episode::id.gt(after));
+      }
+      if let Some(before) = keyset.before {
+        query = query.filter(episode::id.lt(before));
+      }
+      let query = if keyset.backwards {
+        query.order(episode::id.desc())
+      } else {
+        query.order(episode::id.asc())
+      };
+      let rows: Vec<Episode> = diesel::QueryDsl::limit(query, keyset.limit + 1).load(&db_conn)?;
+      context.loaders.register_episodes(&rows);
+      Ok(rows)
+    })
+  }
+
+  fn locations(
+    page: i32,
+    page_size: Option<i32>,
+    order_by: Option<Vec<LocationOrder>>,
+    context: &Ctx,
+  ) -> FieldResult<ListResult<Location>> {
+    let query = order_locations(location::table.into_boxed(), &order_by.unwrap_or_default());
+    let db_conn = context.conn()?;
+    let list = load_many(
+      query,
+      page,
+      page_size,
+      db::count_rows("location", &db_conn)?,
+      context.pagination(),
+      &db_conn,
+    )?;
+    context.loaders.register_locations(&list.results);
+    Ok(list)
   }
 
-  fn location(id: i32) -> FieldResult<Location> {
-    let db_conn = establish_connection();
+  fn locations_filtered(
+    page: i32,
+    page_size: Option<i32>,
+    order_by: Option<Vec<LocationOrder>>,
+    filter: LocationFilter,
+    context: &Ctx,
+  ) -> FieldResult<ListResult<Location>> {
+    let db_conn = context.conn()?;
+    let item_count: i64 = location::table
+      .filter(filter_locations(&filter))
+      .select(count_star())
+      .get_result(&db_conn)?;
+    let query = order_locations(
+      location::table
+        .filter(filter_locations(&filter))
+        .into_boxed(),
+      &order_by.unwrap_or_default(),
+    );
+    let list = load_many(
+      query,
+      page,
+      page_size,
+      item_count as i32,
+      context.pagination(),
+      &db_conn,
+    )?;
+    context.loaders.register_locations(&list.results);
+    Ok(list)
+  }
+
+  fn location(id: i32, context: &Ctx) -> FieldResult<Location> {
+    let db_conn = context.conn()?;
     Ok(location::table.find(id).first(&db_conn)?)
   }
+
+  fn locations_connection(
+    first: Option<i32>,
+    after: Option<String>,
+    last: Option<i32>,
+    before: Option<String>,
+    context: &Ctx,
+  ) -> FieldResult<Connection<Location>> {
+    let args = ConnectionArgs {
+      first,
+      after,
+      last,
+      before,
+    };
+    let db_conn = context.conn()?;
+    let total_count = db::count_rows("location", &db_conn)?;
+    Connection::load(args, context.pagination(), total_count, |keyset| {
+      let mut query = location::table.into_boxed();
+      if let Some(after) = keyset.after {
+        query = query.filter(location::id.gt(after));
+      }
+      if let Some(before) = keyset.before {
+        query = query.filter(location::id.lt(before));
+      }
+      let query = if keyset.backwards {
+        query.order(location::id.desc())
+      } else {
+        query.order(location::id.asc())
+      };
+      let rows: Vec<Location> = diesel::QueryDsl::limit(query, keyset.limit + 1).load(&db_conn)?;
+      context.loaders.register_locations(&rows);
+      Ok(rows)
+    })
+  }
 }
 
 use diesel::dsl::{Limit, Offset};
@@ -144,8 +463,37 @@ use diesel::query_dsl::{
 #[derive(juniper::GraphQLObject)]
 struct InfoListResult {
   next_page: Option<i32>,
+  prev_page: Option<i32>,
   num_pages: i32,
   item_count: i32,
+  page_size: i32,
+}
+
+impl InfoListResult {
+  /// Page numbers are 1-based; an `offset` that is not a multiple of
+  /// `page_size` counts as being on the page that contains it.
+  ///
+  /// The arithmetic is done in i64, where `offset + page_size` cannot
+  /// overflow. The page numbers returned then fit in an i32: there is a
+  /// next page only when `offset + page_size` is below `item_count`.
+  fn new(item_count: i32, offset: i64, page_size: i32) -> InfoListResult {
+    let page = offset / i64::from(page_size) + 1;
+    InfoListResult {
+      next_page: if i64::from(item_count) > offset + i64::from(page_size) {
+        Some((page + 1) as i32)
+      } else {
+        None
+      },
+      prev_page: if page > 1 {
+        Some((page - 1) as i32)
+      } else {
+        None
+      },
+      num_pages: f64::ceil(item_count as f64 / (page_size as f64)) as i32,
+      item_count,
+      page_size,
+    }
+  }
 }
 
 struct ListResult<Model> {
@@ -181,42 +529,46 @@ impl ListResult<Episode> {
   }
 }
 
-const ITEMS_PER_PAGE: i32 = 30;
+fn check_page_size(page_size: Option<i32>, pagination: &PaginationConfig) -> FieldResult<i32> {
+  match page_size {
+    None => Ok(pagination.items_per_page),
+    Some(size) if size >= 1 && size <= pagination.max_page_size => Ok(size),
+    Some(size) => Err(
+      format!(
+        "pageSize must be between 1 and {}, got {}",
+        pagination.max_page_size, size
+      )
+      .into(),
+    ),
+  }
+}
 
 fn load_many<Model, Table>(
   table: Table,
   page_input: i32,
-  count: &RwLock<i32>,
+  page_size: Option<i32>,
+  item_count: i32,
+  pagination: &PaginationConfig,
+  db_conn: &PgConnection,
 ) -> FieldResult<ListResult<Model>>
 where
-  Table: OffsetDsl + LoadQuery<diesel::pg::PgConnection, Model>,
+  Table: OffsetDsl + LoadQuery<PgConnection, Model>,
   Offset<Table>: LimitDsl,
-  Limit<Offset<Table>>: LoadQuery<diesel::pg::PgConnection, Model>,
+  Limit<Offset<Table>>: LoadQuery<PgConnection, Model>,
 {
+  let page_size = check_page_size(page_size, pagination)?;
+  // Pages below 1, the `-1` older clients send included, read the first
+  // page: the whole table is never loaded at once.
   let page = std::cmp::max(page_input, 1);
-  let item_count = *count.read().unwrap();
-  let offset = ITEMS_PER_PAGE * (page - 1);
-  let num_pages = f64::ceil(item_count as f64 / (ITEMS_PER_PAGE as f64)) as i32;
-
-  let info = InfoListResult {
-    next_page: if item_count > offset + ITEMS_PER_PAGE {
-      Some(page + 1)
-    } else {
-      None
-    },
-    num_pages,
-    item_count: item_count,
-  };
+  // In i64, as a large `page` times `page_size` overflows an i32.
+  let offset = i64::from(page_size) * (i64::from(page) - 1);
+  let info = InfoListResult::new(item_count, offset, page_size);
 
-  let results = if page_input == -1 {
-    let db_conn = establish_connection();
-    table.load(&db_conn)?
-  } else if item_count > offset {
-    let db_conn = establish_connection();
+  let results = if i64::from(item_count) > offset {
     table
-      .offset(offset as i64)
-      .limit(ITEMS_PER_PAGE as i64)
-      .load::<Model>(&db_conn)?
+      .offset(offset)
+      .limit(page_size as i64)
+      .load::<Model>(db_conn)?
   } else {
     vec![]
   };
@@ -231,28 +583,77 @@ pub struct Mutation;
 )]
 impl Mutation {
   fn reset_db(context: &Ctx) -> FieldResult<bool> {
-    let db_conn = establish_connection();
-    db::reset_db(&db_conn)?;
-    db_conn.transaction::<(), diesel::result::Error, _>(|| {
-      let counts = db::get_all_counts(&db_conn)?;
-      *context.character.write().unwrap() = counts.character;
-      *context.episode.write().unwrap() = counts.episode;
-      *context.location.write().unwrap() = counts.location;
-      Ok(())
-    })?;
+    context.require(Role::Admin)?;
+    let db_conn = context.conn()?;
+    let mut source = DirSource(context.app.config.data_dir.clone());
+    db::reset_db(&db_conn, &mut source)?;
     Ok(true)
   }
 
-  fn character_mutation() -> CaracterMutation {
-    CaracterMutation
+  /// Upserts the seed files by id, keeping the rows edited since. With
+  /// `prune` the rows missing from the files are deleted. Only reports the
+  /// changes unless `apply` is true.
+  fn import_db(
+    prune: Option<bool>,
+    apply: Option<bool>,
+    context: &Ctx,
+  ) -> FieldResult<Vec<TableChanges>> {
+    context.require(Role::Admin)?;
+    let db_conn = context.conn()?;
+    let options = ImportOptions {
+      prune: prune.unwrap_or(false),
+      apply: apply.unwrap_or(false),
+    };
+    let mut source = DirSource(context.app.config.data_dir.clone());
+    Ok(import::import_db(&db_conn, &mut source, options)?.tables)
   }
 
-  fn episode_mutation() -> EpisodeMutation {
-    EpisodeMutation
+  /// Saves every row under `name`, which must not be taken yet
+  fn create_snapshot(name: String, context: &Ctx) -> FieldResult<Snapshot> {
+    context.require(Role::Admin)?;
+    let db_conn = context.conn()?;
+    Ok(snapshot::create_snapshot(&db_conn, &name)?)
   }
 
-  fn location_mutation() -> LocationMutation {
-    LocationMutation
+  /// Replaces every row with the rows saved under `name`
+  fn restore_snapshot(name: String, context: &Ctx) -> FieldResult<Snapshot> {
+    context.require(Role::Admin)?;
+    let db_conn = context.conn()?;
+    Ok(snapshot::restore_snapshot(&db_conn, &name)?)
+  }
+
+  fn delete_snapshot(name: String, context: &Ctx) -> FieldResult<bool> {
+    context.require(Role::Admin)?;
+    let db_conn = context.conn()?;
+    snapshot::delete_snapshot(&db_conn, &name)?;
+    Ok(true)
+  }
+
+  /// Writes every table to a new export directory, in the layout `resetDb`
+  /// reads. TSV unless `format` says otherwise.
+  fn export_db(format: Option<ExportFormat>, context: &Ctx) -> FieldResult<DataExport> {
+    context.require(Role::Admin)?;
+    let db_conn = context.conn()?;
+    Ok(DataExport::create(
+      &db_conn,
+      &context.app.config.export_dir,
+      format.unwrap_or(ExportFormat::Tsv),
+    )?)
+  }
+
+  fn character_mutation(context: &Ctx) -> FieldResult<CaracterMutation> {
+    context.require(Role::Editor)?;
+    Ok(CaracterMutation)
+  }
+
+  fn episode_mutation(context: &Ctx) -> FieldResult<EpisodeMutation> {
+    context.require(Role::Editor)?;
+    Ok(EpisodeMutation)
+  }
+
+  fn location_mutation(context: &Ctx) -> FieldResult<LocationMutation> {
+    context.require(Role::Editor)?;
+    Ok(LocationMutation)
   }
 
   // fn create_character(
@@ -278,6 +679,64 @@ pub fn create_schema() -> GraphqlSchema {
   GraphqlSchema::new(Query, Mutation)
 }
 
+/// The introspection result of the schema, as in `graphql_schema.json`,
+/// subscriptions included. Introspection never touches the database, so
+/// none is needed.
+pub fn introspect_schema() -> serde_json::Value {
+  let app = AppState::new(db::unconnected_pool(), Arc::new(Config::default()));
+  let ctx = Ctx::new(Arc::new(app), None, request::new_request_id());
+  let (result, _errors) = juniper::introspect(
+    &create_schema(),
+    &ctx,
+    juniper::IntrospectionFormat::default(),
+  )
+  .expect("The schema cannot introspect itself");
+  let (subscription_result, _errors) = juniper::introspect(
+    &subscriptions::subscription_root(None),
+    &ctx,
+    juniper::IntrospectionFormat::default(),
+  )
+  .expect("The subscription schema cannot introspect itself");
+  let mut result = serde_json::to_value(&result).expect("Introspection result is not valid JSON");
+  let mut subscription_result =
+    serde_json::to_value(&subscription_result).expect("Introspection result is not valid JSON");
+  let subscription_schema = &mut subscription_result["__schema"];
+  subscription_schema["subscriptionType"] = subscription_schema["queryType"].clone();
+  subscriptions::merge_subscription_introspection(&mut result, &subscription_result);
+  result
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SchemaFormat {
+  /// The introspection result, as in `graphql_schema.json`.
+  Json,
+  /// The GraphQL schema language.
+  Sdl,
+}
+
+impl FromStr for SchemaFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "json" => Ok(SchemaFormat::Json),
+      "sdl" | "graphql" => Ok(SchemaFormat::Sdl),
+      _ => Err(format!("Unknown schema format {}, use json or sdl", s)),
+    }
+  }
+}
+
+/// The schema printed as `format`, without a server or a database.
+pub fn print_schema(format: SchemaFormat) -> String {
+  let introspection = introspect_schema();
+  match format {
+    SchemaFormat::Json => {
+      serde_json::to_string_pretty(&introspection).expect("Introspection result is not valid JSON")
+    }
+    SchemaFormat::Sdl => sdl::print_schema(&introspection),
+  }
+}
+
 // impl Ctx {
 //   pub fn new(characters: Vec<Character>) -> Ctx {
 //     Ctx(Database {
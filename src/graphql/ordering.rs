@@ -0,0 +1,130 @@
+use crate::graphql::episode_model::{to_date, AIR_DATE_FORMAT};
+use crate::schema::{character, episode, location};
+use diesel::{
+  expression::{AppearsOnTable, Expression},
+  pg::Pg,
+  prelude::*,
+  query_builder::{BoxedSelectStatement, QueryFragment},
+};
+
+#[derive(juniper::GraphQLEnum, Clone, Copy, PartialEq)]
+pub enum OrderDirection {
+  Asc,
+  Desc,
+}
+
+#[derive(juniper::GraphQLEnum, Clone, Copy)]
+pub enum CharacterOrderField {
+  Id,
+  Name,
+  Status,
+  Species,
+  Gender,
+  Type,
+}
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct CharacterOrder {
+  field: CharacterOrderField,
+  direction: Option<OrderDirection>,
+}
+
+#[derive(juniper::GraphQLEnum, Clone, Copy)]
+pub enum EpisodeOrderField {
+  Id,
+  Name,
+  AirDate,
+  Code,
+}
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct EpisodeOrder {
+  field: EpisodeOrderField,
+  direction: Option<OrderDirection>,
+}
+
+#[derive(juniper::GraphQLEnum, Clone, Copy)]
+pub enum LocationOrderField {
+  Id,
+  Name,
+  Type,
+  Dimension,
+}
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct LocationOrder {
+  field: LocationOrderField,
+  direction: Option<OrderDirection>,
+}
+
+type Boxed<'a, ST, QS> = BoxedSelectStatement<'a, ST, QS, Pg>;
+
+fn then_order<'a, ST, QS, C>(
+  query: Boxed<'a, ST, QS>,
+  column: C,
+  direction: OrderDirection,
+) -> Boxed<'a, ST, QS>
+where
+  C: Expression + ExpressionMethods + QueryFragment<Pg> + AppearsOnTable<QS> + 'a,
+{
+  match direction {
+    OrderDirection::Asc => query.then_order_by(column.asc()),
+    OrderDirection::Desc => query.then_order_by(column.desc()),
+  }
+}
+
+/// Applies the requested keys in order and always finishes with `id` so
+/// pages are stable when the requested keys have ties.
+pub fn order_characters<'a, ST>(
+  mut query: Boxed<'a, ST, character::table>,
+  order_by: &[CharacterOrder],
+) -> Boxed<'a, ST, character::table> {
+  for order in order_by {
+    let direction = order.direction.unwrap_or(OrderDirection::Asc);
+    query = match order.field {
+      CharacterOrderField::Id => then_order(query, character::id, direction),
+      CharacterOrderField::Name => then_order(query, character::name, direction),
+      CharacterOrderField::Status => then_order(query, character::status, direction),
+      CharacterOrderField::Species => then_order(query, character::species, direction),
+      CharacterOrderField::Gender => then_order(query, character::gender, direction),
+      CharacterOrderField::Type => then_order(query, character::type_, direction),
+    };
+  }
+  query.then_order_by(character::id.asc())
+}
+
+pub fn order_episodes<'a, ST>(
+  mut query: Boxed<'a, ST, episode::table>,
+  order_by: &[EpisodeOrder],
+) -> Boxed<'a, ST, episode::table> {
+  for order in order_by {
+    let direction = order.direction.unwrap_or(OrderDirection::Asc);
+    query = match order.field {
+      EpisodeOrderField::Id => then_order(query, episode::id, direction),
+      EpisodeOrderField::Name => then_order(query, episode::name, direction),
+      EpisodeOrderField::AirDate => then_order(
+        query,
+        to_date(episode::air_date, AIR_DATE_FORMAT),
+        direction,
+      ),
+      EpisodeOrderField::Code => then_order(query, episode::code, direction),
+    };
+  }
+  query.then_order_by(episode::id.asc())
+}
+
+pub fn order_locations<'a, ST>(
+  mut query: Boxed<'a, ST, location::table>,
+  order_by: &[LocationOrder],
+) -> Boxed<'a, ST, location::table> {
+  for order in order_by {
+    let direction = order.direction.unwrap_or(OrderDirection::Asc);
+    query = match order.field {
+      LocationOrderField::Id => then_order(query, location::id, direction),
+      LocationOrderField::Name => then_order(query, location::name, direction),
+      LocationOrderField::Type => then_order(query, location::type_, direction),
+      LocationOrderField::Dimension => then_order(query, location::dimension, direction),
+    };
+  }
+  query.then_order_by(location::id.asc())
+}
@@ -0,0 +1,90 @@
+use crate::auth::{AuthError, Authorization};
+use crate::graphql::{AppState, Ctx};
+use rocket::{
+  fairing::{Fairing, Info, Kind},
+  http::{Header, Status},
+  request::{self, FromRequest, Request},
+  Outcome, Response, State,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const REQUEST_ID_HEADER: &str = "X-Request-Id";
+/// Longest client supplied request id that is kept as is.
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+static REQUEST_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A new id, unique within this process and unlikely to repeat across
+/// restarts.
+pub fn new_request_id() -> String {
+  let started = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or_default();
+  format!(
+    "{:x}-{:x}",
+    started,
+    REQUEST_COUNTER.fetch_add(1, Ordering::SeqCst)
+  )
+}
+
+struct RequestId(String);
+
+/// The id of `request`: its `X-Request-Id` header when it is reasonable,
+/// a new one otherwise. Cached so the guard and the fairing agree.
+fn request_id(request: &Request) -> String {
+  let RequestId(id) = request.local_cache(|| {
+    let header = request
+      .headers()
+      .get_one(REQUEST_ID_HEADER)
+      .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+      .filter(|id| {
+        id.chars()
+          .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+      });
+    RequestId(header.map_or_else(new_request_id, str::to_string))
+  });
+  id.clone()
+}
+
+/// Builds the context of a GraphQL request from the shared `AppState`,
+/// the bearer token and the request id.
+impl<'a, 'r> FromRequest<'a, 'r> for Ctx {
+  type Error = AuthError;
+
+  fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, AuthError> {
+    let app = match request.guard::<State<Arc<AppState>>>() {
+      Outcome::Success(app) => app.inner().clone(),
+      _ => {
+        eprintln!("AppState is not managed by Rocket");
+        return Outcome::Failure((Status::InternalServerError, AuthError::Unmanaged));
+      }
+    };
+    let identity = match request.guard::<Authorization>() {
+      Outcome::Success(Authorization(identity)) => identity,
+      Outcome::Failure((status, err)) => return Outcome::Failure((status, err)),
+      Outcome::Forward(()) => {
+        return Outcome::Failure((Status::Unauthorized, AuthError::MalformedHeader))
+      }
+    };
+    Outcome::Success(Ctx::new(app, identity, request_id(request)))
+  }
+}
+
+/// Echoes the request id in the `X-Request-Id` response header.
+pub struct RequestIdHeader;
+
+impl Fairing for RequestIdHeader {
+  fn info(&self) -> Info {
+    Info {
+      name: "X-Request-Id header",
+      kind: Kind::Response,
+    }
+  }
+
+  fn on_response(&self, request: &Request, response: &mut Response) {
+    response.set_header(Header::new(REQUEST_ID_HEADER, request_id(request)));
+  }
+}
@@ -0,0 +1,321 @@
+//! Compares two introspection results and classifies every difference by
+//! how it affects existing clients, following the rules of graphql-js'
+//! `findBreakingChanges` and `findDangerousChanges`.
+
+use crate::graphql::sdl::type_ref;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+  /// Queries that worked before can fail or return something else.
+  Breaking,
+  /// Existing queries keep working, but clients may not handle the new
+  /// values, e.g. an enum value they do not know.
+  Dangerous,
+  Safe,
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Severity::Breaking => write!(f, "BREAKING"),
+      Severity::Dangerous => write!(f, "DANGEROUS"),
+      Severity::Safe => write!(f, "SAFE"),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct SchemaChange {
+  pub severity: Severity,
+  pub description: String,
+}
+
+impl fmt::Display for SchemaChange {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "[{}] {}", self.severity, self.description)
+  }
+}
+
+/// Every change from `old` to `new`, most severe first. Both are
+/// introspection results, either full responses or `__schema` objects.
+pub fn diff_schemas(old: &Value, new: &Value) -> Vec<SchemaChange> {
+  let mut diff = Diff(vec![]);
+  let (old, new) = (schema_of(old), schema_of(new));
+  for root in &["queryType", "mutationType", "subscriptionType"] {
+    let (old_root, new_root) = (&old[*root]["name"], &new[*root]["name"]);
+    if old_root != new_root {
+      diff.push(
+        Severity::Breaking,
+        format!("{} changed from {} to {}", root, old_root, new_root),
+      );
+    }
+  }
+
+  let (old_types, new_types) = (types_of(old), types_of(new));
+  for (name, old_type) in &old_types {
+    match new_types.get(name) {
+      None => diff.push(Severity::Breaking, format!("Type {} was removed", name)),
+      Some(new_type) if old_type["kind"] != new_type["kind"] => diff.push(
+        Severity::Breaking,
+        format!(
+          "{} changed from {} to {}",
+          name,
+          kind_name(old_type),
+          kind_name(new_type)
+        ),
+      ),
+      Some(new_type) => diff.compare_types(name, old_type, new_type),
+    }
+  }
+  for name in new_types.keys() {
+    if !old_types.contains_key(name) {
+      diff.push(Severity::Safe, format!("Type {} was added", name));
+    }
+  }
+
+  let Diff(mut changes) = diff;
+  changes.sort_by_key(|change| change.severity);
+  changes
+}
+
+/// Whether any of `changes` breaks existing clients.
+pub fn has_breaking(changes: &[SchemaChange]) -> bool {
+  changes
+    .iter()
+    .any(|change| change.severity == Severity::Breaking)
+}
+
+fn schema_of(introspection: &Value) -> &Value {
+  if introspection["__schema"].is_object() {
+    &introspection["__schema"]
+  } else if introspection["data"]["__schema"].is_object() {
+    &introspection["data"]["__schema"]
+  } else {
+    introspection
+  }
+}
+
+fn name_of(value: &Value) -> &str {
+  value["name"].as_str().unwrap_or_default()
+}
+
+/// The named entries of an introspection list, such as `fields`.
+fn by_name(values: &Value) -> BTreeMap<&str, &Value> {
+  values
+    .as_array()
+    .map(|values| values.iter().map(|v| (name_of(v), v)).collect())
+    .unwrap_or_default()
+}
+
+/// The user defined types; introspection types never change.
+fn types_of(schema: &Value) -> BTreeMap<&str, &Value> {
+  let mut types = by_name(&schema["types"]);
+  types.retain(|name, _| !name.starts_with("__"));
+  types
+}
+
+fn kind_name(t: &Value) -> String {
+  t["kind"]
+    .as_str()
+    .unwrap_or_default()
+    .to_lowercase()
+    .replace('_', " ")
+}
+
+fn is_required(input: &Value) -> bool {
+  input["type"]["kind"] == "NON_NULL" && input["defaultValue"].is_null()
+}
+
+/// Whether values of `new` are still valid where clients expect `old`,
+/// e.g. a field that became non-null.
+fn is_safe_output_change(old: &Value, new: &Value) -> bool {
+  match (old["kind"].as_str(), new["kind"].as_str()) {
+    (Some("LIST"), Some("LIST")) => is_safe_output_change(&old["ofType"], &new["ofType"]),
+    (Some("NON_NULL"), Some("NON_NULL")) => is_safe_output_change(&old["ofType"], &new["ofType"]),
+    (Some("NON_NULL"), _) => false,
+    (_, Some("NON_NULL")) => is_safe_output_change(old, &new["ofType"]),
+    (Some("LIST"), _) | (_, Some("LIST")) => false,
+    _ => name_of(old) == name_of(new),
+  }
+}
+
+/// Whether every value clients sent for `old` is still accepted by `new`,
+/// e.g. an argument that became nullable.
+fn is_safe_input_change(old: &Value, new: &Value) -> bool {
+  match (old["kind"].as_str(), new["kind"].as_str()) {
+    (Some("LIST"), Some("LIST")) => is_safe_input_change(&old["ofType"], &new["ofType"]),
+    (Some("NON_NULL"), Some("NON_NULL")) => is_safe_input_change(&old["ofType"], &new["ofType"]),
+    (Some("NON_NULL"), _) => is_safe_input_change(&old["ofType"], new),
+    (_, Some("NON_NULL")) => false,
+    (Some("LIST"), _) | (_, Some("LIST")) => false,
+    _ => name_of(old) == name_of(new),
+  }
+}
+
+struct Diff(Vec<SchemaChange>);
+
+impl Diff {
+  fn push(&mut self, severity: Severity, description: String) {
+    self.0.push(SchemaChange {
+      severity,
+      description,
+    });
+  }
+
+  fn compare_types(&mut self, name: &str, old: &Value, new: &Value) {
+    match old["kind"].as_str().unwrap_or_default() {
+      "OBJECT" | "INTERFACE" => {
+        self.compare_members(name, "Interface", &old["interfaces"], &new["interfaces"]);
+        self.compare_fields(name, &old["fields"], &new["fields"]);
+      }
+      "INPUT_OBJECT" => self.compare_input_fields(name, &old["inputFields"], &new["inputFields"]),
+      "ENUM" => self.compare_members(name, "Enum value", &old["enumValues"], &new["enumValues"]),
+      "UNION" => self.compare_members(
+        name,
+        "Union member",
+        &old["possibleTypes"],
+        &new["possibleTypes"],
+      ),
+      _ => {}
+    }
+  }
+
+  /// Interfaces, enum values and union members: removing one breaks the
+  /// clients relying on it, adding one may surprise exhaustive clients.
+  fn compare_members(&mut self, name: &str, what: &str, old: &Value, new: &Value) {
+    let (old, new) = (by_name(old), by_name(new));
+    for member in old.keys().filter(|m| !new.contains_key(*m)) {
+      self.push(
+        Severity::Breaking,
+        format!("{} {} was removed from {}", what, member, name),
+      );
+    }
+    for member in new.keys().filter(|m| !old.contains_key(*m)) {
+      self.push(
+        Severity::Dangerous,
+        format!("{} {} was added to {}", what, member, name),
+      );
+    }
+  }
+
+  fn compare_fields(&mut self, type_name: &str, old: &Value, new: &Value) {
+    let (old, new) = (by_name(old), by_name(new));
+    for (name, old_field) in &old {
+      let new_field = match new.get(name) {
+        Some(new_field) => new_field,
+        None => {
+          self.push(
+            Severity::Breaking,
+            format!("Field {}.{} was removed", type_name, name),
+          );
+          continue;
+        }
+      };
+      let (old_type, new_type) = (&old_field["type"], &new_field["type"]);
+      if type_ref(old_type) != type_ref(new_type) {
+        let severity = if is_safe_output_change(old_type, new_type) {
+          Severity::Safe
+        } else {
+          Severity::Breaking
+        };
+        self.push(
+          severity,
+          format!(
+            "Field {}.{} changed type from {} to {}",
+            type_name,
+            name,
+            type_ref(old_type),
+            type_ref(new_type)
+          ),
+        );
+      }
+      if old_field["isDeprecated"] != Value::Bool(true)
+        && new_field["isDeprecated"] == Value::Bool(true)
+      {
+        self.push(
+          Severity::Safe,
+          format!("Field {}.{} was deprecated", type_name, name),
+        );
+      }
+      self.compare_args(
+        &format!("{}.{}", type_name, name),
+        &old_field["args"],
+        &new_field["args"],
+      );
+    }
+    for name in new.keys().filter(|name| !old.contains_key(*name)) {
+      self.push(
+        Severity::Safe,
+        format!("Field {}.{} was added", type_name, name),
+      );
+    }
+  }
+
+  fn compare_args(&mut self, field: &str, old: &Value, new: &Value) {
+    let path = |name: &str| format!("Argument {}({})", field, name);
+    self.compare_inputs(path, old, new);
+  }
+
+  fn compare_input_fields(&mut self, type_name: &str, old: &Value, new: &Value) {
+    let path = |name: &str| format!("Input field {}.{}", type_name, name);
+    self.compare_inputs(path, old, new);
+  }
+
+  /// Arguments and input fields, which follow the same rules: a new
+  /// required one breaks every client, a new optional one is dangerous.
+  fn compare_inputs<P: Fn(&str) -> String>(&mut self, path: P, old: &Value, new: &Value) {
+    let (old, new) = (by_name(old), by_name(new));
+    for (name, old_input) in &old {
+      let new_input = match new.get(name) {
+        Some(new_input) => new_input,
+        None => {
+          self.push(Severity::Breaking, format!("{} was removed", path(name)));
+          continue;
+        }
+      };
+      let (old_type, new_type) = (&old_input["type"], &new_input["type"]);
+      if type_ref(old_type) != type_ref(new_type) {
+        let severity = if is_safe_input_change(old_type, new_type) {
+          Severity::Safe
+        } else {
+          Severity::Breaking
+        };
+        self.push(
+          severity,
+          format!(
+            "{} changed type from {} to {}",
+            path(name),
+            type_ref(old_type),
+            type_ref(new_type)
+          ),
+        );
+      }
+      if !old_input["defaultValue"].is_null()
+        && old_input["defaultValue"] != new_input["defaultValue"]
+      {
+        self.push(
+          Severity::Dangerous,
+          format!(
+            "{} changed default value from {} to {}",
+            path(name),
+            old_input["defaultValue"],
+            new_input["defaultValue"]
+          ),
+        );
+      }
+    }
+    for (name, new_input) in new.iter().filter(|(name, _)| !old.contains_key(*name)) {
+      if is_required(new_input) {
+        self.push(
+          Severity::Breaking,
+          format!("{} was added and is required", path(name)),
+        );
+      } else {
+        self.push(Severity::Dangerous, format!("{} was added", path(name)));
+      }
+    }
+  }
+}
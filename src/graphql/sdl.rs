@@ -0,0 +1,245 @@
+//! Prints a schema in the GraphQL schema language from its introspection
+//! result, since juniper 0.14 cannot print SDL itself.
+
+use serde_json::Value;
+
+const BUILT_IN_SCALARS: &[&str] = &["String", "Int", "Float", "Boolean", "ID"];
+const DEFAULT_DEPRECATION_REASON: &str = "No longer supported";
+
+/// The SDL of an introspection result, either the full response or its
+/// `__schema` object. Types are sorted by name so the output is stable.
+pub fn print_schema(introspection: &Value) -> String {
+  let schema = if introspection["__schema"].is_object() {
+    &introspection["__schema"]
+  } else {
+    introspection
+  };
+
+  let mut blocks = vec![];
+  if let Some(block) = print_schema_definition(schema) {
+    blocks.push(block);
+  }
+  let mut types: Vec<&Value> = schema["types"]
+    .as_array()
+    .map(|types| types.iter().collect())
+    .unwrap_or_default();
+  types.retain(|t| {
+    let name = name_of(t);
+    !name.starts_with("__") && !BUILT_IN_SCALARS.contains(&name)
+  });
+  types.sort_by(|a, b| name_of(a).cmp(name_of(b)));
+  blocks.extend(types.into_iter().map(print_type));
+
+  let mut sdl = blocks.join("\n\n");
+  sdl.push('\n');
+  sdl
+}
+
+fn name_of(value: &Value) -> &str {
+  value["name"].as_str().unwrap_or_default()
+}
+
+/// `schema { ... }`, only needed when the root types have custom names.
+fn print_schema_definition(schema: &Value) -> Option<String> {
+  let roots = [
+    ("query", "queryType", "Query"),
+    ("mutation", "mutationType", "Mutation"),
+    ("subscription", "subscriptionType", "Subscription"),
+  ];
+  let present: Vec<(&str, &str, &str)> = roots
+    .iter()
+    .filter_map(|(operation, key, default)| {
+      schema[*key]["name"]
+        .as_str()
+        .map(|name| (*operation, name, *default))
+    })
+    .collect();
+  if present.iter().all(|(_, name, default)| name == default) {
+    return None;
+  }
+  let fields: Vec<String> = present
+    .iter()
+    .map(|(operation, name, _)| format!("  {}: {}", operation, name))
+    .collect();
+  Some(format!("schema {{\n{}\n}}", fields.join("\n")))
+}
+
+fn print_type(t: &Value) -> String {
+  let name = name_of(t);
+  let definition = match t["kind"].as_str().unwrap_or_default() {
+    "SCALAR" => format!("scalar {}", name),
+    "OBJECT" => format!(
+      "type {}{} {}",
+      name,
+      print_interfaces(t),
+      print_fields(&t["fields"])
+    ),
+    "INTERFACE" => format!("interface {} {}", name, print_fields(&t["fields"])),
+    "UNION" => format!(
+      "union {} = {}",
+      name,
+      type_names(&t["possibleTypes"]).join(" | ")
+    ),
+    "ENUM" => format!("enum {} {}", name, print_enum_values(&t["enumValues"])),
+    "INPUT_OBJECT" => format!("input {} {}", name, print_input_fields(&t["inputFields"])),
+    kind => format!("# Unknown kind {} for {}", kind, name),
+  };
+  format!("{}{}", print_description(&t["description"], ""), definition)
+}
+
+fn type_names(types: &Value) -> Vec<&str> {
+  types
+    .as_array()
+    .map(|types| types.iter().map(name_of).collect())
+    .unwrap_or_default()
+}
+
+fn print_interfaces(t: &Value) -> String {
+  let interfaces = type_names(&t["interfaces"]);
+  if interfaces.is_empty() {
+    String::new()
+  } else {
+    format!(" implements {}", interfaces.join(" & "))
+  }
+}
+
+fn print_block(lines: Vec<String>) -> String {
+  if lines.is_empty() {
+    "{}".to_string()
+  } else {
+    format!("{{\n{}\n}}", lines.join("\n"))
+  }
+}
+
+fn print_fields(fields: &Value) -> String {
+  let lines = fields
+    .as_array()
+    .map(|fields| {
+      fields
+        .iter()
+        .map(|field| {
+          format!(
+            "{}  {}{}: {}{}",
+            print_description(&field["description"], "  "),
+            name_of(field),
+            print_args(&field["args"]),
+            type_ref(&field["type"]),
+            print_deprecated(field)
+          )
+        })
+        .collect()
+    })
+    .unwrap_or_default();
+  print_block(lines)
+}
+
+fn print_input_value(value: &Value) -> String {
+  let default = match value["defaultValue"].as_str() {
+    Some(default) => format!(" = {}", default),
+    None => String::new(),
+  };
+  format!(
+    "{}: {}{}",
+    name_of(value),
+    type_ref(&value["type"]),
+    default
+  )
+}
+
+fn print_args(args: &Value) -> String {
+  let args = match args.as_array() {
+    Some(args) if !args.is_empty() => args,
+    _ => return String::new(),
+  };
+  if args.iter().all(|arg| arg["description"].is_null()) {
+    let args: Vec<String> = args.iter().map(print_input_value).collect();
+    return format!("({})", args.join(", "));
+  }
+  let args: Vec<String> = args
+    .iter()
+    .map(|arg| {
+      format!(
+        "{}    {}",
+        print_description(&arg["description"], "    "),
+        print_input_value(arg)
+      )
+    })
+    .collect();
+  format!("(\n{}\n  )", args.join("\n"))
+}
+
+fn print_input_fields(fields: &Value) -> String {
+  let lines = fields
+    .as_array()
+    .map(|fields| {
+      fields
+        .iter()
+        .map(|field| {
+          format!(
+            "{}  {}",
+            print_description(&field["description"], "  "),
+            print_input_value(field)
+          )
+        })
+        .collect()
+    })
+    .unwrap_or_default();
+  print_block(lines)
+}
+
+fn print_enum_values(values: &Value) -> String {
+  let lines = values
+    .as_array()
+    .map(|values| {
+      values
+        .iter()
+        .map(|value| {
+          format!(
+            "{}  {}{}",
+            print_description(&value["description"], "  "),
+            name_of(value),
+            print_deprecated(value)
+          )
+        })
+        .collect()
+    })
+    .unwrap_or_default();
+  print_block(lines)
+}
+
+fn print_deprecated(value: &Value) -> String {
+  if value["isDeprecated"].as_bool() != Some(true) {
+    return String::new();
+  }
+  match value["deprecationReason"].as_str() {
+    Some(reason) if reason != DEFAULT_DEPRECATION_REASON => {
+      format!(" @deprecated(reason: {})", Value::from(reason))
+    }
+    _ => " @deprecated".to_string(),
+  }
+}
+
+fn print_description(description: &Value, indent: &str) -> String {
+  let description = match description.as_str() {
+    Some(description) if !description.is_empty() => description,
+    _ => return String::new(),
+  };
+  if description.contains('\n') {
+    let lines: Vec<String> = description
+      .lines()
+      .map(|line| format!("{}{}", indent, line.replace("\"\"\"", "\\\"\"\"")))
+      .collect();
+    format!("{}\"\"\"\n{}\n{}\"\"\"\n", indent, lines.join("\n"), indent)
+  } else {
+    format!("{}{}\n", indent, Value::from(description))
+  }
+}
+
+/// A type reference such as `[Character!]!`.
+pub fn type_ref(t: &Value) -> String {
+  match t["kind"].as_str() {
+    Some("NON_NULL") => format!("{}!", type_ref(&t["ofType"])),
+    Some("LIST") => format!("[{}]", type_ref(&t["ofType"])),
+    _ => name_of(t).to_string(),
+  }
+}
@@ -0,0 +1,329 @@
+use crate::graphql::{
+  character_model::Character, episode_model::Episode, location_model::Location, Ctx,
+};
+use fallible_iterator::FallibleIterator;
+use juniper::EmptyMutation;
+use postgres::{Connection, TlsMode};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Channel the `notify_changed_rows` trigger publishes to.
+const CHANGES_CHANNEL: &str = "row_changes";
+
+/// Waits between attempts to listen again, doubling from the first to the
+/// last while the database stays unreachable.
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(juniper::GraphQLEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ChangeKind {
+  Created,
+  Updated,
+  Deleted,
+}
+
+#[derive(Clone)]
+pub enum ChangeEvent {
+  Character(ChangeKind, Character),
+  Episode(ChangeKind, Episode),
+  Location(ChangeKind, Location),
+}
+
+/// Fans change events out to every open subscription.
+#[derive(Default)]
+pub struct EventBus {
+  subscribers: Mutex<HashMap<SubscriberId, Sender<ChangeEvent>>>,
+  next_id: AtomicU64,
+}
+
+/// Names a subscriber of an `EventBus`, to unsubscribe it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriberId(u64);
+
+impl EventBus {
+  /// The events published from now on, until `unsubscribe` ends them.
+  pub fn subscribe(&self) -> (SubscriberId, Receiver<ChangeEvent>) {
+    let id = SubscriberId(self.next_id.fetch_add(1, Ordering::Relaxed));
+    let (sender, receiver) = channel();
+    self.subscribers.lock().unwrap().insert(id, sender);
+    (id, receiver)
+  }
+
+  /// Drops the sender of a subscriber, so that its receiver stops waiting
+  /// for events right away.
+  pub fn unsubscribe(&self, id: SubscriberId) {
+    self.subscribers.lock().unwrap().remove(&id);
+  }
+
+  /// Sends `event` to every subscriber, forgetting the ones that hung up.
+  pub fn publish(&self, event: ChangeEvent) {
+    self
+      .subscribers
+      .lock()
+      .unwrap()
+      .retain(|_, subscriber| subscriber.send(event.clone()).is_ok());
+  }
+}
+
+// ######### SCHEMA ###############
+
+pub struct Change<Model> {
+  kind: ChangeKind,
+  node: Model,
+}
+
+#[juniper::object(name = "CharacterChange", Context = Ctx,)]
+impl Change<Character> {
+  fn kind(&self) -> ChangeKind {
+    self.kind
+  }
+  fn character(&self) -> &Character {
+    &self.node
+  }
+}
+#[juniper::object(name = "EpisodeChange", Context = Ctx,)]
+impl Change<Episode> {
+  fn kind(&self) -> ChangeKind {
+    self.kind
+  }
+  fn episode(&self) -> &Episode {
+    &self.node
+  }
+}
+#[juniper::object(name = "LocationChange", Context = Ctx,)]
+impl Change<Location> {
+  fn kind(&self) -> ChangeKind {
+    self.kind
+  }
+  fn location(&self) -> &Location {
+    &self.node
+  }
+}
+
+/// Subscription root, resolved once per published event. Every field is
+/// null except the one matching the event, so a subscription document
+/// produces data only for the changes it asked for.
+pub struct Subscription {
+  event: Option<ChangeEvent>,
+}
+
+#[juniper::object(
+  Context = Ctx,
+)]
+impl Subscription {
+  /// Only changes to the character with this id, when given
+  fn character_changed(&self, id: Option<i32>) -> Option<Change<Character>> {
+    match &self.event {
+      Some(ChangeEvent::Character(kind, node)) if id.map_or(true, |id| id == node.id) => {
+        Some(Change {
+          kind: *kind,
+          node: node.clone(),
+        })
+      }
+      _ => None,
+    }
+  }
+
+  /// Only changes to the episode with this id, when given
+  fn episode_changed(&self, id: Option<i32>) -> Option<Change<Episode>> {
+    match &self.event {
+      Some(ChangeEvent::Episode(kind, node)) if id.map_or(true, |id| id == node.id) => {
+        Some(Change {
+          kind: *kind,
+          node: node.clone(),
+        })
+      }
+      _ => None,
+    }
+  }
+
+  /// Only changes to the location with this id, when given
+  fn location_changed(&self, id: Option<i32>) -> Option<Change<Location>> {
+    match &self.event {
+      Some(ChangeEvent::Location(kind, node)) if id.map_or(true, |id| id == node.id) => {
+        Some(Change {
+          kind: *kind,
+          node: node.clone(),
+        })
+      }
+      _ => None,
+    }
+  }
+}
+
+pub type SubscriptionSchema = juniper::RootNode<'static, Subscription, EmptyMutation<Ctx>>;
+
+/// The subscription schema for one event; `None` resolves every field to
+/// null, which is used to validate documents when they are started.
+pub fn subscription_root(event: Option<ChangeEvent>) -> SubscriptionSchema {
+  SubscriptionSchema::new(Subscription { event }, EmptyMutation::new())
+}
+
+/// Completes the `data` of an introspection of the main schema with the
+/// `data` the subscription schema answers to the same document, with its
+/// `subscriptionType` fields asking for the `queryType` instead. The
+/// `RootNode` of juniper 0.14 has no subscription type, so the main schema
+/// answers `subscriptionType: null` and lacks the types only subscriptions
+/// use, while the subscription schema has them with its root as query type.
+/// Fields are matched by response key, aliases included.
+pub fn merge_subscription_introspection(data: &mut Value, subscription_data: &Value) {
+  let (data, subscription_data) = match (data, subscription_data) {
+    // Juniper nulls the whole `data` for `__type` of a type it lacks.
+    (data @ Value::Null, _) => {
+      *data = subscription_data.clone();
+      return;
+    }
+    (Value::Object(data), Value::Object(subscription_data)) => (data, subscription_data),
+    _ => return,
+  };
+  for (key, answer) in subscription_data {
+    match data.get_mut(key) {
+      // `__type` of a type only the subscription schema has.
+      Some(value) if value.is_null() => *value = answer.clone(),
+      Some(Value::Object(schema)) => merge_schema(schema, answer),
+      _ => {}
+    }
+  }
+}
+
+fn merge_schema(schema: &mut serde_json::Map<String, Value>, subscription_schema: &Value) {
+  let subscription_schema = match subscription_schema {
+    Value::Object(subscription_schema) => subscription_schema,
+    _ => return,
+  };
+  for (key, answer) in subscription_schema {
+    match schema.get_mut(key) {
+      // `subscriptionType`.
+      Some(value) if value.is_null() => *value = answer.clone(),
+      // `types`, and `directives` both schemas have.
+      Some(Value::Array(types)) => {
+        let names: HashSet<String> = types
+          .iter()
+          .filter_map(|type_| type_["name"].as_str().map(str::to_string))
+          .collect();
+        let missing: Vec<Value> = answer
+          .as_array()
+          .into_iter()
+          .flatten()
+          .filter(|type_| {
+            type_["name"]
+              .as_str()
+              .map_or(false, |name| !names.contains(name))
+          })
+          .cloned()
+          .collect();
+        types.extend(missing);
+      }
+      _ => {}
+    }
+  }
+}
+
+// ######### DATABASE NOTIFICATIONS ###############
+
+/// Payload of the `notify_changed_rows` trigger: the rows one statement
+/// changed in a table, or part of them.
+#[derive(Deserialize)]
+struct ChangedRows {
+  table: String,
+  op: String,
+  rows: Vec<Value>,
+}
+
+impl ChangedRows {
+  fn into_events(self) -> Result<Vec<ChangeEvent>, String> {
+    let kind = match self.op.as_str() {
+      "INSERT" => ChangeKind::Created,
+      "UPDATE" => ChangeKind::Updated,
+      "DELETE" => ChangeKind::Deleted,
+      op => return Err(format!("unknown operation {}", op)),
+    };
+    let table = self.table;
+    self
+      .rows
+      .into_iter()
+      .map(|row| {
+        let event = match table.as_str() {
+          "character" => serde_json::from_value(row).map(|c| ChangeEvent::Character(kind, c)),
+          "episode" => serde_json::from_value(row).map(|e| ChangeEvent::Episode(kind, e)),
+          "location" => serde_json::from_value(row).map(|l| ChangeEvent::Location(kind, l)),
+          table => return Err(format!("unknown table {}", table)),
+        };
+        event.map_err(|err| err.to_string())
+      })
+      .collect()
+  }
+}
+
+/// Publishes the rows changed in the database, as reported by the
+/// `notify_changed_rows` trigger once their transaction commits. Every
+/// write is published this way, whether it comes from a mutation of this
+/// server, `resetDb`, `importDb`, `restoreSnapshot`, another process or
+/// plain SQL.
+///
+/// The listening connection is opened again whenever it fails, after a
+/// delay that grows while the failures follow each other. Changes made
+/// while it is down are not published.
+pub fn listen_for_changes(database_url: String, events: Arc<EventBus>) {
+  thread::spawn(move || {
+    let mut delay = MIN_RECONNECT_DELAY;
+    loop {
+      let listened_since = Instant::now();
+      match forward_notifications(&database_url, &events) {
+        Ok(()) => eprintln!("The database closed the change notifications"),
+        Err(err) => eprintln!("Stopped listening for database changes: {}", err),
+      }
+      // A connection that lasted was not part of a series of failures.
+      if listened_since.elapsed() > MAX_RECONNECT_DELAY {
+        delay = MIN_RECONNECT_DELAY;
+      }
+      eprintln!("Listening again in {}s", delay.as_secs());
+      thread::sleep(delay);
+      delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+  });
+}
+
+fn forward_notifications(database_url: &str, events: &EventBus) -> Result<(), postgres::Error> {
+  let conn = Connection::connect(database_url, TlsMode::None)?;
+  conn.execute(&format!("LISTEN {}", CHANGES_CHANNEL), &[])?;
+  let notifications = conn.notifications();
+  let mut notifications = notifications.blocking_iter();
+  while let Some(notification) = notifications.next()? {
+    let changed: ChangedRows = match serde_json::from_str(&notification.payload) {
+      Ok(changed) => changed,
+      Err(err) => {
+        eprintln!("Ignoring malformed change notification: {}", err);
+        continue;
+      }
+    };
+    match changed.into_events() {
+      Ok(changes) => changes.into_iter().for_each(|event| events.publish(event)),
+      Err(err) => eprintln!("Ignoring change notification: {}", err),
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn unsubscribing_ends_the_events() {
+    let bus = EventBus::default();
+    let (first, first_events) = bus.subscribe();
+    let (second, second_events) = bus.subscribe();
+    assert_ne!(first, second);
+    bus.unsubscribe(first);
+    assert!(first_events.recv().is_err());
+    assert!(second_events.try_recv().is_err());
+    assert_eq!(bus.subscribers.lock().unwrap().len(), 1);
+  }
+}
@@ -0,0 +1,275 @@
+//! WebSocket endpoint for GraphQL subscriptions, speaking the `graphql-ws`
+//! protocol of subscriptions-transport-ws (the one Apollo clients use).
+//!
+//! juniper 0.14 has no subscription executor, so each started document is
+//! rewritten into a query and run against `subscription_root(event)` for
+//! every published event; it is sent to the client when any of its root
+//! fields matched the event.
+
+use crate::graphql::{
+  request::new_request_id,
+  subscriptions::{subscription_root, SubscriberId},
+  AppState, Ctx,
+};
+use juniper::{http::GraphQLRequest, InputValue};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const PROTOCOL: &str = "graphql-ws";
+
+/// Serves subscriptions on `address` from a background thread.
+pub fn serve(address: String, app: Arc<AppState>) {
+  thread::spawn(move || {
+    let result = ws::listen(address.as_str(), |out| Connection {
+      out,
+      app: app.clone(),
+      operations: Arc::new(Mutex::new(HashMap::new())),
+      subscriber: None,
+    });
+    if let Err(err) = result {
+      eprintln!("GraphQL WebSocket server stopped: {}", err);
+    }
+  });
+}
+
+#[derive(Deserialize)]
+struct ClientMessage {
+  #[serde(rename = "type")]
+  type_: String,
+  id: Option<String>,
+  payload: Option<StartPayload>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StartPayload {
+  query: String,
+  operation_name: Option<String>,
+  variables: Option<InputValue>,
+}
+
+type Operations = Arc<Mutex<HashMap<String, GraphQLRequest>>>;
+
+struct Connection {
+  out: ws::Sender,
+  app: Arc<AppState>,
+  operations: Operations,
+  /// Receives the events `forward_events` runs the operations against.
+  subscriber: Option<SubscriberId>,
+}
+
+fn message(type_: &str, id: Option<&str>, payload: Option<Value>) -> String {
+  let mut message = Map::new();
+  message.insert("type".to_string(), json!(type_));
+  if let Some(id) = id {
+    message.insert("id".to_string(), json!(id));
+  }
+  if let Some(payload) = payload {
+    message.insert("payload".to_string(), payload);
+  }
+  Value::Object(message).to_string()
+}
+
+/// Subscriptions only read, so they run anonymously.
+fn context(app: &Arc<AppState>) -> Ctx {
+  Ctx::new(app.clone(), None, new_request_id())
+}
+
+fn error_payload(message: &str) -> Value {
+  json!([{ "message": message }])
+}
+
+impl Connection {
+  fn start(&self, id: &str, payload: StartPayload) -> ws::Result<()> {
+    let query = match as_query(&payload.query) {
+      Some(query) => query,
+      None => {
+        return self.out.send(message(
+          "error",
+          Some(id),
+          Some(error_payload("Only subscription operations are supported")),
+        ))
+      }
+    };
+    let request = GraphQLRequest::new(query, payload.operation_name, payload.variables);
+
+    // Resolving without an event validates the document without sending data.
+    let (root, context) = (subscription_root(None), context(&self.app));
+    let response = request.execute(&root, &context);
+    if !response.is_ok() {
+      let mut payload = serde_json::to_value(&response).unwrap_or_default();
+      return self
+        .out
+        .send(message("error", Some(id), Some(payload["errors"].take())));
+    }
+    self
+      .operations
+      .lock()
+      .unwrap()
+      .insert(id.to_string(), request);
+    Ok(())
+  }
+
+  fn stop(&self, id: &str) -> ws::Result<()> {
+    self.operations.lock().unwrap().remove(id);
+    self.out.send(message("complete", Some(id), None))
+  }
+
+  /// Ends the thread of `forward_events`, which stops at once.
+  fn unsubscribe(&mut self) {
+    if let Some(subscriber) = self.subscriber.take() {
+      self.app.events().unsubscribe(subscriber);
+    }
+  }
+
+  /// Runs every started operation against each published event until the
+  /// connection closes and unsubscribes.
+  fn forward_events(&mut self) {
+    let (subscriber, events) = self.app.events().subscribe();
+    self.subscriber = Some(subscriber);
+    let out = self.out.clone();
+    let app = self.app.clone();
+    let operations = self.operations.clone();
+    thread::spawn(move || {
+      for event in events {
+        let root = subscription_root(Some(event));
+        // Executing takes a while, so `start` and `stop` do not wait for it.
+        let started: Vec<(String, GraphQLRequest)> = operations
+          .lock()
+          .unwrap()
+          .iter()
+          .map(|(id, request)| (id.clone(), request.clone()))
+          .collect();
+        for (id, request) in &started {
+          let response = request.execute(&root, &context(&app));
+          let payload = serde_json::to_value(&response).unwrap_or_default();
+          if !matched(&payload) || !operations.lock().unwrap().contains_key(id) {
+            // No data after the `complete` of a stop sent meanwhile.
+            continue;
+          }
+          if out.send(message("data", Some(id), Some(payload))).is_err() {
+            return;
+          }
+        }
+      }
+    });
+  }
+}
+
+/// Whether a response has a non-null root field or an error to report.
+fn matched(payload: &Value) -> bool {
+  let has_data = payload["data"]
+    .as_object()
+    .map_or(false, |data| data.values().any(|value| !value.is_null()));
+  has_data || !payload["errors"].is_null()
+}
+
+impl ws::Handler for Connection {
+  fn on_request(&mut self, request: &ws::Request) -> ws::Result<ws::Response> {
+    let mut response = ws::Response::from_request(request)?;
+    if request.protocols()?.contains(&PROTOCOL) {
+      response.set_protocol(PROTOCOL);
+    }
+    Ok(response)
+  }
+
+  fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+    self.forward_events();
+    Ok(())
+  }
+
+  fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+    let text = msg.into_text()?;
+    let msg: ClientMessage = match serde_json::from_str(&text) {
+      Ok(msg) => msg,
+      Err(err) => {
+        return self.out.send(message(
+          "connection_error",
+          None,
+          Some(json!({ "message": err.to_string() })),
+        ))
+      }
+    };
+    match (msg.type_.as_str(), msg.id, msg.payload) {
+      ("connection_init", _, _) => self.out.send(message("connection_ack", None, None)),
+      ("start", Some(id), Some(payload)) => self.start(&id, payload),
+      ("stop", Some(id), _) => self.stop(&id),
+      ("connection_terminate", _, _) => self.out.close(ws::CloseCode::Normal),
+      (type_, id, _) => self.out.send(message(
+        "error",
+        id.as_ref().map(String::as_str),
+        Some(error_payload(&format!("Unexpected message: {}", type_))),
+      )),
+    }
+  }
+
+  fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+    self.unsubscribe();
+    self.operations.lock().unwrap().clear();
+  }
+}
+
+/// Connections that fail are dropped without `on_close`.
+impl Drop for Connection {
+  fn drop(&mut self) {
+    self.unsubscribe();
+  }
+}
+
+/// Rewrites the `subscription` operations of `document` as queries, or
+/// returns `None` when there is none. Only keywords outside of selection
+/// sets are touched; strings and comments are copied as they are.
+fn as_query(document: &str) -> Option<String> {
+  let mut query = String::with_capacity(document.len());
+  let mut found = false;
+  let mut depth = 0;
+  let mut chars = document.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      '{' => depth += 1,
+      '}' => depth -= 1,
+      '#' | '"' => {
+        let end = if c == '#' { '\n' } else { '"' };
+        query.push(c);
+        while let Some(c) = chars.next() {
+          query.push(c);
+          if c == '\\' && end == '"' {
+            if let Some(escaped) = chars.next() {
+              query.push(escaped);
+            }
+          } else if c == end {
+            break;
+          }
+        }
+        continue;
+      }
+      c if depth == 0 && (c.is_ascii_alphabetic() || c == '_') => {
+        let mut name = c.to_string();
+        while let Some(&c) = chars.peek() {
+          if !(c.is_ascii_alphanumeric() || c == '_') {
+            break;
+          }
+          name.push(c);
+          chars.next();
+        }
+        if name == "subscription" {
+          found = true;
+          query.push_str("query");
+        } else {
+          query.push_str(&name);
+        }
+        continue;
+      }
+      _ => {}
+    }
+    query.push(c);
+  }
+  if found {
+    Some(query)
+  } else {
+    None
+  }
+}
@@ -0,0 +1,259 @@
+//! Loads a data source into a database that already has rows, keeping the
+//! edits made since: rows are upserted by id instead of `db::reset_db`
+//! deleting everything first.
+//!
+//! Each row is compared with the stored one, so the report tells the rows
+//! inserted, updated and left unchanged per table. With `prune`, rows the
+//! source does not have are deleted too. A dry run does all of this in a
+//! transaction that is rolled back, so it reports exactly what a real
+//! import would change, constraint violations included.
+
+use crate::db::{self, DataSource, DbError};
+use crate::graphql::{
+  character_model::{Character, CHARACTER_COLUMNS},
+  episode_model::{CharacterEpisode, Episode},
+  location_model::Location,
+};
+use crate::loader::LoadReport;
+use crate::schema::*;
+use diesel::{
+  pg::upsert::excluded,
+  pg::PgConnection,
+  prelude::*,
+  result::Error,
+  sql_types::{Array, Int4},
+};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ImportOptions {
+  /// Delete the rows that are not in the source.
+  pub prune: bool,
+  /// Commit the changes. Without it, the default, everything is rolled
+  /// back once the changes are counted.
+  pub apply: bool,
+}
+
+/// What an import changed, or would change, in one table.
+#[derive(juniper::GraphQLObject, Debug, Default, Clone)]
+pub struct TableChanges {
+  pub table: String,
+  pub inserted: i32,
+  pub updated: i32,
+  pub unchanged: i32,
+  /// Always 0 unless the import prunes
+  pub deleted: i32,
+}
+
+impl TableChanges {
+  fn new(table: &str) -> TableChanges {
+    TableChanges {
+      table: table.to_string(),
+      ..TableChanges::default()
+    }
+  }
+
+  /// Counts each of `rows` against the stored row with the same id and
+  /// returns those to write, i.e. the new and the changed ones.
+  fn classify<T, F>(&mut self, rows: Vec<T>, stored: Vec<T>, id: F) -> Vec<T>
+  where
+    T: PartialEq,
+    F: Fn(&T) -> i32,
+  {
+    let stored: HashMap<i32, T> = stored.into_iter().map(|row| (id(&row), row)).collect();
+    rows
+      .into_iter()
+      .filter(|row| match stored.get(&id(row)) {
+        None => {
+          self.inserted += 1;
+          true
+        }
+        Some(old) if old == row => {
+          self.unchanged += 1;
+          false
+        }
+        Some(_) => {
+          self.updated += 1;
+          true
+        }
+      })
+      .collect()
+  }
+}
+
+impl fmt::Display for TableChanges {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{}: {} inserted, {} updated, {} unchanged, {} deleted",
+      self.table, self.inserted, self.updated, self.unchanged, self.deleted
+    )
+  }
+}
+
+#[derive(Debug)]
+pub struct ImportReport {
+  pub tables: Vec<TableChanges>,
+  /// True when nothing was written.
+  pub dry_run: bool,
+}
+
+/// Upserts the rows of `source` by id, in one transaction that rolls back
+/// when any file or row cannot be loaded, or unless `apply` is set.
+pub fn import_db(
+  conn: &PgConnection,
+  source: &mut dyn DataSource,
+  options: ImportOptions,
+) -> Result<ImportReport, DbError> {
+  let mut dry_run_report = None;
+  let result = conn.transaction::<ImportReport, DbError, _>(|| {
+    let report = upsert_all(conn, source, options)?;
+    if !options.apply {
+      dry_run_report = Some(report);
+      return Err(DbError::Query(Error::RollbackTransaction));
+    }
+    Ok(report)
+  });
+  match (result, dry_run_report) {
+    (Err(DbError::Query(Error::RollbackTransaction)), Some(report)) => Ok(report),
+    (result, _) => result,
+  }
+}
+
+fn upsert_all(
+  conn: &PgConnection,
+  source: &mut dyn DataSource,
+  options: ImportOptions,
+) -> Result<ImportReport, DbError> {
+  let mut load = LoadReport::default();
+  // ############  location  ################
+  let mut locations = TableChanges::new("location");
+  let mut location_ids = vec![];
+  source.locations(&mut load, &mut |rows: Vec<Location>| {
+    let count = rows.len();
+    let ids: Vec<i32> = rows.iter().map(|row| row.id).collect();
+    let stored = location::table
+      .filter(location::id.eq_any(&ids))
+      .load(conn)?;
+    location_ids.extend(ids);
+    let rows = locations.classify(rows, stored, |row| row.id);
+    if !rows.is_empty() {
+      diesel::insert_into(location::table)
+        .values(&rows)
+        .on_conflict(location::id)
+        .do_update()
+        .set((
+          location::name.eq(excluded(location::name)),
+          location::type_.eq(excluded(location::type_)),
+          location::dimension.eq(excluded(location::dimension)),
+        ))
+        .execute(conn)?;
+    }
+    Ok(count)
+  })?;
+  // #############  character  ################
+  let mut characters = TableChanges::new("character");
+  let mut character_ids = vec![];
+  source.characters(&mut load, &mut |rows: Vec<Character>| {
+    let count = rows.len();
+    let ids: Vec<i32> = rows.iter().map(|row| row.id).collect();
+    let stored = character::table
+      .select(CHARACTER_COLUMNS)
+      .filter(character::id.eq_any(&ids))
+      .load(conn)?;
+    character_ids.extend(ids);
+    let rows = characters.classify(rows, stored, |row| row.id);
+    if !rows.is_empty() {
+      diesel::insert_into(character::table)
+        .values(&rows)
+        .on_conflict(character::id)
+        .do_update()
+        .set((
+          character::name.eq(excluded(character::name)),
+          character::status.eq(excluded(character::status)),
+          character::species.eq(excluded(character::species)),
+          character::gender.eq(excluded(character::gender)),
+          character::type_.eq(excluded(character::type_)),
+          character::origin_id.eq(excluded(character::origin_id)),
+          character::location_id.eq(excluded(character::location_id)),
+        ))
+        .execute(conn)?;
+    }
+    Ok(count)
+  })?;
+  // ##############  episode  ################
+  let mut episodes = TableChanges::new("episode");
+  let mut episode_ids = vec![];
+  source.episodes(&mut load, &mut |rows: Vec<Episode>| {
+    let count = rows.len();
+    let ids: Vec<i32> = rows.iter().map(|row| row.id).collect();
+    let stored = episode::table.filter(episode::id.eq_any(&ids)).load(conn)?;
+    episode_ids.extend(ids);
+    let rows = episodes.classify(rows, stored, |row| row.id);
+    if !rows.is_empty() {
+      diesel::insert_into(episode::table)
+        .values(&rows)
+        .on_conflict(episode::id)
+        .do_update()
+        .set((
+          episode::name.eq(excluded(episode::name)),
+          episode::air_date.eq(excluded(episode::air_date)),
+          episode::code.eq(excluded(episode::code)),
+        ))
+        .execute(conn)?;
+    }
+    Ok(count)
+  })?;
+  // ############  character_episode  ################
+  // The pairs are the whole row, so they are only inserted or unchanged.
+  let mut character_episodes = TableChanges::new("character_episode");
+  let (mut pair_characters, mut pair_episodes) = (vec![], vec![]);
+  source.character_episodes(&mut load, &mut |rows: Vec<CharacterEpisode>| {
+    let count = rows.len();
+    pair_characters.extend(rows.iter().map(|row| row.character_id));
+    pair_episodes.extend(rows.iter().map(|row| row.episode_id));
+    let inserted = diesel::insert_into(character_episode::table)
+      .values(&rows)
+      .on_conflict_do_nothing()
+      .execute(conn)?;
+    character_episodes.inserted += inserted as i32;
+    character_episodes.unchanged += (count - inserted) as i32;
+    Ok(count)
+  })?;
+  load.into_result()?;
+
+  if options.prune {
+    // Dependent rows first, so no reference is left dangling in between.
+    // One statement whatever the number of pairs, the source pairs being
+    // zipped back together by `unnest`.
+    character_episodes.deleted = diesel::sql_query(
+      r#"
+      DELETE FROM "character_episode" ce
+      WHERE NOT EXISTS (
+        SELECT 1 FROM unnest($1::int4[], $2::int4[]) AS kept("character_id", "episode_id")
+        WHERE kept."character_id" = ce."character_id" AND kept."episode_id" = ce."episode_id"
+      )
+      "#,
+    )
+    .bind::<Array<Int4>, _>(&pair_characters)
+    .bind::<Array<Int4>, _>(&pair_episodes)
+    .execute(conn)? as i32;
+    episodes.deleted =
+      diesel::delete(episode::table.filter(episode::id.ne_all(&episode_ids))).execute(conn)? as i32;
+    characters.deleted =
+      diesel::delete(character::table.filter(character::id.ne_all(&character_ids))).execute(conn)?
+        as i32;
+    locations.deleted = diesel::delete(location::table.filter(location::id.ne_all(&location_ids)))
+      .execute(conn)? as i32;
+  }
+  // `setval` is not rolled back, so a dry run must not move the sequences.
+  if options.apply {
+    db::reset_sequences(conn)?;
+  }
+
+  Ok(ImportReport {
+    tables: vec![locations, characters, episodes, character_episodes],
+    dry_run: !options.apply,
+  })
+}
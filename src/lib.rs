@@ -1,6 +1,19 @@
+#![feature(decl_macro, proc_macro_hygiene)]
+
 #[macro_use]
 extern crate diesel;
 
+pub mod auth;
+pub mod config;
 pub mod schema;
+pub mod full_text_search;
 pub mod graphql;  
-pub mod db;
\ No newline at end of file
+pub mod db;
+pub mod export;
+pub mod import;
+pub mod loader;
+pub mod migrations;
+pub mod rest;
+pub mod snapshot;
+pub mod upstream;
+pub mod graphql_ws;
@@ -0,0 +1,453 @@
+//! Streams seed files into the database in batches.
+//!
+//! TSV, CSV, JSON (an array of flat objects, as in `raw-data/*.json`) and
+//! NDJSON (one object per line) are read row by row into
+//! `csv::StringRecord`s, so every format deserializes the same way, e.g.
+//! the string ids of the JSON files. Rows
+//! that do not deserialize are skipped and reported with their position
+//! and column; the caller decides whether the report fails the load.
+
+use crate::db::DbError;
+use csv::StringRecord;
+use diesel::QueryResult;
+use serde::de::{self, DeserializeOwned, SeqAccess, Visitor};
+use serde_json::Value;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Rows sent to the database per insert.
+pub const BATCH_SIZE: usize = 500;
+/// Row errors shown by `Display` before the rest are only counted.
+const MAX_DISPLAYED_ERRORS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SeedFormat {
+  Tsv,
+  Csv,
+  Json,
+  Ndjson,
+}
+
+impl SeedFormat {
+  pub const ALL: [SeedFormat; 4] = [
+    SeedFormat::Tsv,
+    SeedFormat::Csv,
+    SeedFormat::Json,
+    SeedFormat::Ndjson,
+  ];
+
+  pub fn extension(self) -> &'static str {
+    match self {
+      SeedFormat::Tsv => "tsv",
+      SeedFormat::Csv => "csv",
+      SeedFormat::Json => "json",
+      SeedFormat::Ndjson => "ndjson",
+    }
+  }
+
+  pub fn from_path(path: &Path) -> Option<SeedFormat> {
+    let extension = path.extension()?.to_str()?;
+    SeedFormat::ALL
+      .iter()
+      .cloned()
+      .find(|format| format.extension().eq_ignore_ascii_case(extension))
+  }
+}
+
+/// The seed file `<stem>.tsv`, `.csv`, `.json` or `.ndjson` in `data_dir`,
+/// in this order of preference.
+pub fn find_seed_file(data_dir: &Path, stem: &str) -> Result<PathBuf, DbError> {
+  SeedFormat::ALL
+    .iter()
+    .map(|format| data_dir.join(format!("{}.{}", stem, format.extension())))
+    .find(|path| path.is_file())
+    .ok_or_else(|| {
+      DbError::Seed(vec![RowError::file(
+        &data_dir.join(stem),
+        "No .tsv, .csv, .json or .ndjson seed file".to_string(),
+      )])
+    })
+}
+
+/// A row, or a whole file, that could not be loaded.
+#[derive(Debug, Clone)]
+pub struct RowError {
+  pub file: String,
+  /// 1-based position of the row among the file's records.
+  pub record: Option<u64>,
+  /// Line of the row, for TSV, CSV and NDJSON.
+  pub line: Option<u64>,
+  pub column: Option<String>,
+  pub reason: String,
+}
+
+impl RowError {
+  fn file(path: &Path, reason: String) -> RowError {
+    RowError {
+      file: path.display().to_string(),
+      record: None,
+      line: None,
+      column: None,
+      reason,
+    }
+  }
+}
+
+impl fmt::Display for RowError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.file)?;
+    match (self.line, self.record) {
+      (Some(line), _) => write!(f, ", line {}", line)?,
+      (None, Some(record)) => write!(f, ", record {}", record)?,
+      (None, None) => {}
+    }
+    if let Some(column) = &self.column {
+      write!(f, ", column {}", column)?;
+    }
+    write!(f, ": {}", self.reason)
+  }
+}
+
+/// Displays row errors one per line, up to `MAX_DISPLAYED_ERRORS`.
+pub struct RowErrors<'a>(pub &'a [RowError]);
+
+impl fmt::Display for RowErrors<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} seed rows could not be loaded:", self.0.len())?;
+    for err in self.0.iter().take(MAX_DISPLAYED_ERRORS) {
+      write!(f, "\n  {}", err)?;
+    }
+    if self.0.len() > MAX_DISPLAYED_ERRORS {
+      write!(f, "\n  and {} more", self.0.len() - MAX_DISPLAYED_ERRORS)?;
+    }
+    Ok(())
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct LoadReport {
+  /// Each file read and the number of rows it inserted.
+  pub files: Vec<(String, usize)>,
+  pub errors: Vec<RowError>,
+}
+
+impl LoadReport {
+  /// Fails with every row error collected so far, if any.
+  pub fn into_result(self) -> Result<LoadReport, DbError> {
+    if self.errors.is_empty() {
+      Ok(self)
+    } else {
+      Err(DbError::Seed(self.errors))
+    }
+  }
+}
+
+/// Streams the rows of `path` to `insert` in batches of `BATCH_SIZE`,
+/// recording the rows that do not deserialize in `report`. Fails only when
+/// the file cannot be read or an insert fails.
+pub fn load_file<T, F>(path: &Path, report: &mut LoadReport, insert: F) -> Result<usize, DbError>
+where
+  T: DeserializeOwned,
+  F: FnMut(Vec<T>) -> QueryResult<usize>,
+{
+  let format = SeedFormat::from_path(path).ok_or_else(|| {
+    DbError::Seed(vec![RowError::file(
+      path,
+      "Unknown seed file format, expected .tsv, .csv, .json or .ndjson".to_string(),
+    )])
+  })?;
+  let file =
+    File::open(path).map_err(|err| DbError::Seed(vec![RowError::file(path, err.to_string())]))?;
+  let mut batch = Batch {
+    file: path.display().to_string(),
+    rows: Vec::with_capacity(BATCH_SIZE),
+    insert,
+    inserted: 0,
+    errors: &mut report.errors,
+  };
+  let reader = BufReader::new(file);
+  match format {
+    SeedFormat::Tsv => read_delimited(reader, b'\t', &mut batch)?,
+    SeedFormat::Csv => read_delimited(reader, b',', &mut batch)?,
+    SeedFormat::Json => read_json(reader, &mut batch)?,
+    SeedFormat::Ndjson => read_ndjson(reader, &mut batch)?,
+  }
+  batch.flush()?;
+  let inserted = batch.inserted;
+  report.files.push((path.display().to_string(), inserted));
+  Ok(inserted)
+}
+
+/// Streams JSON objects that are not read from a file, such as API
+/// responses, to `insert` like the rows of a JSON seed file. `name` stands
+/// for the file in `report`. An `Err` value stops the stream.
+pub fn load_values<T, I, F>(
+  name: &str,
+  values: I,
+  report: &mut LoadReport,
+  insert: F,
+) -> Result<usize, DbError>
+where
+  T: DeserializeOwned,
+  I: IntoIterator<Item = Result<Value, DbError>>,
+  F: FnMut(Vec<T>) -> QueryResult<usize>,
+{
+  let mut batch = Batch {
+    file: name.to_string(),
+    rows: Vec::with_capacity(BATCH_SIZE),
+    insert,
+    inserted: 0,
+    errors: &mut report.errors,
+  };
+  for (index, value) in values.into_iter().enumerate() {
+    batch.push_json(value?, index as u64 + 1, None)?;
+  }
+  batch.flush()?;
+  let inserted = batch.inserted;
+  report.files.push((name.to_string(), inserted));
+  Ok(inserted)
+}
+
+struct Batch<'r, T, F> {
+  file: String,
+  rows: Vec<T>,
+  insert: F,
+  inserted: usize,
+  errors: &'r mut Vec<RowError>,
+}
+
+impl<T, F> Batch<'_, T, F>
+where
+  T: DeserializeOwned,
+  F: FnMut(Vec<T>) -> QueryResult<usize>,
+{
+  fn push(
+    &mut self,
+    record: &StringRecord,
+    headers: &StringRecord,
+    number: u64,
+    line: Option<u64>,
+  ) -> Result<(), DbError> {
+    match record.deserialize::<T>(Some(headers)) {
+      Ok(row) => {
+        self.rows.push(row);
+        if self.rows.len() >= BATCH_SIZE {
+          self.flush()?;
+        }
+      }
+      Err(err) => {
+        let (column, reason) = match err.kind() {
+          csv::ErrorKind::Deserialize { err, .. } => (
+            err
+              .field()
+              .and_then(|field| headers.get(field as usize))
+              .map(str::to_string),
+            err.kind().to_string(),
+          ),
+          _ => (None, err.to_string()),
+        };
+        self.errors.push(RowError {
+          file: self.file.clone(),
+          record: Some(number),
+          line,
+          column,
+          reason,
+        })
+      }
+    }
+    Ok(())
+  }
+
+  /// Pushes a flat JSON object like a record whose headers are its keys.
+  fn push_json(&mut self, value: Value, number: u64, line: Option<u64>) -> Result<(), DbError> {
+    let object = match value {
+      Value::Object(object) => object,
+      _ => {
+        self.error(number, line, "Expected an object".to_string());
+        return Ok(());
+      }
+    };
+    let mut headers = StringRecord::new();
+    let mut record = StringRecord::new();
+    for (key, value) in &object {
+      match json_field(value) {
+        Some(field) => {
+          headers.push_field(key);
+          record.push_field(&field);
+        }
+        None => {
+          self.errors.push(RowError {
+            file: self.file.clone(),
+            record: Some(number),
+            line,
+            column: Some(key.clone()),
+            reason: "Nested arrays and objects are not supported".to_string(),
+          });
+          return Ok(());
+        }
+      }
+    }
+    self.push(&record, &headers, number, line)
+  }
+
+  fn error(&mut self, number: u64, line: Option<u64>, reason: String) {
+    self.errors.push(RowError {
+      file: self.file.clone(),
+      record: Some(number),
+      line,
+      column: None,
+      reason,
+    });
+  }
+
+  fn flush(&mut self) -> Result<(), DbError> {
+    if !self.rows.is_empty() {
+      let rows = std::mem::replace(&mut self.rows, Vec::with_capacity(BATCH_SIZE));
+      self.inserted += (self.insert)(rows)?;
+    }
+    Ok(())
+  }
+}
+
+fn read_delimited<R, T, F>(reader: R, delimiter: u8, batch: &mut Batch<T, F>) -> Result<(), DbError>
+where
+  R: Read,
+  T: DeserializeOwned,
+  F: FnMut(Vec<T>) -> QueryResult<usize>,
+{
+  let mut reader = csv::ReaderBuilder::new()
+    .delimiter(delimiter)
+    .from_reader(reader);
+  let headers = reader
+    .headers()
+    .map_err(|err| {
+      DbError::Seed(vec![RowError::file(
+        Path::new(&batch.file),
+        err.to_string(),
+      )])
+    })?
+    .clone();
+  let mut record = StringRecord::new();
+  let mut number = 0;
+  loop {
+    number += 1;
+    match reader.read_record(&mut record) {
+      Ok(false) => return Ok(()),
+      Ok(true) => {
+        let line = record.position().map(|pos| pos.line());
+        batch.push(&record, &headers, number, line)?;
+      }
+      Err(err) => {
+        let line = err.position().map(|pos| pos.line());
+        let fatal = !matches!(
+          err.kind(),
+          csv::ErrorKind::UnequalLengths { .. } | csv::ErrorKind::Utf8 { .. }
+        );
+        batch.error(number, line, err.to_string());
+        if fatal {
+          return Ok(());
+        }
+      }
+    }
+  }
+}
+
+/// A JSON value as a record field: scalars as their text, null as an
+/// empty field like an empty TSV cell.
+fn json_field(value: &Value) -> Option<String> {
+  match value {
+    Value::Null => Some(String::new()),
+    Value::String(s) => Some(s.clone()),
+    Value::Bool(_) | Value::Number(_) => Some(value.to_string()),
+    Value::Array(_) | Value::Object(_) => None,
+  }
+}
+
+fn read_json<R, T, F>(reader: R, batch: &mut Batch<T, F>) -> Result<(), DbError>
+where
+  R: Read,
+  T: DeserializeOwned,
+  F: FnMut(Vec<T>) -> QueryResult<usize>,
+{
+  let mut failure = None;
+  let mut number = 0;
+  let mut each = |value: Value| {
+    number += 1;
+    batch.push_json(value, number, None)
+  };
+  let mut deserializer = serde_json::Deserializer::from_reader(reader);
+  let result = de::Deserializer::deserialize_seq(
+    &mut deserializer,
+    EachElement {
+      each: &mut each,
+      failure: &mut failure,
+    },
+  );
+  if let Some(err) = failure {
+    return Err(err);
+  }
+  result.map_err(|err| {
+    DbError::Seed(vec![RowError::file(
+      Path::new(&batch.file),
+      err.to_string(),
+    )])
+  })
+}
+
+fn read_ndjson<R, T, F>(reader: R, batch: &mut Batch<T, F>) -> Result<(), DbError>
+where
+  R: BufRead,
+  T: DeserializeOwned,
+  F: FnMut(Vec<T>) -> QueryResult<usize>,
+{
+  let mut number = 0;
+  for (index, line) in reader.lines().enumerate() {
+    let line_number = Some(index as u64 + 1);
+    let line = line.map_err(|err| {
+      DbError::Seed(vec![RowError::file(
+        Path::new(&batch.file),
+        err.to_string(),
+      )])
+    })?;
+    if line.trim().is_empty() {
+      continue;
+    }
+    number += 1;
+    match serde_json::from_str(&line) {
+      Ok(value) => batch.push_json(value, number, line_number)?,
+      Err(err) => batch.error(number, line_number, err.to_string()),
+    }
+  }
+  Ok(())
+}
+
+/// Hands the elements of a JSON array to `each` one at a time, so the
+/// file is never held in memory.
+struct EachElement<'a, E> {
+  each: &'a mut E,
+  /// The error of `each` that stopped the stream.
+  failure: &'a mut Option<DbError>,
+}
+
+impl<'de, E> Visitor<'de> for EachElement<'_, E>
+where
+  E: FnMut(Value) -> Result<(), DbError>,
+{
+  type Value = ();
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "an array of objects")
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+    while let Some(value) = seq.next_element::<Value>()? {
+      if let Err(err) = (self.each)(value) {
+        *self.failure = Some(err);
+        return Err(de::Error::custom("stopped after an insert failed"));
+      }
+    }
+    Ok(())
+  }
+}
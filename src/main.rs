@@ -1,8 +1,19 @@
 #![feature(decl_macro, proc_macro_hygiene)]
 
-use juniper::IntrospectionFormat;
-use rick_morty_back::db;
-use rick_morty_back::graphql::{self, Ctx, GraphqlSchema};
+use juniper::{graphql_value, FieldError};
+use rick_morty_back::auth::{AuthError, Authenticator};
+use rick_morty_back::config::Config;
+use rick_morty_back::export::{self, ExportFormat};
+use rick_morty_back::graphql::{
+    self,
+    http::{BatchRequest, HttpRequest},
+    request::RequestIdHeader,
+    schema_diff::{self, Severity},
+    subscriptions, AppState, Ctx, GraphqlSchema, SchemaFormat,
+};
+use rick_morty_back::import::{self, ImportOptions};
+use rick_morty_back::upstream::{self, ApiSource};
+use rick_morty_back::{db, graphql_ws, rest};
 use rocket::{http, response::content, State};
 
 #[rocket::get("/graphql")]
@@ -10,44 +21,365 @@ fn graphiql() -> content::Html<String> {
     juniper_rocket::playground_source("/graphql")
 }
 
-#[rocket::get("/graphql?<request>")]
+#[rocket::get("/graphql?<query>&<operation_name>&<variables>")]
 fn get_graphql_handler(
-    context: State<Ctx>,
-    request: juniper_rocket::GraphQLRequest,
+    context: Result<Ctx, AuthError>,
+    query: String,
+    operation_name: Option<String>,
+    variables: Option<String>,
     schema: State<GraphqlSchema>,
 ) -> juniper_rocket::GraphQLResponse {
-    request.execute(&schema, &context)
+    let variables = match variables.map(|v| serde_json::from_str(&v)).transpose() {
+        Ok(variables) => variables,
+        Err(err) => return bad_request(format!("Invalid variables: {}", err)),
+    };
+    let request = HttpRequest::new(query, operation_name, variables);
+    execute(context, &BatchRequest::Single(request), &schema)
 }
 
-#[rocket::post("/graphql", data = "<request>")]
+#[rocket::post("/graphql", format = "json", data = "<body>")]
 fn post_graphql_handler(
-    context: State<Ctx>,
-    request: juniper_rocket::GraphQLRequest,
+    context: Result<Ctx, AuthError>,
+    body: rocket::Data,
     schema: State<GraphqlSchema>,
 ) -> juniper_rocket::GraphQLResponse {
-    request.execute(&schema, &context)
+    let mut text = String::new();
+    if let Err(err) = body.open().read_to_string(&mut text) {
+        return bad_request(err.to_string());
+    }
+    match serde_json::from_str(&text) {
+        Ok(request) => execute(context, &request, &schema),
+        Err(err) => bad_request(err.to_string()),
+    }
 }
 
-use std::fs::File;
-use std::io::BufWriter;
+fn bad_request(message: String) -> juniper_rocket::GraphQLResponse {
+    juniper_rocket::GraphQLResponse::custom(
+        http::Status::BadRequest,
+        serde_json::json!({ "errors": [{ "message": message }] }),
+    )
+}
 
-fn main() {
-    let counts = db::init_db().unwrap();
+fn execute(
+    context: Result<Ctx, AuthError>,
+    request: &BatchRequest,
+    schema: &GraphqlSchema,
+) -> juniper_rocket::GraphQLResponse {
+    match context {
+        Ok(context) => {
+            let (ok, response) = request.execute(schema, &context);
+            let status = if ok {
+                http::Status::Ok
+            } else {
+                http::Status::BadRequest
+            };
+            juniper_rocket::GraphQLResponse::custom(status, response)
+        }
+        Err(AuthError::Unmanaged) => juniper_rocket::GraphQLResponse::custom(
+            http::Status::InternalServerError,
+            serde_json::json!({ "errors": [{ "message": AuthError::Unmanaged.to_string() }] }),
+        ),
+        Err(err) => juniper_rocket::GraphQLResponse::error(FieldError::new(
+            err,
+            graphql_value!({ "code": "UNAUTHENTICATED" }),
+        )),
+    }
+}
+
+use diesel::pg::PgConnection;
+use rick_morty_back::db::{DataSource, DbConnection, DbPool, DirSource, JsonDump};
+use rick_morty_back::migrations;
+use rick_morty_back::snapshot;
+use std::fmt::Display;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(about = "GraphQL and REST API over the Rick and Morty dataset")]
+struct Opt {
+    /// TOML config file [default: $RMB_CONFIG, else rick-morty-back.toml
+    /// when it exists]
+    #[structopt(long, global = true, parse(from_os_str))]
+    config: Option<PathBuf>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Starts the GraphQL, REST and subscription servers (the default)
+    Serve(ServeOpt),
+    /// Loads the seed data when the database is empty
+    Seed(SourceOpt),
+    /// Replaces every row with the seed data
+    Reset(SourceOpt),
+    /// Upserts the seed data by id, keeping the rows edited since
+    Import {
+        #[structopt(flatten)]
+        source: SourceOpt,
+        /// Also delete the rows missing from the seed data
+        #[structopt(long)]
+        prune: bool,
+        /// Write the changes, which are only printed otherwise
+        #[structopt(long)]
+        apply: bool,
+    },
+    /// Prints the number of characters, locations and episodes
+    Counts,
+    /// Lists the rows referencing missing locations, characters or episodes
+    /// and fails when there are any
+    Check,
+    /// Writes every table as seed files that `reset` can load back
+    Export {
+        #[structopt(long, default_value = "tsv", possible_values = &["tsv", "json", "ndjson"])]
+        format: ExportFormat,
+        /// Defaults to a new directory of `export_dir`
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Writes the GraphQL schema as introspection JSON or SDL
+    ExportSchema {
+        #[structopt(long, default_value = "json", possible_values = &["json", "sdl"])]
+        format: SchemaFormat,
+        /// Defaults to standard output
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Compares the schema with a committed introspection result and fails
+    /// when the changes break existing clients
+    DiffSchema {
+        #[structopt(long, default_value = "graphql_schema.json", parse(from_os_str))]
+        against: PathBuf,
+        /// Also list the safe changes
+        #[structopt(short, long)]
+        verbose: bool,
+    },
+    /// Applies or reverts the embedded migrations
+    Migrate(MigrateCommand),
+    /// Saves and restores named copies of every row
+    Snapshot(SnapshotCommand),
+}
+
+#[derive(StructOpt)]
+struct ServeOpt {
+    #[structopt(long)]
+    host: Option<String>,
+    #[structopt(long)]
+    port: Option<u16>,
+    /// Port of the graphql-ws subscription endpoint
+    #[structopt(long)]
+    ws_port: Option<u16>,
+    /// Origin allowed by CORS, may be repeated; replaces the configured ones
+    #[structopt(long = "cors-origin")]
+    cors_origins: Vec<String>,
+    /// Seed files used by the `resetDb` mutation
+    #[structopt(long, parse(from_os_str))]
+    data_dir: Option<PathBuf>,
+}
+
+impl ServeOpt {
+    /// Overrides the settings given on the command line.
+    fn apply(&self, config: &mut Config) {
+        let server = &mut config.server;
+        if let Some(host) = &self.host {
+            server.host = host.clone();
+        }
+        server.port = self.port.unwrap_or(server.port);
+        server.ws_port = self.ws_port.unwrap_or(server.ws_port);
+        if !self.cors_origins.is_empty() {
+            server.cors_origins = self.cors_origins.clone();
+        }
+        if let Some(data_dir) = &self.data_dir {
+            config.data_dir = data_dir.clone();
+        }
+    }
+}
+
+/// Where `seed`, `reset` and `import` read the rows from, the seed files
+/// of `data_dir` unless told otherwise.
+#[derive(StructOpt)]
+struct SourceOpt {
+    #[structopt(long, parse(from_os_str))]
+    data_dir: Option<PathBuf>,
+    /// A single JSON file holding every table
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["data-dir", "api"])]
+    dump: Option<PathBuf>,
+    /// A REST API shaped like rickandmortyapi.com's, which is the default
+    /// base URL
+    #[structopt(long, conflicts_with = "data-dir")]
+    api: Option<Option<String>>,
+}
+
+impl SourceOpt {
+    fn open(&self, config: &Config) -> Box<dyn DataSource> {
+        match (&self.dump, &self.api) {
+            (Some(dump), _) => Box::new(JsonDump::new(dump.clone())),
+            (None, Some(api)) => Box::new(ApiSource::new(
+                api.as_deref().unwrap_or(upstream::UPSTREAM_API),
+            )),
+            (None, None) => Box::new(DirSource(config.data_dir.clone())),
+        }
+    }
+}
+
+#[derive(StructOpt)]
+enum MigrateCommand {
+    /// Applies every pending migration
+    Up,
+    /// Reverts the latest applied migration
+    Down,
+    /// Lists the migrations and whether they are applied
+    Status,
+    /// Reverts and reapplies the latest applied migration
+    Redo,
+}
+
+#[derive(StructOpt)]
+enum SnapshotCommand {
+    /// Saves every row under a new name
+    Create {
+        name: String,
+    },
+    /// Replaces every row with a snapshot
+    Restore {
+        name: String,
+    },
+    /// Lists the snapshots, newest first
+    List,
+    Delete {
+        name: String,
+    },
+}
+
+fn fail(err: impl Display) -> ! {
+    eprintln!("{}", err);
+    process::exit(1);
+}
+
+/// A connection to a database whose schema is up to date, applying the
+/// pending migrations first when `database.auto_migrate` is set.
+fn connect(pool: &DbPool, config: &Config) -> DbConnection {
+    let conn = pool.get().unwrap_or_else(|err| fail(err));
+    if config.database.auto_migrate {
+        for name in migrations::run_pending(&conn).unwrap_or_else(|err| fail(err)) {
+            println!("Applied migration {}", name);
+        }
+    } else if let Err(err) = migrations::check_up_to_date(&conn) {
+        fail(err);
+    }
+    conn
+}
+
+fn migrate(command: MigrateCommand, conn: &PgConnection) {
+    let result = match command {
+        MigrateCommand::Up => migrations::run_pending(conn).map(|names| {
+            for name in names {
+                println!("Applied {}", name);
+            }
+        }),
+        MigrateCommand::Down => {
+            migrations::revert_latest(conn).map(|m| println!("Reverted {}", m.name))
+        }
+        MigrateCommand::Redo => migrations::redo(conn).map(|m| println!("Redid {}", m.name)),
+        MigrateCommand::Status => migrations::status(conn).map(|status| {
+            for (migration, applied) in status {
+                println!("[{}] {}", if applied { "X" } else { " " }, migration.name);
+            }
+        }),
+    };
+    if let Err(err) = result {
+        fail(err);
+    }
+}
+
+fn manage_snapshots(command: SnapshotCommand, conn: &PgConnection) {
+    let result = match command {
+        SnapshotCommand::Create { name } => {
+            snapshot::create_snapshot(conn, &name).map(|s| println!("Created {}", s))
+        }
+        SnapshotCommand::Restore { name } => {
+            snapshot::restore_snapshot(conn, &name).map(|s| println!("Restored {}", s))
+        }
+        SnapshotCommand::List => snapshot::list_snapshots(conn)
+            .map(|snapshots| {
+                for s in snapshots {
+                    println!("{}", s);
+                }
+            })
+            .map_err(From::from),
+        SnapshotCommand::Delete { name } => {
+            snapshot::delete_snapshot(conn, &name).map(|()| println!("Deleted {}", name))
+        }
+    };
+    if let Err(err) = result {
+        fail(err);
+    }
+}
+
+fn export_schema(format: SchemaFormat, output: Option<PathBuf>) {
+    let contents = graphql::print_schema(format);
+    match output {
+        Some(path) => fs::write(&path, contents).unwrap_or_else(|err| fail(err)),
+        None => println!("{}", contents.trim_end()),
+    }
+}
+
+fn diff_schema(against: PathBuf, verbose: bool) {
+    let committed: serde_json::Value = fs::read_to_string(&against)
+        .map_err(|err| err.to_string())
+        .and_then(|contents| serde_json::from_str(&contents).map_err(|err| err.to_string()))
+        .unwrap_or_else(|err| fail(format!("Cannot read {}: {}", against.display(), err)));
+    let changes = schema_diff::diff_schemas(&committed, &graphql::introspect_schema());
+    for change in &changes {
+        if verbose || change.severity != Severity::Safe {
+            println!("{}", change);
+        }
+    }
+    let count = |severity| changes.iter().filter(|c| c.severity == severity).count();
+    println!(
+        "{} breaking, {} dangerous and {} safe changes against {}",
+        count(Severity::Breaking),
+        count(Severity::Dangerous),
+        count(Severity::Safe),
+        against.display()
+    );
+    if schema_diff::has_breaking(&changes) {
+        process::exit(1);
+    }
+}
+
+fn serve(config: Config, pool: DbPool) {
+    let conn = connect(&pool, &config);
+    let counts = db::get_all_counts(&conn).unwrap_or_else(|err| fail(err));
     println!("{:?}", counts);
+    if counts.character == 0 {
+        println!("Warning: the database is empty, load it with `rick-morty-back seed`");
+    }
+    let dangling = db::check_integrity(&conn)
+        .unwrap_or_else(|err| fail(format!("Cannot check the references: {}", err)));
+    for reference in &dangling {
+        println!("Warning: {}", reference);
+    }
+    drop(conn);
 
-    let schema_graphql = graphql::create_schema();
-    let ctx = Ctx::new(counts);
+    let authenticator = Authenticator::new(&config.auth);
+    if !authenticator.is_configured() {
+        println!("Warning: auth.jwt_secret is not set, every mutation will be rejected");
+    }
 
-    let (res, _errors) =
-        juniper::introspect(&schema_graphql, &ctx, IntrospectionFormat::default()).unwrap();
-    let file = File::create("graphql_schema.json").unwrap();
-    serde_json::to_writer_pretty(BufWriter::new(file), &res).unwrap();
+    let schema_graphql = graphql::create_schema();
+    let server = config.server.clone();
+    let database_url = config.database.url.clone();
+    let app = Arc::new(AppState::new(pool, Arc::new(config)));
+    subscriptions::listen_for_changes(database_url, app.events());
+    graphql_ws::serve(format!("{}:{}", server.host, server.ws_port), app.clone());
 
     let cors = rocket_cors::CorsOptions {
-        allowed_origins: rocket_cors::AllowedOrigins::some_exact(&[
-            "http://localhost:4200",
-            "http://localhost:8000",
-        ]),
+        allowed_origins: rocket_cors::AllowedOrigins::some_exact(&server.cors_origins),
         allowed_methods: vec![http::Method::Get, http::Method::Post, http::Method::Options]
             .into_iter()
             .map(From::from)
@@ -59,18 +391,124 @@ fn main() {
     .to_cors()
     .expect("Error building CORS");
     let rocket_config = rocket::config::Config::build(rocket::config::Environment::Development)
-        .address("127.0.0.1")
-        .port(8000)
+        .address(server.host)
+        .port(server.port)
         .finalize()
         .unwrap();
 
     rocket::custom(rocket_config)
-        .manage(ctx)
+        .manage(app)
+        .manage(authenticator)
         .manage(schema_graphql)
         .mount(
             "/",
             rocket::routes![graphiql, get_graphql_handler, post_graphql_handler],
         )
+        .mount("/api", rest::routes())
+        .mount("/exports", export::routes())
         .attach(cors)
+        .attach(RequestIdHeader)
         .launch();
 }
+
+fn main() {
+    let opt = Opt::from_args();
+    let command = opt
+        .command
+        .unwrap_or_else(|| Command::Serve(ServeOpt::from_iter(&["serve"])));
+    match command {
+        Command::ExportSchema { format, output } => return export_schema(format, output),
+        Command::DiffSchema { against, verbose } => return diff_schema(against, verbose),
+        _ => {}
+    }
+
+    let mut config = Config::load(opt.config.as_deref()).unwrap_or_else(|err| fail(err));
+    match &command {
+        Command::Serve(serve_opt) => serve_opt.apply(&mut config),
+        Command::Seed(SourceOpt {
+            data_dir: Some(data_dir),
+            ..
+        })
+        | Command::Reset(SourceOpt {
+            data_dir: Some(data_dir),
+            ..
+        })
+        | Command::Import {
+            source:
+                SourceOpt {
+                    data_dir: Some(data_dir),
+                    ..
+                },
+            ..
+        } => config.data_dir = data_dir.clone(),
+        _ => {}
+    }
+    config.validate().unwrap_or_else(|err| fail(err));
+
+    let pool = db::create_pool(&config.database).unwrap_or_else(|err| fail(err));
+    match command {
+        Command::Serve(_) => serve(config, pool),
+        Command::Seed(source) => {
+            let counts = db::init_db(&connect(&pool, &config), &mut *source.open(&config))
+                .unwrap_or_else(|err| fail(err));
+            println!("{:?}", counts);
+        }
+        Command::Reset(source) => {
+            let conn = connect(&pool, &config);
+            let mut source = source.open(&config);
+            println!("Loading {}", source.describe());
+            let report = db::reset_db(&conn, &mut *source).unwrap_or_else(|err| fail(err));
+            for (file, rows) in &report.files {
+                println!("Loaded {} rows from {}", rows, file);
+            }
+            println!(
+                "{:?}",
+                db::get_all_counts(&conn).unwrap_or_else(|err| fail(err))
+            );
+        }
+        Command::Import {
+            source,
+            prune,
+            apply,
+        } => {
+            let mut source = source.open(&config);
+            println!("Importing {}", source.describe());
+            let options = ImportOptions { prune, apply };
+            let report = import::import_db(&connect(&pool, &config), &mut *source, options)
+                .unwrap_or_else(|err| fail(err));
+            for changes in &report.tables {
+                println!("{}", changes);
+            }
+            if report.dry_run {
+                println!("Dry run, nothing was written: pass --apply to write the changes");
+            }
+        }
+        Command::Counts => {
+            println!(
+                "{:?}",
+                db::get_all_counts(&connect(&pool, &config)).unwrap_or_else(|err| fail(err))
+            );
+        }
+        Command::Check => {
+            let dangling = db::check_integrity(&connect(&pool, &config))
+                .unwrap_or_else(|err| fail(format!("Cannot check the references: {}", err)));
+            for reference in &dangling {
+                println!("{}", reference);
+            }
+            if !dangling.is_empty() {
+                fail(format!("{} dangling references", dangling.len()));
+            }
+        }
+        Command::Export { format, output } => {
+            let dir = output.unwrap_or_else(|| config.export_dir.join(export::export_name(format)));
+            let report = export::export_db(&connect(&pool, &config), &dir, format)
+                .unwrap_or_else(|err| fail(err));
+            for (file, rows) in &report.files {
+                println!("Wrote {} rows to {}", rows, file.display());
+            }
+        }
+        Command::Migrate(command) => migrate(command, &pool.get().unwrap_or_else(|err| fail(err))),
+        Command::Snapshot(command) => manage_snapshots(command, &connect(&pool, &config)),
+        Command::ExportSchema { .. } | Command::DiffSchema { .. } => unreachable!(),
+    }
+}
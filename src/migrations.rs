@@ -0,0 +1,227 @@
+//! The `migrations/` directory, embedded in the binary.
+//!
+//! Applied versions are recorded in `__diesel_schema_migrations` exactly
+//! like the diesel CLI does, so both can be used on the same database.
+
+use diesel::{connection::SimpleConnection, pg::PgConnection, prelude::*};
+use std::collections::HashSet;
+use std::fmt;
+
+table! {
+  __diesel_schema_migrations (version) {
+    version -> VarChar,
+    run_on -> Timestamp,
+  }
+}
+
+use self::__diesel_schema_migrations::dsl::{__diesel_schema_migrations as applied, version};
+
+pub struct Migration {
+  /// The directory name, e.g. `2020-01-02-153701_character-text-index`.
+  pub name: &'static str,
+  up: &'static str,
+  down: &'static str,
+}
+
+impl Migration {
+  /// The version diesel derives from the directory name: the part before
+  /// the first `_`, without dashes.
+  pub fn version(&self) -> String {
+    self
+      .name
+      .split('_')
+      .next()
+      .unwrap_or_default()
+      .replace('-', "")
+  }
+}
+
+macro_rules! embed_migrations {
+  ($($name:expr),* $(,)?) => {
+    &[$(Migration {
+      name: $name,
+      up: include_str!(concat!("../migrations/", $name, "/up.sql")),
+      down: include_str!(concat!("../migrations/", $name, "/down.sql")),
+    }),*]
+  };
+}
+
+/// Every migration, oldest first. New migrations must be added here.
+pub const MIGRATIONS: &[Migration] = embed_migrations![
+  "00000000000000_diesel_initial_setup",
+  "2019-12-22-204642_create_location",
+  "2019-12-22-204721_create_character",
+  "2019-12-22-204754_create_episode",
+  "2019-12-22-204801_create_character_episode",
+  "2020-01-02-153701_character-text-index",
+  "2020-01-12-120000_character-enum-checks",
+  "2020-01-19-120000_notify-row-changes",
+  "2020-01-26-120000_entity-counts",
+  "2020-02-02-120000_create-snapshot",
+  "2020-02-09-120000_snapshot-schema-version",
+  "2020-02-16-120000_episode-air-date-check",
+  "2020-02-23-120000_notify-changed-rows",
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+  Query(diesel::result::Error),
+  /// A migration's SQL failed; its transaction was rolled back.
+  Failed {
+    name: &'static str,
+    err: diesel::result::Error,
+  },
+  /// The database lacks migrations this binary needs.
+  Pending(Vec<&'static str>),
+  /// The latest applied version is not embedded in this binary.
+  Unknown(String),
+  NothingToRevert,
+}
+
+impl fmt::Display for MigrationError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      MigrationError::Query(err) => write!(f, "{}", err),
+      MigrationError::Failed { name, err } => write!(f, "Migration {} failed: {}", name, err),
+      MigrationError::Pending(names) => write!(
+        f,
+        "The database schema is behind this binary, pending migrations: {}. \
+         Run `rick-morty-back migrate up` or set RMB_DATABASE_AUTO_MIGRATE=true.",
+        names.join(", ")
+      ),
+      // Not `version`, which would match the column of that name.
+      MigrationError::Unknown(unknown) => write!(
+        f,
+        "The database has migration {} which this binary does not know",
+        unknown
+      ),
+      MigrationError::NothingToRevert => write!(f, "No migration has been applied"),
+    }
+  }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<diesel::result::Error> for MigrationError {
+  fn from(err: diesel::result::Error) -> Self {
+    MigrationError::Query(err)
+  }
+}
+
+fn setup(conn: &PgConnection) -> QueryResult<()> {
+  conn.batch_execute(
+    "CREATE TABLE IF NOT EXISTS __diesel_schema_migrations (
+       version VARCHAR(50) PRIMARY KEY NOT NULL,
+       run_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+     );",
+  )
+}
+
+fn applied_versions(conn: &PgConnection) -> QueryResult<HashSet<String>> {
+  setup(conn)?;
+  Ok(
+    applied
+      .select(version)
+      .load::<String>(conn)?
+      .into_iter()
+      .collect(),
+  )
+}
+
+/// Every embedded migration and whether it has been applied.
+pub fn status(conn: &PgConnection) -> Result<Vec<(&'static Migration, bool)>, MigrationError> {
+  let versions = applied_versions(conn)?;
+  Ok(
+    MIGRATIONS
+      .iter()
+      .map(|m| (m, versions.contains(&m.version())))
+      .collect(),
+  )
+}
+
+pub fn pending(conn: &PgConnection) -> Result<Vec<&'static Migration>, MigrationError> {
+  Ok(
+    status(conn)?
+      .into_iter()
+      .filter(|(_, is_applied)| !is_applied)
+      .map(|(m, _)| m)
+      .collect(),
+  )
+}
+
+/// The version of the most recently applied migration, which names the
+/// schema of the database.
+pub fn latest_applied(conn: &PgConnection) -> QueryResult<Option<String>> {
+  Ok(applied_versions(conn)?.into_iter().max())
+}
+
+/// Fails with `MigrationError::Pending` when some migration is missing.
+pub fn check_up_to_date(conn: &PgConnection) -> Result<(), MigrationError> {
+  let pending = pending(conn)?;
+  if pending.is_empty() {
+    Ok(())
+  } else {
+    Err(MigrationError::Pending(
+      pending.iter().map(|m| m.name).collect(),
+    ))
+  }
+}
+
+fn run(migration: &'static Migration, conn: &PgConnection) -> Result<(), MigrationError> {
+  conn
+    .transaction::<(), diesel::result::Error, _>(|| {
+      conn.batch_execute(migration.up)?;
+      diesel::insert_into(applied)
+        .values(version.eq(migration.version()))
+        .execute(conn)?;
+      Ok(())
+    })
+    .map_err(|err| MigrationError::Failed {
+      name: migration.name,
+      err,
+    })
+}
+
+fn revert(migration: &'static Migration, conn: &PgConnection) -> Result<(), MigrationError> {
+  conn
+    .transaction::<(), diesel::result::Error, _>(|| {
+      conn.batch_execute(migration.down)?;
+      diesel::delete(applied.filter(version.eq(migration.version()))).execute(conn)?;
+      Ok(())
+    })
+    .map_err(|err| MigrationError::Failed {
+      name: migration.name,
+      err,
+    })
+}
+
+/// Applies every pending migration in order, returning their names.
+pub fn run_pending(conn: &PgConnection) -> Result<Vec<&'static str>, MigrationError> {
+  let mut names = vec![];
+  for migration in pending(conn)? {
+    run(migration, conn)?;
+    names.push(migration.name);
+  }
+  Ok(names)
+}
+
+/// Reverts the most recently applied migration, returning it.
+pub fn revert_latest(conn: &PgConnection) -> Result<&'static Migration, MigrationError> {
+  let latest = applied_versions(conn)?
+    .into_iter()
+    .max()
+    .ok_or(MigrationError::NothingToRevert)?;
+  let migration = MIGRATIONS
+    .iter()
+    .find(|m| m.version() == latest)
+    .ok_or(MigrationError::Unknown(latest))?;
+  revert(migration, conn)?;
+  Ok(migration)
+}
+
+/// Reverts and reapplies the most recently applied migration.
+pub fn redo(conn: &PgConnection) -> Result<&'static Migration, MigrationError> {
+  let migration = revert_latest(conn)?;
+  run(migration, conn)?;
+  Ok(migration)
+}
@@ -0,0 +1,574 @@
+//! REST endpoints with the same shape as https://rickandmortyapi.com/api,
+//! for clients written against the public API.
+//!
+//! One difference: rows have no creation time here, so `created` is always
+//! `CREATED`. Resource URLs start with `server.public_url`.
+
+use crate::graphql::{
+  character_model::{Character, CHARACTER_COLUMNS},
+  episode_model::Episode,
+  filters::{contains_pattern, exact_pattern},
+  location_model::Location,
+  AppState,
+};
+use crate::schema::{character, character_episode, episode, location};
+use diesel::{
+  dsl::count_star,
+  pg::{Pg, PgConnection},
+  prelude::*,
+  r2d2::PoolError,
+  sql_types::Bool,
+};
+use rocket::{
+  get,
+  http::{uri::Uri, Status},
+  request::{self, FromRequest, LenientForm, Request},
+  response::{content, status},
+  FromForm, Outcome, Route, State,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The `created` of every row: the Unix epoch parses like the timestamps
+/// of upstream, and no client mistakes it for a real creation time.
+const CREATED: &str = "1970-01-01T00:00:00.000Z";
+
+pub fn routes() -> Vec<Route> {
+  rocket::routes![
+    characters,
+    characters_by_id,
+    locations,
+    locations_by_id,
+    episodes,
+    episodes_by_id
+  ]
+}
+
+// ######### RESPONSES ###############
+
+pub struct ApiError {
+  status: Status,
+  message: String,
+}
+
+impl ApiError {
+  fn new(status: Status, message: &str) -> ApiError {
+    ApiError {
+      status,
+      message: message.to_string(),
+    }
+  }
+}
+
+/// The details of database errors are logged, not sent to the client.
+impl From<diesel::result::Error> for ApiError {
+  fn from(err: diesel::result::Error) -> Self {
+    eprintln!("REST request failed: {}", err);
+    ApiError::new(Status::InternalServerError, "Internal server error")
+  }
+}
+
+impl From<PoolError> for ApiError {
+  fn from(err: PoolError) -> Self {
+    eprintln!("REST request got no database connection: {}", err);
+    ApiError::new(Status::ServiceUnavailable, "The database is unavailable")
+  }
+}
+
+type ApiResult = Result<Value, ApiError>;
+type JsonResponse = status::Custom<content::Json<String>>;
+
+fn respond(result: ApiResult) -> JsonResponse {
+  match result {
+    Ok(value) => status::Custom(Status::Ok, content::Json(value.to_string())),
+    Err(err) => status::Custom(
+      err.status,
+      content::Json(json!({ "error": err.message }).to_string()),
+    ),
+  }
+}
+
+/// `<public_url>/api`, used to build the resource URLs in responses.
+/// Taken from the config rather than the `Host` header, which the client
+/// controls.
+pub struct BaseUrl(String);
+
+impl BaseUrl {
+  fn url(&self, resource: &str, id: i32) -> String {
+    format!("{}/{}/{}", self.0, resource, id)
+  }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for BaseUrl {
+  type Error = ();
+
+  fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+    let app = match request.guard::<State<Arc<AppState>>>() {
+      Outcome::Success(app) => app,
+      _ => {
+        eprintln!("AppState is not managed by Rocket");
+        return Outcome::Failure((Status::InternalServerError, ()));
+      }
+    };
+    Outcome::Success(BaseUrl(format!("{}/api", app.config().server.public_url())))
+  }
+}
+
+/// Builds the `info` object, rejecting pages past the end like upstream.
+fn page_info(
+  base: &BaseUrl,
+  resource: &str,
+  count: i64,
+  page: i64,
+  page_size: i64,
+  params: &[(&str, &Option<String>)],
+) -> ApiResult {
+  let pages = (count + page_size - 1) / page_size;
+  if page < 1 || page > pages {
+    return Err(ApiError::new(Status::NotFound, "There is nothing here"));
+  }
+  let filters: String = params
+    .iter()
+    .filter_map(|(key, value)| {
+      value
+        .as_ref()
+        .map(|v| format!("&{}={}", key, Uri::percent_encode(v)))
+    })
+    .collect();
+  let link = |page: i64| format!("{}/{}/?page={}{}", base.0, resource, page, filters);
+  Ok(json!({
+    "count": count,
+    "pages": pages,
+    "next": if page < pages { Some(link(page + 1)) } else { None },
+    "prev": if page > 1 { Some(link(page - 1)) } else { None },
+  }))
+}
+
+/// Parses `1`, `1,2,3` or `[1,2,3]`. Lists always produce an array
+/// response, even with a single element, like upstream.
+fn parse_ids(raw: &str) -> Result<(Vec<i32>, bool), ApiError> {
+  let is_list = raw.contains(',') || raw.starts_with('[');
+  let ids = raw
+    .trim_start_matches('[')
+    .trim_end_matches(']')
+    .split(',')
+    .filter(|id| !id.trim().is_empty())
+    .map(|id| id.trim().parse::<i32>())
+    .collect::<Result<Vec<i32>, _>>()
+    .map_err(|_| ApiError::new(Status::BadRequest, "Hey! you must provide an id"))?;
+  Ok((ids, is_list))
+}
+
+fn by_ids(mut values: Vec<Value>, is_list: bool, not_found: &str) -> ApiResult {
+  if is_list {
+    Ok(Value::Array(values))
+  } else if values.is_empty() {
+    Err(ApiError::new(Status::NotFound, not_found))
+  } else {
+    Ok(values.remove(0))
+  }
+}
+
+fn group_pairs(pairs: Vec<(i32, i32)>) -> HashMap<i32, Vec<i32>> {
+  let mut map: HashMap<i32, Vec<i32>> = HashMap::new();
+  for (key, value) in pairs {
+    map.entry(key).or_insert_with(Vec::new).push(value);
+  }
+  map
+}
+
+fn urls(base: &BaseUrl, resource: &str, ids: Option<&Vec<i32>>) -> Vec<String> {
+  ids
+    .map(|ids| ids.iter().map(|id| base.url(resource, *id)).collect())
+    .unwrap_or_default()
+}
+
+// ######### CHARACTER ###############
+
+#[derive(FromForm)]
+pub struct CharacterQuery {
+  page: Option<i64>,
+  name: Option<String>,
+  status: Option<String>,
+  species: Option<String>,
+  #[form(field = "type")]
+  type_: Option<String>,
+  gender: Option<String>,
+}
+
+type CharacterExpr = Box<dyn BoxableExpression<character::table, Pg, SqlType = Bool>>;
+
+fn character_filter(query: &CharacterQuery) -> CharacterExpr {
+  let mut expr: CharacterExpr = Box::new(character::id.eq(character::id));
+  if let Some(name) = &query.name {
+    expr = Box::new(expr.and(character::name.ilike(contains_pattern(name))));
+  }
+  if let Some(status) = &query.status {
+    expr = Box::new(expr.and(character::status.ilike(exact_pattern(status))));
+  }
+  if let Some(species) = &query.species {
+    expr = Box::new(expr.and(character::species.ilike(exact_pattern(species))));
+  }
+  if let Some(type_) = &query.type_ {
+    expr = Box::new(expr.and(character::type_.ilike(exact_pattern(type_))));
+  }
+  if let Some(gender) = &query.gender {
+    expr = Box::new(expr.and(character::gender.ilike(exact_pattern(gender))));
+  }
+  expr
+}
+
+fn characters_json(
+  characters: &[Character],
+  base: &BaseUrl,
+  conn: &PgConnection,
+) -> Result<Vec<Value>, ApiError> {
+  let ids: Vec<i32> = characters.iter().map(|c| c.id).collect();
+  let location_ids: Vec<i32> = characters
+    .iter()
+    .flat_map(|c| c.origin_id.into_iter().chain(c.location_id))
+    .collect();
+  let location_names: HashMap<i32, String> = location::table
+    .filter(location::id.eq_any(&location_ids))
+    .select((location::id, location::name))
+    .load::<(i32, String)>(conn)?
+    .into_iter()
+    .collect();
+  let episodes = group_pairs(
+    character_episode::table
+      .filter(character_episode::character_id.eq_any(&ids))
+      .select((
+        character_episode::character_id,
+        character_episode::episode_id,
+      ))
+      .order(character_episode::episode_id)
+      .load(conn)?,
+  );
+
+  let location_ref = |id: Option<i32>| match id {
+    Some(id) => json!({
+      "name": location_names.get(&id).cloned().unwrap_or_default(),
+      "url": base.url("location", id),
+    }),
+    None => json!({ "name": "unknown", "url": "" }),
+  };
+  Ok(
+    characters
+      .iter()
+      .map(|c| {
+        json!({
+          "id": c.id,
+          "name": c.name,
+          "status": c.status.as_str(),
+          "species": c.species,
+          "type": c.type_.clone().unwrap_or_default(),
+          "gender": c.gender.as_str(),
+          "origin": location_ref(c.origin_id),
+          "location": location_ref(c.location_id),
+          "episode": urls(base, "episode", episodes.get(&c.id)),
+          "url": base.url("character", c.id),
+          "created": CREATED,
+        })
+      })
+      .collect(),
+  )
+}
+
+fn load_characters(query: &CharacterQuery, base: &BaseUrl, app: &AppState) -> ApiResult {
+  let conn = app.conn()?;
+  let page = query.page.unwrap_or(1);
+  let page_size = app.config().pagination.rest_page_size;
+  let count: i64 = character::table
+    .filter(character_filter(query))
+    .select(count_star())
+    .get_result(&conn)?;
+  let info = page_info(
+    base,
+    "character",
+    count,
+    page,
+    page_size,
+    &[
+      ("name", &query.name),
+      ("status", &query.status),
+      ("species", &query.species),
+      ("type", &query.type_),
+      ("gender", &query.gender),
+    ],
+  )?;
+  let results: Vec<Character> = character::table
+    .select(CHARACTER_COLUMNS)
+    .filter(character_filter(query))
+    .order(character::id)
+    .offset((page - 1) * page_size)
+    .limit(page_size)
+    .load(&conn)?;
+  Ok(json!({
+    "info": info,
+    "results": characters_json(&results, base, &conn)?,
+  }))
+}
+
+fn load_characters_by_id(ids: &str, base: &BaseUrl, app: &AppState) -> ApiResult {
+  let (ids, is_list) = parse_ids(ids)?;
+  let conn = app.conn()?;
+  let results: Vec<Character> = character::table
+    .select(CHARACTER_COLUMNS)
+    .filter(character::id.eq_any(&ids))
+    .order(character::id)
+    .load(&conn)?;
+  by_ids(
+    characters_json(&results, base, &conn)?,
+    is_list,
+    "Character not found",
+  )
+}
+
+#[get("/character?<query..>")]
+fn characters(
+  query: LenientForm<CharacterQuery>,
+  base: BaseUrl,
+  app: State<Arc<AppState>>,
+) -> JsonResponse {
+  respond(load_characters(&query, &base, &app))
+}
+
+#[get("/character/<ids>")]
+fn characters_by_id(ids: String, base: BaseUrl, app: State<Arc<AppState>>) -> JsonResponse {
+  respond(load_characters_by_id(&ids, &base, &app))
+}
+
+// ######### LOCATION ###############
+
+#[derive(FromForm)]
+pub struct LocationQuery {
+  page: Option<i64>,
+  name: Option<String>,
+  #[form(field = "type")]
+  type_: Option<String>,
+  dimension: Option<String>,
+}
+
+type LocationExpr = Box<dyn BoxableExpression<location::table, Pg, SqlType = Bool>>;
+
+fn location_filter(query: &LocationQuery) -> LocationExpr {
+  let mut expr: LocationExpr = Box::new(location::id.eq(location::id));
+  if let Some(name) = &query.name {
+    expr = Box::new(expr.and(location::name.ilike(contains_pattern(name))));
+  }
+  if let Some(type_) = &query.type_ {
+    expr = Box::new(expr.and(location::type_.ilike(exact_pattern(type_))));
+  }
+  if let Some(dimension) = &query.dimension {
+    expr = Box::new(expr.and(location::dimension.ilike(exact_pattern(dimension))));
+  }
+  expr
+}
+
+fn locations_json(
+  locations: &[Location],
+  base: &BaseUrl,
+  conn: &PgConnection,
+) -> Result<Vec<Value>, ApiError> {
+  let ids: Vec<i32> = locations.iter().map(|l| l.id).collect();
+  let residents: Vec<(Option<i32>, i32)> = character::table
+    .filter(character::location_id.eq_any(&ids))
+    .select((character::location_id, character::id))
+    .order(character::id)
+    .load(conn)?;
+  let residents = group_pairs(
+    residents
+      .into_iter()
+      .filter_map(|(location_id, id)| location_id.map(|location_id| (location_id, id)))
+      .collect(),
+  );
+  Ok(
+    locations
+      .iter()
+      .map(|l| {
+        json!({
+          "id": l.id,
+          "name": l.name,
+          "type": l.type_,
+          "dimension": l.dimension,
+          "residents": urls(base, "character", residents.get(&l.id)),
+          "url": base.url("location", l.id),
+          "created": CREATED,
+        })
+      })
+      .collect(),
+  )
+}
+
+fn load_locations(query: &LocationQuery, base: &BaseUrl, app: &AppState) -> ApiResult {
+  let conn = app.conn()?;
+  let page = query.page.unwrap_or(1);
+  let page_size = app.config().pagination.rest_page_size;
+  let count: i64 = location::table
+    .filter(location_filter(query))
+    .select(count_star())
+    .get_result(&conn)?;
+  let info = page_info(
+    base,
+    "location",
+    count,
+    page,
+    page_size,
+    &[
+      ("name", &query.name),
+      ("type", &query.type_),
+      ("dimension", &query.dimension),
+    ],
+  )?;
+  let results: Vec<Location> = location::table
+    .filter(location_filter(query))
+    .order(location::id)
+    .offset((page - 1) * page_size)
+    .limit(page_size)
+    .load(&conn)?;
+  Ok(json!({
+    "info": info,
+    "results": locations_json(&results, base, &conn)?,
+  }))
+}
+
+fn load_locations_by_id(ids: &str, base: &BaseUrl, app: &AppState) -> ApiResult {
+  let (ids, is_list) = parse_ids(ids)?;
+  let conn = app.conn()?;
+  let results: Vec<Location> = location::table
+    .filter(location::id.eq_any(&ids))
+    .order(location::id)
+    .load(&conn)?;
+  by_ids(
+    locations_json(&results, base, &conn)?,
+    is_list,
+    "Location not found",
+  )
+}
+
+#[get("/location?<query..>")]
+fn locations(
+  query: LenientForm<LocationQuery>,
+  base: BaseUrl,
+  app: State<Arc<AppState>>,
+) -> JsonResponse {
+  respond(load_locations(&query, &base, &app))
+}
+
+#[get("/location/<ids>")]
+fn locations_by_id(ids: String, base: BaseUrl, app: State<Arc<AppState>>) -> JsonResponse {
+  respond(load_locations_by_id(&ids, &base, &app))
+}
+
+// ######### EPISODE ###############
+
+#[derive(FromForm)]
+pub struct EpisodeQuery {
+  page: Option<i64>,
+  name: Option<String>,
+  episode: Option<String>,
+}
+
+type EpisodeExpr = Box<dyn BoxableExpression<episode::table, Pg, SqlType = Bool>>;
+
+fn episode_filter(query: &EpisodeQuery) -> EpisodeExpr {
+  let mut expr: EpisodeExpr = Box::new(episode::id.eq(episode::id));
+  if let Some(name) = &query.name {
+    expr = Box::new(expr.and(episode::name.ilike(contains_pattern(name))));
+  }
+  if let Some(code) = &query.episode {
+    expr = Box::new(expr.and(episode::code.ilike(contains_pattern(code))));
+  }
+  expr
+}
+
+fn episodes_json(
+  episodes: &[Episode],
+  base: &BaseUrl,
+  conn: &PgConnection,
+) -> Result<Vec<Value>, ApiError> {
+  let ids: Vec<i32> = episodes.iter().map(|e| e.id).collect();
+  let characters = group_pairs(
+    character_episode::table
+      .filter(character_episode::episode_id.eq_any(&ids))
+      .select((
+        character_episode::episode_id,
+        character_episode::character_id,
+      ))
+      .order(character_episode::character_id)
+      .load(conn)?,
+  );
+  Ok(
+    episodes
+      .iter()
+      .map(|e| {
+        json!({
+          "id": e.id,
+          "name": e.name,
+          "air_date": e.air_date,
+          "episode": e.code,
+          "characters": urls(base, "character", characters.get(&e.id)),
+          "url": base.url("episode", e.id),
+          "created": CREATED,
+        })
+      })
+      .collect(),
+  )
+}
+
+fn load_episodes(query: &EpisodeQuery, base: &BaseUrl, app: &AppState) -> ApiResult {
+  let conn = app.conn()?;
+  let page = query.page.unwrap_or(1);
+  let page_size = app.config().pagination.rest_page_size;
+  let count: i64 = episode::table
+    .filter(episode_filter(query))
+    .select(count_star())
+    .get_result(&conn)?;
+  let info = page_info(
+    base,
+    "episode",
+    count,
+    page,
+    page_size,
+    &[("name", &query.name), ("episode", &query.episode)],
+  )?;
+  let results: Vec<Episode> = episode::table
+    .filter(episode_filter(query))
+    .order(episode::id)
+    .offset((page - 1) * page_size)
+    .limit(page_size)
+    .load(&conn)?;
+  Ok(json!({
+    "info": info,
+    "results": episodes_json(&results, base, &conn)?,
+  }))
+}
+
+fn load_episodes_by_id(ids: &str, base: &BaseUrl, app: &AppState) -> ApiResult {
+  let (ids, is_list) = parse_ids(ids)?;
+  let conn = app.conn()?;
+  let results: Vec<Episode> = episode::table
+    .filter(episode::id.eq_any(&ids))
+    .order(episode::id)
+    .load(&conn)?;
+  by_ids(
+    episodes_json(&results, base, &conn)?,
+    is_list,
+    "Episode not found",
+  )
+}
+
+#[get("/episode?<query..>")]
+fn episodes(
+  query: LenientForm<EpisodeQuery>,
+  base: BaseUrl,
+  app: State<Arc<AppState>>,
+) -> JsonResponse {
+  respond(load_episodes(&query, &base, &app))
+}
+
+#[get("/episode/<ids>")]
+fn episodes_by_id(ids: String, base: BaseUrl, app: State<Arc<AppState>>) -> JsonResponse {
+  respond(load_episodes_by_id(&ids, &base, &app))
+}
@@ -1,4 +1,7 @@
 table! {
+    use diesel::sql_types::*;
+    use crate::full_text_search::types::*;
+
     character (id) {
         id -> Int4,
         name -> Varchar,
@@ -9,10 +12,14 @@ table! {
         type_ -> Nullable<Varchar>,
         origin_id -> Nullable<Int4>,
         location_id -> Nullable<Int4>,
+        search_text -> TsVector,
     }
 }
 
 table! {
+    use diesel::sql_types::*;
+    use crate::full_text_search::types::*;
+
     character_episode (character_id, episode_id) {
         character_id -> Int4,
         episode_id -> Int4,
@@ -20,6 +27,19 @@ table! {
 }
 
 table! {
+    use diesel::sql_types::*;
+    use crate::full_text_search::types::*;
+
+    entity_count (table_name) {
+        table_name -> Varchar,
+        count -> Int4,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::full_text_search::types::*;
+
     episode (id) {
         id -> Int4,
         name -> Varchar,
@@ -29,6 +49,9 @@ table! {
 }
 
 table! {
+    use diesel::sql_types::*;
+    use crate::full_text_search::types::*;
+
     location (id) {
         id -> Int4,
         name -> Varchar,
@@ -38,12 +61,26 @@ table! {
     }
 }
 
+table! {
+    use diesel::sql_types::*;
+    use crate::full_text_search::types::*;
+
+    snapshot (name) {
+        name -> Varchar,
+        created_at -> Timestamptz,
+        data -> Jsonb,
+        schema_version -> Varchar,
+    }
+}
+
 joinable!(character_episode -> character (character_id));
 joinable!(character_episode -> episode (episode_id));
 
 allow_tables_to_appear_in_same_query!(
     character,
     character_episode,
+    entity_count,
     episode,
     location,
+    snapshot,
 );
@@ -0,0 +1,221 @@
+//! Named copies of the whole dataset, kept in the `snapshot` table so a
+//! demo or a test can return to any state, not only to the seed files.
+//!
+//! Each snapshot holds the rows of the four main tables as JSON arrays.
+//! Both taking and restoring one are single statements per table run by
+//! Postgres, so no row goes through this process. The rows only fit the
+//! schema they were taken from, so a snapshot records the latest migration
+//! applied then and is only restored into a database at that migration.
+
+use crate::db;
+use crate::migrations;
+use crate::schema::{character, character_episode, episode, location, snapshot};
+use diesel::{
+  pg::PgConnection,
+  prelude::*,
+  result::{DatabaseErrorKind, Error},
+  sql_types::Text,
+};
+use std::fmt;
+
+/// A snapshot without its rows.
+#[derive(Debug, QueryableByName, juniper::GraphQLObject)]
+pub struct Snapshot {
+  #[sql_type = "diesel::sql_types::Text"]
+  pub name: String,
+  /// When the snapshot was taken, in UTC
+  #[sql_type = "diesel::sql_types::Text"]
+  pub created_at: String,
+  #[sql_type = "diesel::sql_types::Int4"]
+  pub locations: i32,
+  #[sql_type = "diesel::sql_types::Int4"]
+  pub characters: i32,
+  #[sql_type = "diesel::sql_types::Int4"]
+  pub episodes: i32,
+  #[sql_type = "diesel::sql_types::Int4"]
+  pub character_episodes: i32,
+  /// Version of the latest migration applied when the snapshot was taken
+  #[sql_type = "diesel::sql_types::Text"]
+  pub schema_version: String,
+}
+
+impl fmt::Display for Snapshot {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{} ({}): {} locations, {} characters, {} episodes, {} character episodes",
+      self.name,
+      self.created_at,
+      self.locations,
+      self.characters,
+      self.episodes,
+      self.character_episodes
+    )
+  }
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+  Query(Error),
+  NotFound(String),
+  /// Snapshots are never overwritten; delete the old one first.
+  Exists(String),
+  InvalidName(String),
+  /// The snapshot was taken at another migration than the database is at.
+  SchemaMismatch {
+    name: String,
+    snapshot: String,
+    database: String,
+  },
+}
+
+impl fmt::Display for SnapshotError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      SnapshotError::Query(err) => write!(f, "{}", err),
+      SnapshotError::NotFound(name) => write!(f, "No snapshot named {:?}", name),
+      SnapshotError::Exists(name) => write!(f, "A snapshot named {:?} already exists", name),
+      SnapshotError::InvalidName(name) => write!(
+        f,
+        "Invalid snapshot name {:?}: use 1 to 100 characters without surrounding spaces",
+        name
+      ),
+      SnapshotError::SchemaMismatch {
+        name,
+        snapshot,
+        database,
+      } => write!(
+        f,
+        "Snapshot {:?} was taken at migration {} but the database is at {}",
+        name, snapshot, database
+      ),
+    }
+  }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<Error> for SnapshotError {
+  fn from(err: Error) -> Self {
+    SnapshotError::Query(err)
+  }
+}
+
+const SELECT_SNAPSHOTS: &str = r#"
+  SELECT "name",
+    to_char("created_at" AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS"Z"') AS "created_at",
+    jsonb_array_length("data"->'location') AS "locations",
+    jsonb_array_length("data"->'character') AS "characters",
+    jsonb_array_length("data"->'episode') AS "episodes",
+    jsonb_array_length("data"->'character_episode') AS "character_episodes",
+    "schema_version"
+  FROM "snapshot"
+"#;
+
+/// Every snapshot, newest first.
+pub fn list_snapshots(conn: &PgConnection) -> QueryResult<Vec<Snapshot>> {
+  diesel::sql_query(format!(
+    r#"{} ORDER BY "created_at" DESC, "name""#,
+    SELECT_SNAPSHOTS
+  ))
+  .load(conn)
+}
+
+pub fn find_snapshot(conn: &PgConnection, name: &str) -> Result<Snapshot, SnapshotError> {
+  diesel::sql_query(format!(r#"{} WHERE "name" = $1"#, SELECT_SNAPSHOTS))
+    .bind::<Text, _>(name)
+    .get_result(conn)
+    .optional()?
+    .ok_or_else(|| SnapshotError::NotFound(name.to_string()))
+}
+
+/// Copies the four tables into a new snapshot, in one statement so the
+/// copy is consistent, along with the version of their schema.
+pub fn create_snapshot(conn: &PgConnection, name: &str) -> Result<Snapshot, SnapshotError> {
+  if name.is_empty() || name.len() > 100 || name.trim() != name {
+    return Err(SnapshotError::InvalidName(name.to_string()));
+  }
+  let schema_version = migrations::latest_applied(conn)?.unwrap_or_default();
+  let inserted = diesel::sql_query(
+    r#"
+    INSERT INTO "snapshot" ("name", "schema_version", "data")
+    SELECT $1, $2, jsonb_build_object(
+      'location', (SELECT coalesce(jsonb_agg(l ORDER BY l."id"), '[]') FROM "location" l),
+      'character', (
+        SELECT coalesce(jsonb_agg(to_jsonb(c) - 'search_text' ORDER BY c."id"), '[]')
+        FROM "character" c
+      ),
+      'episode', (SELECT coalesce(jsonb_agg(e ORDER BY e."id"), '[]') FROM "episode" e),
+      'character_episode', (
+        SELECT coalesce(jsonb_agg(ce ORDER BY ce."character_id", ce."episode_id"), '[]')
+        FROM "character_episode" ce
+      )
+    )
+    "#,
+  )
+  .bind::<Text, _>(name)
+  .bind::<Text, _>(schema_version)
+  .execute(conn);
+  match inserted {
+    Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+      return Err(SnapshotError::Exists(name.to_string()))
+    }
+    result => result?,
+  };
+  find_snapshot(conn, name)
+}
+
+/// Replaces every row with the rows of the snapshot, in one transaction,
+/// and moves the id sequences past the restored ids like `db::reset_db`.
+/// Fails without touching any row when the snapshot was taken at another
+/// migration.
+pub fn restore_snapshot(conn: &PgConnection, name: &str) -> Result<Snapshot, SnapshotError> {
+  conn.transaction(|| {
+    let restored = find_snapshot(conn, name)?;
+    let schema_version = migrations::latest_applied(conn)?.unwrap_or_default();
+    if restored.schema_version != schema_version {
+      return Err(SnapshotError::SchemaMismatch {
+        name: restored.name,
+        snapshot: restored.schema_version,
+        database: schema_version,
+      });
+    }
+    diesel::delete(character_episode::table).execute(conn)?;
+    diesel::delete(episode::table).execute(conn)?;
+    diesel::delete(character::table).execute(conn)?;
+    diesel::delete(location::table).execute(conn)?;
+    // `search_text` is left to its trigger.
+    for (table, columns) in &[
+      ("location", r#""id", "name", "type", "dimension""#),
+      (
+        "character",
+        r#""id", "name", "status", "species", "gender", "type", "origin_id", "location_id""#,
+      ),
+      ("episode", r#""id", "name", "air_date", "code""#),
+      ("character_episode", r#""character_id", "episode_id""#),
+    ] {
+      diesel::sql_query(format!(
+        r#"
+        INSERT INTO "{table}" ({columns})
+        SELECT {columns} FROM jsonb_populate_recordset(
+          NULL::"{table}",
+          (SELECT "data"->'{table}' FROM "snapshot" WHERE "name" = $1)
+        )
+        "#,
+        table = table,
+        columns = columns
+      ))
+      .bind::<Text, _>(name)
+      .execute(conn)?;
+    }
+    db::reset_sequences(conn)?;
+    Ok(restored)
+  })
+}
+
+pub fn delete_snapshot(conn: &PgConnection, name: &str) -> Result<(), SnapshotError> {
+  match diesel::delete(snapshot::table.find(name)).execute(conn)? {
+    0 => Err(SnapshotError::NotFound(name.to_string())),
+    _ => Ok(()),
+  }
+}
@@ -0,0 +1,217 @@
+//! Seed rows fetched page by page from the REST API of
+//! rickandmortyapi.com, replacing the `raw-data/ss.py` and `load_db.py`
+//! scripts.
+//!
+//! Any server with the same shape works too, including the `/api` of this
+//! one, which makes a running instance a local fixture of the upstream
+//! API: `reset --api http://localhost:8000/api` round-trips its data.
+
+use crate::db::{DataSource, DbError, Insert};
+use crate::graphql::{
+  character_model::Character,
+  episode_model::{CharacterEpisode, Episode},
+  location_model::Location,
+};
+use crate::loader::{load_values, LoadReport};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+pub const UPSTREAM_API: &str = "https://rickandmortyapi.com/api";
+
+/// Longest a page may take, from connecting to the last byte, so an API
+/// that stops answering fails the load instead of hanging it.
+const PAGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A paginated API answering `<base_url>/location`, `/character` and
+/// `/episode` with `{"info": {"next": ...}, "results": [...]}`.
+pub struct ApiSource {
+  base_url: String,
+  agent: ureq::Agent,
+  /// The character-episode pairs seen while fetching the characters, as
+  /// upstream has no resource of its own for them.
+  pairs: Option<Vec<Value>>,
+}
+
+impl ApiSource {
+  pub fn new(base_url: &str) -> ApiSource {
+    ApiSource {
+      base_url: base_url.trim_end_matches('/').to_string(),
+      agent: ureq::AgentBuilder::new().timeout(PAGE_TIMEOUT).build(),
+      pairs: None,
+    }
+  }
+
+  fn url(&self, resource: &str) -> String {
+    format!("{}/{}", self.base_url, resource)
+  }
+
+  fn pages(&self, resource: &str) -> Pages {
+    Pages {
+      agent: self.agent.clone(),
+      next: Some(self.url(resource)),
+      results: VecDeque::new(),
+    }
+  }
+}
+
+impl DataSource for ApiSource {
+  fn describe(&self) -> String {
+    self.base_url.clone()
+  }
+
+  fn locations(
+    &mut self,
+    report: &mut LoadReport,
+    insert: Insert<Location>,
+  ) -> Result<usize, DbError> {
+    let rows = self.pages("location").map(|page| page.map(location_row));
+    load_values(&self.url("location"), rows, report, insert)
+  }
+
+  fn characters(
+    &mut self,
+    report: &mut LoadReport,
+    insert: Insert<Character>,
+  ) -> Result<usize, DbError> {
+    let mut pairs = vec![];
+    let rows = self.pages("character").map(|page| {
+      page.map(|character| {
+        pairs.extend(episode_pairs(&character));
+        character_row(character)
+      })
+    });
+    let inserted = load_values(&self.url("character"), rows, report, insert)?;
+    self.pairs = Some(pairs);
+    Ok(inserted)
+  }
+
+  fn episodes(
+    &mut self,
+    report: &mut LoadReport,
+    insert: Insert<Episode>,
+  ) -> Result<usize, DbError> {
+    let rows = self.pages("episode").map(|page| page.map(episode_row));
+    load_values(&self.url("episode"), rows, report, insert)
+  }
+
+  /// The pairs of the characters already fetched, else of a new walk
+  /// through the characters.
+  fn character_episodes(
+    &mut self,
+    report: &mut LoadReport,
+    insert: Insert<CharacterEpisode>,
+  ) -> Result<usize, DbError> {
+    let pairs = match self.pairs.take() {
+      Some(pairs) => pairs,
+      None => {
+        let mut pairs = vec![];
+        for character in self.pages("character") {
+          pairs.extend(episode_pairs(&character?));
+        }
+        pairs
+      }
+    };
+    let name = format!("{} episodes", self.url("character"));
+    load_values(&name, pairs.into_iter().map(Ok), report, insert)
+  }
+}
+
+/// The results of every page of a resource, fetching the next page once
+/// the previous one is consumed.
+struct Pages {
+  agent: ureq::Agent,
+  next: Option<String>,
+  results: VecDeque<Value>,
+}
+
+impl Iterator for Pages {
+  type Item = Result<Value, DbError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    loop {
+      if let Some(result) = self.results.pop_front() {
+        return Some(Ok(result));
+      }
+      let url = self.next.take()?;
+      match fetch_page(&self.agent, &url) {
+        Ok((results, next)) => {
+          self.results.extend(results);
+          self.next = next;
+        }
+        Err(err) => return Some(Err(err)),
+      }
+    }
+  }
+}
+
+fn fetch_page(agent: &ureq::Agent, url: &str) -> Result<(Vec<Value>, Option<String>), DbError> {
+  let error = |message: String| DbError::Source {
+    name: url.to_string(),
+    message,
+  };
+  let mut page: Value = agent
+    .get(url)
+    .call()
+    .map_err(|err| error(err.to_string()))?
+    .into_json()
+    .map_err(|err| error(err.to_string()))?;
+  let results = match page["results"].take() {
+    Value::Array(results) => results,
+    _ => return Err(error("Expected a page with a results array".to_string())),
+  };
+  let next = page["info"]["next"].as_str().map(str::to_string);
+  Ok((results, next))
+}
+
+/// The id at the end of a resource URL, null for the empty URL upstream
+/// gives unknown locations.
+fn id_from_url(url: &Value) -> Value {
+  match url.as_str().and_then(|url| url.rsplit('/').next()) {
+    Some(id) if !id.is_empty() => Value::String(id.to_string()),
+    _ => Value::Null,
+  }
+}
+
+fn location_row(location: Value) -> Value {
+  json!({
+    "id": location["id"],
+    "name": location["name"],
+    "type": location["type"],
+    "dimension": location["dimension"],
+  })
+}
+
+fn character_row(character: Value) -> Value {
+  json!({
+    "id": character["id"],
+    "name": character["name"],
+    "status": character["status"],
+    "species": character["species"],
+    "type": character["type"],
+    "gender": character["gender"],
+    "origin_id": id_from_url(&character["origin"]["url"]),
+    "location_id": id_from_url(&character["location"]["url"]),
+  })
+}
+
+fn episode_row(episode: Value) -> Value {
+  json!({
+    "id": episode["id"],
+    "name": episode["name"],
+    "air_date": episode["air_date"],
+    "code": episode["episode"],
+  })
+}
+
+fn episode_pairs(character: &Value) -> Vec<Value> {
+  character["episode"]
+    .as_array()
+    .map(|episodes| {
+      episodes
+        .iter()
+        .map(|url| json!({ "character_id": character["id"], "episode_id": id_from_url(url) }))
+        .collect()
+    })
+    .unwrap_or_default()
+}
@@ -0,0 +1,69 @@
+//! Air dates `to_date` cannot read never reach the `episode` table, as a
+//! single one would fail the air date filters and ordering of every query.
+
+mod common;
+
+use diesel::prelude::*;
+use rick_morty_back::db::{self, DbError, JsonDump};
+use rick_morty_back::schema::episode;
+use serde_json::json;
+use std::{env, fs, process};
+
+#[test]
+#[ignore]
+fn seed_rows_with_unreadable_air_dates_are_rejected() {
+  let (_lock, conn) = common::seeded_conn();
+  let before = common::dump(&conn);
+  let path = env::temp_dir().join(format!("air-date-{}.json", process::id()));
+  let episode = |id: &str, air_date: &str| json!({ "id": id, "name": "Pilot", "air_date": air_date, "code": "S01E01" });
+  let tables = json!({
+    "locations": [],
+    "characters": [],
+    "episodes": [
+      episode("1", "December 2, 2013"),
+      episode("2", "Dec 9, 2013"),
+      episode("3", "February 30, 2014"),
+    ],
+    "character_episode_join": [],
+  });
+  fs::write(&path, tables.to_string()).unwrap();
+  let result = db::reset_db(&conn, &mut JsonDump::new(path.clone()));
+  fs::remove_file(&path).unwrap();
+  match result {
+    Err(DbError::Seed(errors)) => {
+      let reasons: Vec<_> = errors.iter().map(|err| err.reason.as_str()).collect();
+      assert_eq!(reasons.len(), 2, "{:?}", reasons);
+      assert!(reasons[0].contains("\"Dec 9, 2013\""), "{}", reasons[0]);
+      assert!(
+        reasons[1].contains("\"February 30, 2014\""),
+        "{}",
+        reasons[1]
+      );
+    }
+    Err(err) => panic!("{}", err),
+    Ok(_) => panic!("The seed rows were loaded"),
+  }
+  assert_eq!(common::dump(&conn), before);
+}
+
+#[test]
+#[ignore]
+fn the_table_rejects_unreadable_air_dates() {
+  let (_lock, conn) = common::seeded_conn();
+  for air_date in &[
+    "Dec 9, 2013",
+    "December 09, 2013",
+    "February 30, 2014",
+    "2013-12-09",
+    "",
+  ] {
+    let updated = diesel::update(episode::table.find(1))
+      .set(episode::air_date.eq(air_date))
+      .execute(&conn);
+    assert!(updated.is_err(), "{:?}", air_date);
+  }
+  diesel::update(episode::table.find(1))
+    .set(episode::air_date.eq("February 29, 2016"))
+    .execute(&conn)
+    .unwrap();
+}
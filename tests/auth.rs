@@ -0,0 +1,59 @@
+//! Mutations check the role of the caller before touching the database,
+//! so these run without one.
+
+use juniper::http::GraphQLRequest;
+use rick_morty_back::auth::{Identity, Role};
+use rick_morty_back::config::Config;
+use rick_morty_back::db;
+use rick_morty_back::graphql::{create_schema, AppState, Ctx};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+fn execute_as(role: Option<Role>, query: &str) -> Value {
+  let app = AppState::new(db::unconnected_pool(), Arc::new(Config::default()));
+  let identity = role.map(|role| Identity {
+    subject: "summer".to_string(),
+    role,
+  });
+  let context = Ctx::new(Arc::new(app), identity, "test".to_string());
+  let request = GraphQLRequest::new(query.to_string(), None, None);
+  serde_json::to_value(request.execute(&create_schema(), &context)).unwrap()
+}
+
+fn error_code(response: &Value) -> &Value {
+  &response["errors"][0]["extensions"]["code"]
+}
+
+const EDITOR_MUTATION: &str = "mutation { characterMutation { __typename } }";
+
+#[test]
+fn anonymous_callers_cannot_mutate() {
+  let response = execute_as(None, EDITOR_MUTATION);
+  assert_eq!(error_code(&response), "UNAUTHENTICATED", "{}", response);
+  assert_eq!(
+    response["errors"][0]["extensions"]["requiredRole"],
+    "editor"
+  );
+}
+
+#[test]
+fn lower_roles_are_forbidden() {
+  let response = execute_as(Some(Role::Reader), EDITOR_MUTATION);
+  assert_eq!(error_code(&response), "FORBIDDEN", "{}", response);
+  let response = execute_as(Some(Role::Editor), "mutation { resetDb }");
+  assert_eq!(error_code(&response), "FORBIDDEN", "{}", response);
+  assert_eq!(response["errors"][0]["extensions"]["requiredRole"], "admin");
+}
+
+#[test]
+fn the_required_role_and_higher_ones_pass() {
+  for role in &[Role::Editor, Role::Admin] {
+    let response = execute_as(Some(*role), EDITOR_MUTATION);
+    assert_eq!(
+      response,
+      json!({ "data": { "characterMutation": { "__typename": "CaracterMutation" } } }),
+      "{}",
+      role
+    );
+  }
+}
@@ -0,0 +1,97 @@
+//! The database of the tests that need one, named by `TEST_DATABASE_URL`.
+//! Its rows are replaced by the seed files of `raw-data/` before each test.
+//! Those tests are ignored by default, run them with
+//! `TEST_DATABASE_URL=... cargo test -- --ignored`.
+
+#![allow(dead_code)]
+
+pub mod statements;
+
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::{pg::PgConnection, prelude::*};
+use juniper::http::GraphQLRequest;
+use rick_morty_back::config::Config;
+use rick_morty_back::graphql::{create_schema, AppState, Ctx};
+use rick_morty_back::{db, migrations};
+use serde_json::Value;
+use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Held by each test while it uses the database, as they share its rows.
+static DATABASE: Mutex<()> = Mutex::new(());
+
+pub fn database_url() -> String {
+  env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set to run the database tests")
+}
+
+pub fn seed_dir() -> PathBuf {
+  PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("raw-data")
+}
+
+/// A connection to the migrated database holding the seed rows, along with
+/// the lock the test holds until it is done.
+pub fn seeded_conn() -> (MutexGuard<'static, ()>, PgConnection) {
+  let url = database_url();
+  let lock = DATABASE
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner());
+  let conn = PgConnection::establish(&url).expect("Cannot connect to TEST_DATABASE_URL");
+  migrations::run_pending(&conn).expect("Cannot migrate the test database");
+  db::reset_db(&conn, &mut db::DirSource(seed_dir())).expect("Cannot seed the test database");
+  (lock, conn)
+}
+
+/// The state of a server on the test database, for the tests holding the
+/// lock of `seeded_conn`.
+pub fn app() -> Arc<AppState> {
+  app_on(&database_url())
+}
+
+/// The state of a server reaching the test database through `url`.
+pub fn app_on(url: &str) -> Arc<AppState> {
+  // Without the `SELECT 1` r2d2 runs on each checkout, the statements
+  // through the pool are only those of the resolvers.
+  let pool = Pool::builder()
+    .max_size(2)
+    .test_on_check_out(false)
+    .build(ConnectionManager::new(url))
+    .expect("Cannot connect to TEST_DATABASE_URL");
+  let config = Config {
+    data_dir: seed_dir(),
+    ..Config::default()
+  };
+  Arc::new(AppState::new(pool, Arc::new(config)))
+}
+
+/// Runs a query like `POST /graphql` without a token, returning the whole
+/// response, `errors` included.
+pub fn execute(app: &Arc<AppState>, query: &str, variables: Value) -> Value {
+  let variables = serde_json::from_value(variables).expect("Invalid variables");
+  let request = GraphQLRequest::new(query.to_string(), None, Some(variables));
+  let context = Ctx::new(app.clone(), None, "test".to_string());
+  serde_json::to_value(request.execute(&create_schema(), &context)).unwrap()
+}
+
+/// Every row of the four tables and the id sequences, to tell whether
+/// anything was written.
+pub fn dump(conn: &PgConnection) -> String {
+  diesel::select(diesel::dsl::sql::<diesel::sql_types::Text>(
+    r#"
+    jsonb_build_array(
+      (SELECT jsonb_agg(l ORDER BY l."id") FROM "location" l),
+      (SELECT jsonb_agg(to_jsonb(c) - 'search_text' ORDER BY c."id") FROM "character" c),
+      (SELECT jsonb_agg(e ORDER BY e."id") FROM "episode" e),
+      (
+        SELECT jsonb_agg(ce ORDER BY ce."character_id", ce."episode_id")
+        FROM "character_episode" ce
+      ),
+      (SELECT "last_value" FROM "location_id_seq"),
+      (SELECT "last_value" FROM "character_id_seq"),
+      (SELECT "last_value" FROM "episode_id_seq")
+    )::text
+    "#,
+  ))
+  .get_result(conn)
+  .expect("Cannot read the test database")
+}
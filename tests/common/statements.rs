@@ -0,0 +1,129 @@
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Codes of the startup packets after which the client sends its real
+/// startup message, once the server declined the encryption.
+const SSL_REQUEST: u32 = 80_877_103;
+const GSSENC_REQUEST: u32 = 80_877_104;
+
+/// A proxy to the test database counting the statements its clients run,
+/// that is the `Execute` messages of the extended query protocol diesel
+/// sends for every query. The `BEGIN`/`COMMIT` of transactions go through
+/// the simple query protocol and are not counted.
+pub struct StatementCounter {
+  url: String,
+  executed: Arc<AtomicUsize>,
+}
+
+impl StatementCounter {
+  /// Listens on a free local port, forwarding every connection to the host
+  /// of `TEST_DATABASE_URL`, which must be reached over TCP.
+  pub fn start() -> StatementCounter {
+    let url = super::database_url();
+    let (prefix, host, rest) = split_host(&url);
+    assert!(!host.is_empty(), "TEST_DATABASE_URL must name a TCP host");
+    let upstream = if host.contains(':') {
+      host.to_string()
+    } else {
+      format!("{}:5432", host)
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Cannot listen on a local port");
+    let port = listener.local_addr().unwrap().port();
+    let executed = Arc::new(AtomicUsize::new(0));
+    let counted = executed.clone();
+    thread::spawn(move || {
+      for client in listener.incoming() {
+        let client = match client {
+          Ok(client) => client,
+          Err(_) => break,
+        };
+        let server = TcpStream::connect(&upstream).expect("Cannot connect to TEST_DATABASE_URL");
+        let (mut from_server, mut to_client) =
+          (server.try_clone().unwrap(), client.try_clone().unwrap());
+        thread::spawn(move || {
+          let _ = std::io::copy(&mut from_server, &mut to_client);
+          let _ = to_client.shutdown(Shutdown::Write);
+        });
+        let counted = counted.clone();
+        thread::spawn(move || forward_counting(client, server, &counted));
+      }
+    });
+    // The proxy cannot read encrypted messages.
+    let separator = if rest.contains('?') { '&' } else { '?' };
+    StatementCounter {
+      url: format!(
+        "{}127.0.0.1:{}{}{}sslmode=disable",
+        prefix, port, rest, separator
+      ),
+      executed,
+    }
+  }
+
+  /// The URL of the database through the proxy.
+  pub fn url(&self) -> &str {
+    &self.url
+  }
+
+  /// The statements executed since the previous call.
+  pub fn take(&self) -> usize {
+    self.executed.swap(0, Ordering::SeqCst)
+  }
+}
+
+/// Splits `postgres://user@host:port/db?options` into the part before the
+/// host, the host with its port and the rest.
+fn split_host(url: &str) -> (&str, &str, &str) {
+  let start = url.find("://").map_or(0, |scheme| scheme + 3);
+  let end = url[start..]
+    .find(|c| c == '/' || c == '?')
+    .map_or(url.len(), |end| start + end);
+  let host = url[start..end]
+    .rfind('@')
+    .map_or(start, |at| start + at + 1);
+  (&url[..host], &url[host..end], &url[end..])
+}
+
+/// Copies what a client sends to the server, counting each `Execute`
+/// message before the server can receive it, so the count is complete
+/// once the client has its response.
+fn forward_counting(mut client: TcpStream, mut server: TcpStream, executed: &AtomicUsize) {
+  let mut buffer = [0; 8192];
+  let mut pending = Vec::new();
+  // The startup packets are the only messages without a type byte.
+  let mut startup = true;
+  loop {
+    let read = match client.read(&mut buffer) {
+      Ok(0) | Err(_) => break,
+      Ok(read) => read,
+    };
+    pending.extend_from_slice(&buffer[..read]);
+    loop {
+      let type_len = if startup { 0 } else { 1 };
+      if pending.len() < type_len + 4 {
+        break;
+      }
+      let len = be_u32(&pending[type_len..]) as usize;
+      if pending.len() < type_len + len {
+        break;
+      }
+      if startup {
+        let code = if len >= 8 { be_u32(&pending[4..]) } else { 0 };
+        startup = code == SSL_REQUEST || code == GSSENC_REQUEST;
+      } else if pending[0] == b'E' {
+        executed.fetch_add(1, Ordering::SeqCst);
+      }
+      pending.drain(..type_len + len);
+    }
+    if server.write_all(&buffer[..read]).is_err() {
+      break;
+    }
+  }
+  let _ = server.shutdown(Shutdown::Write);
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+  u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
@@ -0,0 +1,93 @@
+//! `db::count_rows` reads the counts the triggers keep in `entity_count`,
+//! which must match `COUNT(*)` whatever happened to the rows.
+
+mod common;
+
+use diesel::{dsl::sql, pg::PgConnection, prelude::*, result::Error, sql_types::BigInt};
+use rick_morty_back::db;
+use rick_morty_back::schema::location;
+use std::thread;
+
+fn counted(table: &str, conn: &PgConnection) -> i64 {
+  diesel::select(sql::<BigInt>(&format!(
+    r#"(SELECT count(*) FROM "{}")"#,
+    table
+  )))
+  .get_result(conn)
+  .unwrap()
+}
+
+fn assert_counts_match(conn: &PgConnection) {
+  for table in &["character", "episode", "location"] {
+    assert_eq!(
+      i64::from(db::count_rows(table, conn).unwrap()),
+      counted(table, conn),
+      "{}",
+      table
+    );
+  }
+}
+
+fn insert_location(name: &str, conn: &PgConnection) -> i32 {
+  diesel::insert_into(location::table)
+    .values((
+      location::name.eq(name),
+      location::type_.eq("Planet"),
+      location::dimension.eq("unknown"),
+    ))
+    .returning(location::id)
+    .get_result(conn)
+    .unwrap()
+}
+
+#[test]
+#[ignore]
+fn counts_ignore_rolled_back_writes() {
+  let (_lock, conn) = common::seeded_conn();
+  let before = db::count_rows("location", &conn).unwrap();
+  let rolled_back = conn.transaction::<(), _, _>(|| {
+    insert_location("Rolled back", &conn);
+    diesel::sql_query(r#"DELETE FROM "character_episode" WHERE "episode_id" = 1"#)
+      .execute(&conn)?;
+    diesel::sql_query(r#"DELETE FROM "episode" WHERE "id" = 1"#).execute(&conn)?;
+    assert_eq!(db::count_rows("location", &conn)?, before + 1);
+    Err(Error::RollbackTransaction)
+  });
+  assert_eq!(rolled_back, Err(Error::RollbackTransaction));
+  assert_eq!(db::count_rows("location", &conn).unwrap(), before);
+  assert_counts_match(&conn);
+}
+
+#[test]
+#[ignore]
+fn counts_match_after_concurrent_writers() {
+  let (_lock, conn) = common::seeded_conn();
+  let url = common::database_url();
+  let writers: Vec<_> = (0..4)
+    .map(|writer| {
+      let url = url.clone();
+      thread::spawn(move || {
+        let conn = PgConnection::establish(&url).unwrap();
+        for i in 0..25 {
+          let id = insert_location(&format!("Writer {} #{}", writer, i), &conn);
+          // Every third row is deleted again and every fifth write rolled back.
+          if i % 3 == 0 {
+            diesel::delete(location::table.find(id))
+              .execute(&conn)
+              .unwrap();
+          }
+          if i % 5 == 0 {
+            let _ = conn.transaction::<(), _, _>(|| {
+              insert_location("Rolled back", &conn);
+              Err(Error::RollbackTransaction)
+            });
+          }
+        }
+      })
+    })
+    .collect();
+  for writer in writers {
+    writer.join().unwrap();
+  }
+  assert_counts_match(&conn);
+}
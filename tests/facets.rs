@@ -0,0 +1,51 @@
+//! `characterFacets` names the status and gender values like the filter
+//! arguments, so a value can be passed back as a filter.
+
+mod common;
+
+use serde_json::{json, Value};
+
+#[test]
+#[ignore]
+fn facet_values_are_the_filter_values() {
+  let (_lock, _) = common::seeded_conn();
+  let app = common::app();
+  let facets = |filter: Value| {
+    let response = common::execute(
+      &app,
+      r#"
+      query ($filter: CharacterFilter) {
+        characterFacets(filter: $filter) {
+          status { value count }
+          gender { value count }
+        }
+      }
+      "#,
+      json!({ "filter": filter }),
+    );
+    assert_eq!(response.get("errors"), None, "{}", response);
+    response["data"]["characterFacets"].clone()
+  };
+  let all = facets(Value::Null);
+  for (facet, argument) in &[("status", "status"), ("gender", "gender")] {
+    let values = all[facet].as_array().unwrap();
+    let total: i64 = values.iter().map(|v| v["count"].as_i64().unwrap()).sum();
+    assert_eq!(total, 493, "{}", facet);
+    for value in values {
+      // The other facet counts the rows its filter selects.
+      let other = if *facet == "status" {
+        "gender"
+      } else {
+        "status"
+      };
+      let filtered = facets(json!({ argument.to_string(): [value["value"]] }));
+      let total: i64 = filtered[other]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["count"].as_i64().unwrap())
+        .sum();
+      assert_eq!(json!(total), value["count"], "{} {}", facet, value);
+    }
+  }
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use diesel::{connection::SimpleConnection, pg::PgConnection};
+use rick_morty_back::db::DirSource;
+use rick_morty_back::import::{import_db, ImportOptions, ImportReport};
+
+/// Edits the seeded rows so that an import of the seed files has a change
+/// of every kind to make.
+fn edit_rows(conn: &PgConnection) {
+  conn
+    .batch_execute(
+      r#"
+      UPDATE "character" SET "name" = 'Edited' WHERE "id" = 1;
+      INSERT INTO "location" ("id", "name", "type", "dimension")
+        VALUES (9999, 'Not seeded', 'Planet', 'unknown');
+      SELECT setval('location_id_seq', 9999);
+      DELETE FROM "character_episode" WHERE "character_id" = 1 AND "episode_id" = 1;
+      INSERT INTO "character_episode" ("character_id", "episode_id") VALUES (5, 1), (5, 2);
+      "#,
+    )
+    .unwrap();
+}
+
+fn import(conn: &PgConnection, apply: bool) -> ImportReport {
+  let options = ImportOptions { prune: true, apply };
+  import_db(conn, &mut DirSource(common::seed_dir()), options).unwrap()
+}
+
+fn changes(report: &ImportReport) -> Vec<String> {
+  report.tables.iter().map(ToString::to_string).collect()
+}
+
+#[test]
+#[ignore]
+fn dry_run_counts_the_changes_of_a_real_run_without_writing() {
+  let (_lock, conn) = common::seeded_conn();
+  edit_rows(&conn);
+  let before = common::dump(&conn);
+
+  let dry_run = import(&conn, false);
+  assert!(dry_run.dry_run);
+  assert_eq!(common::dump(&conn), before);
+
+  let real_run = import(&conn, true);
+  assert!(!real_run.dry_run);
+  assert_eq!(changes(&dry_run), changes(&real_run));
+  let character = &real_run.tables[1];
+  assert_eq!((character.updated, character.deleted), (1, 0));
+  let location = &real_run.tables[0];
+  assert_eq!(location.deleted, 1);
+  let pairs = &real_run.tables[3];
+  assert_eq!((pairs.inserted, pairs.deleted), (1, 2));
+  assert_ne!(common::dump(&conn), before);
+}
+
+#[test]
+#[ignore]
+fn prune_keeps_every_pair_of_the_source() {
+  let (_lock, conn) = common::seeded_conn();
+  let before = common::dump(&conn);
+  let report = import(&conn, true);
+  assert!(report.tables.iter().all(|table| table.deleted == 0));
+  assert_eq!(common::dump(&conn), before);
+}
+
+#[test]
+#[ignore]
+fn imports_only_write_when_applied() {
+  let (_lock, conn) = common::seeded_conn();
+  edit_rows(&conn);
+  let before = common::dump(&conn);
+  let report = import_db(
+    &conn,
+    &mut DirSource(common::seed_dir()),
+    ImportOptions::default(),
+  )
+  .unwrap();
+  assert!(report.dry_run);
+  assert!(report.tables[1].updated > 0);
+  assert_eq!(common::dump(&conn), before);
+}
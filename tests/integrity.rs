@@ -0,0 +1,48 @@
+//! `db::check_integrity` lists the rows whose foreign keys point to missing
+//! rows, which the constraints only let in when they were not enforced.
+
+mod common;
+
+use diesel::{prelude::*, result::Error};
+use rick_morty_back::db;
+
+#[test]
+#[ignore]
+fn seed_rows_reference_existing_rows() {
+  let (_lock, conn) = common::seeded_conn();
+  let dangling = db::check_integrity(&conn).unwrap();
+  assert!(dangling.is_empty(), "{:?}", dangling);
+}
+
+#[test]
+#[ignore]
+fn lists_the_references_to_missing_rows() {
+  let (_lock, conn) = common::seeded_conn();
+  let rolled_back = conn.transaction::<(), _, _>(|| {
+    // Skips the foreign key triggers until the end of the transaction.
+    diesel::sql_query("SET LOCAL session_replication_role = replica").execute(&conn)?;
+    diesel::sql_query(r#"UPDATE "character" SET "location_id" = 9001 WHERE "id" = 1"#)
+      .execute(&conn)?;
+    diesel::sql_query(r#"DELETE FROM "episode" WHERE "id" = 31"#).execute(&conn)?;
+    let dangling: Vec<_> = db::check_integrity(&conn)?
+      .iter()
+      .map(ToString::to_string)
+      .collect();
+    assert_eq!(
+      dangling[0],
+      "character 1 references missing id 9001 in column location_id"
+    );
+    assert_eq!(dangling.len(), 15);
+    for reference in &dangling[1..] {
+      assert!(
+        reference.starts_with("character_episode ")
+          && reference.ends_with(" references missing id 31 in column episode_id"),
+        "{}",
+        reference
+      );
+    }
+    Err(Error::RollbackTransaction)
+  });
+  assert_eq!(rolled_back, Err(Error::RollbackTransaction));
+  assert!(db::check_integrity(&conn).unwrap().is_empty());
+}
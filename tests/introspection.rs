@@ -0,0 +1,74 @@
+use rick_morty_back::config::Config;
+use rick_morty_back::db;
+use rick_morty_back::graphql::http::BatchRequest;
+use rick_morty_back::graphql::{create_schema, introspect_schema, AppState, Ctx};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// Runs `body` like `POST /graphql`. Introspection needs no database.
+fn post(body: Value) -> (bool, Value) {
+  let app = AppState::new(db::unconnected_pool(), Arc::new(Config::default()));
+  let context = Ctx::new(Arc::new(app), None, "test".to_string());
+  let request: BatchRequest = serde_json::from_value(body).unwrap();
+  request.execute(&create_schema(), &context)
+}
+
+#[test]
+fn introspection_includes_the_subscription_schema() {
+  let introspection = introspect_schema();
+  let schema = &introspection["__schema"];
+  assert_eq!(schema["queryType"]["name"], "Query");
+  assert_eq!(schema["subscriptionType"]["name"], "Subscription");
+  let names: Vec<&str> = schema["types"]
+    .as_array()
+    .unwrap()
+    .iter()
+    .filter_map(|type_| type_["name"].as_str())
+    .collect();
+  for name in &["Subscription", "CharacterChange", "ChangeKind", "Character"] {
+    assert_eq!(
+      names.iter().filter(|found| *found == name).count(),
+      1,
+      "{}",
+      name
+    );
+  }
+}
+
+/// `export-schema -o graphql_schema.json` regenerates it.
+#[test]
+fn committed_introspection_is_up_to_date() {
+  let path = concat!(env!("CARGO_MANIFEST_DIR"), "/graphql_schema.json");
+  let committed: serde_json::Value =
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+  assert!(
+    committed == introspect_schema(),
+    "graphql_schema.json is stale, run `rick-morty-back export-schema -o graphql_schema.json`"
+  );
+}
+
+#[test]
+fn http_introspection_includes_the_subscription_schema() {
+  let (ok, response) = post(json!({
+    "query": "{ schema: __schema { subscriptionType { name } } }",
+  }));
+  assert!(ok);
+  assert_eq!(
+    response,
+    json!({ "data": { "schema": { "subscriptionType": { "name": "Subscription" } } } })
+  );
+}
+
+#[test]
+fn http_batches_are_rejected_when_a_request_is() {
+  let (ok, response) = post(json!([
+    { "query": "{ __type(name: \"Subscription\") { name } }" },
+    { "query": "{ notAField }" },
+  ]));
+  assert!(!ok);
+  assert_eq!(
+    response[0],
+    json!({ "data": { "__type": { "name": "Subscription" } } })
+  );
+  assert!(response[1]["errors"].is_array());
+}
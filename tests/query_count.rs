@@ -0,0 +1,46 @@
+//! The loaders keep the number of statements of a nested query independent
+//! of the number of rows it returns.
+
+mod common;
+
+use common::statements::StatementCounter;
+use serde_json::json;
+
+const NESTED: &str = r#"
+  query ($id: Int!) {
+    episode(id: $id) {
+      characters {
+        name
+        origin { name }
+        episodes { characters { name } }
+      }
+    }
+  }
+"#;
+
+#[test]
+#[ignore]
+fn nested_characters_take_the_same_statements_for_any_cast() {
+  let (_lock, _) = common::seeded_conn();
+  let statements = StatementCounter::start();
+  let app = common::app_on(statements.url());
+
+  // Episodes of 10, 13 and 65 characters.
+  let runs: Vec<(usize, usize)> = [20, 4, 28]
+    .iter()
+    .map(|id| {
+      statements.take();
+      let response = common::execute(&app, NESTED, json!({ "id": id }));
+      assert_eq!(response.get("errors"), None, "{}", response);
+      let cast = response["data"]["episode"]["characters"]
+        .as_array()
+        .unwrap()
+        .len();
+      (cast, statements.take())
+    })
+    .collect();
+  let casts: Vec<usize> = runs.iter().map(|(cast, _)| *cast).collect();
+  assert_eq!(casts, vec![10, 13, 65]);
+  // The episode, then one statement for each relation of the selection.
+  assert!(runs.iter().all(|(_, count)| *count == 5), "{:?}", runs);
+}
@@ -0,0 +1,90 @@
+//! `searchText` reaches Postgres as a bind parameter of `plainto_tsquery`,
+//! never as SQL, whatever characters it holds.
+
+mod common;
+
+use diesel::prelude::*;
+use rick_morty_back::schema::character;
+use serde_json::json;
+
+const HOSTILE: &[&str] = &[
+  "'); DROP TABLE character; --",
+  "Rick's \"portal",
+  "'''",
+  "\"",
+  "& | ! : *",
+  "rick & !morty | :* (",
+];
+
+#[test]
+#[ignore]
+fn hostile_search_text_runs_as_a_plain_search() {
+  let (_lock, conn) = common::seeded_conn();
+  let app = common::app();
+  for search_text in HOSTILE {
+    let response = common::execute(
+      &app,
+      r#"
+      query ($filter: CharacterFilter!) {
+        charactersFiltered(limit: 10, offset: 0, filter: $filter) {
+          results { id }
+        }
+      }
+      "#,
+      json!({ "filter": { "searchText": search_text } }),
+    );
+    assert_eq!(
+      response.get("errors"),
+      None,
+      "{}: {}",
+      search_text,
+      response
+    );
+    assert!(response["data"]["charactersFiltered"]["results"].is_array());
+  }
+  let characters: i64 = character::table.count().get_result(&conn).unwrap();
+  assert_eq!(characters, 493);
+}
+
+/// The pages of a search, or of a filter alone, list every match once.
+#[test]
+#[ignore]
+fn pages_neither_overlap_nor_skip_rows() {
+  let (_lock, _) = common::seeded_conn();
+  let app = common::app();
+  for filter in &[
+    json!({ "searchText": "rick" }),
+    json!({ "status": ["ALIVE"] }),
+  ] {
+    let page = |offset: i32| {
+      let response = common::execute(
+        &app,
+        r#"
+        query ($filter: CharacterFilter!, $offset: Int!) {
+          charactersFiltered(limit: 7, offset: $offset, filter: $filter) {
+            info { itemCount }
+            results { id }
+          }
+        }
+        "#,
+        json!({ "filter": filter, "offset": offset }),
+      );
+      assert_eq!(response.get("errors"), None, "{}", response);
+      response["data"]["charactersFiltered"].clone()
+    };
+    let count = page(0)["info"]["itemCount"].as_i64().unwrap();
+    let mut ids: Vec<i64> = (0..count as i32)
+      .step_by(7)
+      .flat_map(|offset| {
+        let results = page(offset)["results"].as_array().unwrap().clone();
+        results
+          .into_iter()
+          .map(|result| result["id"].as_i64().unwrap())
+      })
+      .collect();
+    assert_eq!(ids.len() as i64, count, "{}", filter);
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len() as i64, count, "{}", filter);
+  }
+}
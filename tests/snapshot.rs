@@ -0,0 +1,61 @@
+mod common;
+
+use diesel::{connection::SimpleConnection, pg::PgConnection};
+use rick_morty_back::snapshot::{self, SnapshotError};
+
+fn clear_snapshots(conn: &PgConnection) {
+  conn.batch_execute(r#"DELETE FROM "snapshot""#).unwrap();
+}
+
+#[test]
+#[ignore]
+fn restore_returns_to_the_rows_and_sequences_of_create() {
+  let (_lock, conn) = common::seeded_conn();
+  clear_snapshots(&conn);
+  let before = common::dump(&conn);
+  let created = snapshot::create_snapshot(&conn, "seed").unwrap();
+  assert_eq!(created.characters, 493);
+
+  // New rows take their ids from the sequences, which a restore resets.
+  conn
+    .batch_execute(
+      r#"
+      UPDATE "character" SET "name" = 'Edited' WHERE "id" = 1;
+      DELETE FROM "character_episode" WHERE "episode_id" = 1;
+      INSERT INTO "location" ("name", "type", "dimension") VALUES ('New', 'Planet', 'unknown');
+      INSERT INTO "episode" ("name", "air_date", "code") VALUES ('New', 'May 1, 2020', 'S05E01');
+      "#,
+    )
+    .unwrap();
+  assert_ne!(common::dump(&conn), before);
+
+  let restored = snapshot::restore_snapshot(&conn, "seed").unwrap();
+  assert_eq!(restored.name, "seed");
+  assert_eq!(common::dump(&conn), before);
+}
+
+#[test]
+#[ignore]
+fn restore_refuses_a_snapshot_of_another_schema() {
+  let (_lock, conn) = common::seeded_conn();
+  clear_snapshots(&conn);
+  let created = snapshot::create_snapshot(&conn, "old").unwrap();
+  conn
+    .batch_execute(r#"UPDATE "snapshot" SET "schema_version" = '20200126120000'"#)
+    .unwrap();
+  conn
+    .batch_execute(r#"UPDATE "character" SET "name" = 'Edited' WHERE "id" = 1"#)
+    .unwrap();
+  let edited = common::dump(&conn);
+
+  match snapshot::restore_snapshot(&conn, "old") {
+    Err(SnapshotError::SchemaMismatch {
+      snapshot, database, ..
+    }) => {
+      assert_eq!(snapshot, "20200126120000");
+      assert_eq!(database, created.schema_version);
+    }
+    other => panic!("expected a schema mismatch, got {:?}", other),
+  }
+  assert_eq!(common::dump(&conn), edited);
+}
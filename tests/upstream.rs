@@ -0,0 +1,135 @@
+//! `ApiSource` against a local server shaped like rickandmortyapi.com,
+//! serving the characters in two pages.
+
+use rick_morty_back::db::{DataSource, DbError};
+use rick_morty_back::graphql::{character_model::Character, episode_model::CharacterEpisode};
+use rick_morty_back::loader::LoadReport;
+use rick_morty_back::upstream::ApiSource;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+fn character(id: i32, origin_url: &str, episodes: &[i32], base_url: &str) -> Value {
+  json!({
+    "id": id,
+    "name": format!("Character {}", id),
+    "status": "Alive",
+    "species": "Human",
+    "type": "",
+    "gender": "Male",
+    "origin": { "name": "Somewhere", "url": origin_url },
+    "location": { "name": "Earth", "url": format!("{}/location/20", base_url) },
+    "episode": episodes
+      .iter()
+      .map(|episode| format!("{}/episode/{}", base_url, episode))
+      .collect::<Vec<_>>(),
+  })
+}
+
+/// The body served for the request line `GET <path> HTTP/1.1`.
+fn page(path: &str, base_url: &str) -> Value {
+  match path {
+    "/character" => json!({
+      "info": { "count": 3, "pages": 2, "next": format!("{}/character?page=2", base_url) },
+      "results": [
+        character(1, &format!("{}/location/1", base_url), &[1, 2], base_url),
+        character(2, "", &[2], base_url),
+      ],
+    }),
+    "/character?page=2" => json!({
+      "info": { "count": 3, "pages": 2, "next": null },
+      "results": [character(3, &format!("{}/location/3", base_url), &[3], base_url)],
+    }),
+    _ => json!({ "info": { "next": null }, "results": { "error": "not a list" } }),
+  }
+}
+
+fn serve(mut stream: TcpStream, base_url: &str) {
+  let mut reader = BufReader::new(stream.try_clone().unwrap());
+  let mut request_line = String::new();
+  reader.read_line(&mut request_line).unwrap();
+  let mut header = String::new();
+  while reader.read_line(&mut header).unwrap() > 2 {
+    header.clear();
+  }
+  let path = request_line.split(' ').nth(1).unwrap_or_default();
+  let body = page(path, base_url).to_string();
+  write!(
+    stream,
+    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    body.len(),
+    body
+  )
+  .unwrap();
+}
+
+/// Starts the server on a free port and returns its base URL.
+fn start_server() -> String {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let base_url = format!("http://{}", listener.local_addr().unwrap());
+  let served_url = base_url.clone();
+  thread::spawn(move || {
+    for stream in listener.incoming() {
+      serve(stream.unwrap(), &served_url);
+    }
+  });
+  base_url
+}
+
+#[test]
+fn follows_next_through_every_page() {
+  let mut source = ApiSource::new(&start_server());
+  let mut report = LoadReport::default();
+  let mut characters: Vec<Character> = vec![];
+  let inserted = source
+    .characters(&mut report, &mut |rows| {
+      let count = rows.len();
+      characters.extend(rows);
+      Ok(count)
+    })
+    .unwrap();
+  assert!(report.errors.is_empty());
+  assert_eq!(inserted, 3);
+  let ids: Vec<i32> = characters.iter().map(|row| row.id).collect();
+  assert_eq!(ids, vec![1, 2, 3]);
+  assert_eq!(characters[0].origin_id, Some(1));
+  assert_eq!(characters[0].location_id, Some(20));
+  // Upstream gives unknown locations an empty URL.
+  assert_eq!(characters[1].origin_id, None);
+}
+
+#[test]
+fn pairs_characters_with_their_episodes() {
+  let mut source = ApiSource::new(&start_server());
+  let mut report = LoadReport::default();
+  source
+    .characters(&mut report, &mut |rows| Ok(rows.len()))
+    .unwrap();
+  let mut pairs: Vec<CharacterEpisode> = vec![];
+  source
+    .character_episodes(&mut report, &mut |rows| {
+      let count = rows.len();
+      pairs.extend(rows);
+      Ok(count)
+    })
+    .unwrap();
+  let pairs: Vec<(i32, i32)> = pairs
+    .iter()
+    .map(|pair| (pair.character_id, pair.episode_id))
+    .collect();
+  assert_eq!(pairs, vec![(1, 1), (1, 2), (2, 2), (3, 3)]);
+}
+
+#[test]
+fn fails_on_results_that_are_not_an_array() {
+  let mut source = ApiSource::new(&start_server());
+  let mut report = LoadReport::default();
+  match source.locations(&mut report, &mut |rows| Ok(rows.len())) {
+    Err(DbError::Source { name, message }) => {
+      assert!(name.ends_with("/location"), "{}", name);
+      assert_eq!(message, "Expected a page with a results array");
+    }
+    other => panic!("expected a source error, got {:?}", other),
+  }
+}